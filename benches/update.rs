@@ -0,0 +1,40 @@
+//! Times a single simulation step, i.e. one `update_particles` call, for growing amounts of
+//! particles spread over the three kinds of the default parameters.
+//!
+//! Every force evaluation visits all pairs, so this is the baseline any spatial acceleration has
+//! to beat. Such variants get their own function in the `update_particles` group, next to
+//! `naive`, so `cargo bench` reports them side by side.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use atomata::parameters::Parameters;
+use atomata::Simulation;
+
+/// Total amounts of particles, rounded down to a multiple of the kinds
+const PARTICLE_COUNTS: [usize; 3] = [100, 500, 1000];
+
+fn simulation(particle_count: usize) -> Simulation {
+    let base = Parameters::default();
+    let kinds = base.particle_parameters.len();
+    Simulation::new(Parameters {
+        amount: particle_count / kinds,
+        seed: Some(42),
+        ..base
+    })
+}
+
+fn update_particles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_particles");
+    for particle_count in PARTICLE_COUNTS {
+        let mut simulation = simulation(particle_count);
+        group.bench_with_input(
+            BenchmarkId::new("naive", particle_count),
+            &particle_count,
+            |b, _| b.iter(|| simulation.step().unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, update_particles);
+criterion_main!(benches);
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use three_d::{vec3, InnerSpace, Vector3};
+
+use crate::parameters::{ForceLaw, InteractionType, Parameters};
+use crate::particle::{nearest_image, pair_force, Particle, StateVector, LENNARD_JONES_CUTOFF};
+
+/// Returns the Shannon entropy of the occupied state vector buckets given their visit counts,
+/// normalized by the log of the number of occupied buckets.
+///
+/// Values near zero mean the system collapsed or froze into a few states, values near one mean
+/// the states are visited uniformly, i.e. noise. Structured emergence is expected in between.
+pub fn normalized_entropy(state_counts: &HashMap<StateVector, usize>) -> f64 {
+    let occupied = state_counts.values().filter(|&&count| count > 0).count();
+    if occupied < 2 {
+        return 0.0;
+    }
+
+    let total = state_counts.values().sum::<usize>() as f64;
+    let entropy: f64 = state_counts
+        .values()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.ln()
+        })
+        .sum();
+
+    entropy / (occupied as f64).ln()
+}
+
+/// Returns the total variation distance between the state vector distributions of two windows
+/// of a run, from zero for identical distributions to one for disjoint ones.
+pub fn state_distribution_change(previous: &[StateVector], current: &[StateVector]) -> f64 {
+    if previous.is_empty() || current.is_empty() {
+        return if previous.len() == current.len() {
+            0.0
+        } else {
+            1.0
+        };
+    }
+
+    let mut probabilities: HashMap<&StateVector, (f64, f64)> = HashMap::new();
+    for state_vector in previous {
+        probabilities.entry(state_vector).or_default().0 += 1.0 / previous.len() as f64;
+    }
+    for state_vector in current {
+        probabilities.entry(state_vector).or_default().1 += 1.0 / current.len() as f64;
+    }
+
+    probabilities
+        .values()
+        .map(|(p, q)| (p - q).abs())
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Returns the sum of the kinetic energies of all particles.
+pub fn total_kinetic_energy(particles: &[Particle]) -> f32 {
+    particles.iter().map(Particle::kinetic_energy).sum()
+}
+
+/// Returns the potential energy summed over all pairs of particles. For the inverse power law
+/// that is `-G * m_i * m_j / ((p - 1) * distance^(p - 1))` for attracting pairs and its negation
+/// for repelling ones, where `p` is the force exponent, scaled by the attraction or repulsion
+/// constant. The logarithmic potential of `p = 1` is used for that exponent. For the
+/// Lennard-Jones law it's `4ε((σ/r)¹² - (σ/r)⁶)`, of which repelling pairs only get the first
+/// term. Neutral pairs, pairs closer than the force cutoff and pairs beyond the Lennard-Jones
+/// cutoff don't contribute.
+pub fn potential_energy(particles: &[Particle], parameters: &Parameters) -> Result<f32, String> {
+    let mut energy = 0.0;
+    for (i, particle) in particles.iter().enumerate() {
+        for other in &particles[i + 1..] {
+            let interaction_type =
+                parameters.interaction_by_indices(particle.index, other.index)?;
+            if interaction_type == InteractionType::Neutral {
+                continue;
+            }
+            let other_position = nearest_image(particle.position, other.position, parameters);
+            let distance = (other_position - particle.position).magnitude();
+            if distance <= 0.0001 {
+                continue;
+            }
+            energy += match parameters.force_law {
+                ForceLaw::InversePower => {
+                    let scale = if interaction_type == InteractionType::Attraction {
+                        -parameters.attraction_constant
+                    } else {
+                        parameters.repulsion_constant
+                    };
+                    scale
+                        * parameters.gravity_constant
+                        * particle.mass
+                        * other.mass
+                        * inverse_potential(distance, parameters.force_exponent)
+                }
+                ForceLaw::LennardJones { epsilon, sigma } => {
+                    lennard_jones_potential(distance, epsilon, sigma, interaction_type)
+                }
+            };
+        }
+    }
+    Ok(energy)
+}
+
+/// Returns the magnitude of the potential of the force law `1 / distance^exponent` at the given
+/// distance, so that the potential of an attracting pair is its negation.
+fn inverse_potential(distance: f32, exponent: f32) -> f32 {
+    if (exponent - 1.0).abs() < f32::EPSILON {
+        -distance.ln()
+    } else {
+        1.0 / ((exponent - 1.0) * distance.powf(exponent - 1.0))
+    }
+}
+
+fn lennard_jones_potential(
+    distance: f32,
+    epsilon: f32,
+    sigma: f32,
+    interaction_type: InteractionType,
+) -> f32 {
+    if distance > LENNARD_JONES_CUTOFF * sigma {
+        return 0.0;
+    }
+    let sigma_6 = (sigma / distance).powi(6);
+    if interaction_type == InteractionType::Attraction {
+        4.0 * epsilon * (sigma_6 * sigma_6 - sigma_6)
+    } else {
+        4.0 * epsilon * sigma_6 * sigma_6
+    }
+}
+
+/// Returns the sum of the momenta of all particles.
+pub fn total_momentum(particles: &[Particle]) -> Vector3<f32> {
+    particles
+        .iter()
+        .fold(vec3(0.0, 0.0, 0.0), |sum, p| sum + p.velocity() * p.mass)
+}
+
+/// Returns the mass weighted mean velocity of the given particles.
+pub fn center_of_mass_velocity(particles: &[Particle]) -> Vector3<f32> {
+    let total_mass: f32 = particles.iter().map(|p| p.mass).sum();
+    if total_mass <= 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+
+    total_momentum(particles) / total_mass
+}
+
+/// Returns `resolution³` points on a cubic lattice spanning the cube of edge length `border`
+/// around the center, at the centers of its cells like the nodes of `Placement::Grid`.
+pub fn field_grid(resolution: usize, border: f32) -> Vec<Vector3<f32>> {
+    let spacing = border / resolution as f32;
+    let coordinate = |node: usize| (node as f32 + 0.5) * spacing - border / 2.0;
+    (0..resolution * resolution * resolution)
+        .map(|i| {
+            vec3(
+                coordinate(i % resolution),
+                coordinate(i / resolution % resolution),
+                coordinate(i / (resolution * resolution)),
+            )
+        })
+        .collect()
+}
+
+/// Strength of the force field at a point, see `field_strength`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldSample {
+    pub position: Vector3<f32>,
+    pub field: Vector3<f32>,
+}
+
+/// Returns the force per unit mass a probe particle of the kind `probe_index` would feel at the
+/// given point, i.e. the acceleration `update_velocity` would sum up for it over all particles.
+pub fn field_strength(
+    point: Vector3<f32>,
+    probe_index: usize,
+    particles: &[Particle],
+    parameters: &Parameters,
+) -> Result<Vector3<f32>, String> {
+    let mut field = vec3(0.0, 0.0, 0.0);
+    for particle in particles {
+        let interaction_type = parameters.interaction_by_indices(probe_index, particle.index)?;
+        let direction = nearest_image(point, particle.position, parameters) - point;
+        let distance = direction.magnitude();
+        if interaction_type != InteractionType::Neutral && distance > 0.0001 {
+            field += direction.normalize()
+                * pair_force(distance, 1.0, particle.mass, interaction_type, parameters);
+        }
+    }
+    Ok(field)
+}
+
+/// Disjoint sets over the indices `0..len` with path compression and union by size.
+struct UnionFind {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+            sizes: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        let mut i = i;
+        while self.parents[i] != root {
+            let next = self.parents[i];
+            self.parents[i] = root;
+            i = next;
+        }
+
+        root
+    }
+
+    fn union(&mut self, i: usize, j: usize) {
+        let (i, j) = (self.find(i), self.find(j));
+        if i == j {
+            return;
+        }
+
+        let (larger, smaller) = if self.sizes[i] >= self.sizes[j] {
+            (i, j)
+        } else {
+            (j, i)
+        };
+        self.parents[smaller] = larger;
+        self.sizes[larger] += self.sizes[smaller];
+    }
+}
+
+/// Groups particles into clusters, where two particles belong to the same cluster if they are
+/// connected by a chain of particles closer than `distance_threshold` to each other. Returns the
+/// size of every cluster.
+pub fn cluster_sizes(particles: &[Particle], distance_threshold: f32) -> Vec<usize> {
+    let mut clusters = UnionFind::new(particles.len());
+    for i in 0..particles.len() {
+        for j in (i + 1)..particles.len() {
+            if (particles[i].position - particles[j].position).magnitude() < distance_threshold {
+                clusters.union(i, j);
+            }
+        }
+    }
+
+    (0..particles.len())
+        .filter(|&i| clusters.parents[i] == i)
+        .map(|i| clusters.sizes[i])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    fn state_vector(x: f32) -> StateVector {
+        StateVector::new((x, 0.0, 0.0), (0.0, 0.0, 0.0), 1.0, 10.0, 10.0, 1)
+    }
+
+    fn state_counts(state_vectors: &[StateVector]) -> HashMap<StateVector, usize> {
+        let mut counts = HashMap::new();
+        for state_vector in state_vectors {
+            *counts.entry(state_vector.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_normalized_entropy() {
+        let state_vectors = [0.0, 0.0, 10.0, 20.0].map(state_vector);
+
+        // p = (1/2, 1/4, 1/4) => H = 1.5 ln(2), normalized by ln(3)
+        let entropy = normalized_entropy(&state_counts(&state_vectors));
+
+        assert!((entropy - 0.946395).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_normalized_entropy_single_state() {
+        let state_vectors = [0.0, 0.0, 0.0].map(state_vector);
+
+        assert_eq!(normalized_entropy(&state_counts(&state_vectors)), 0.0);
+    }
+
+    #[test]
+    fn test_state_distribution_change() {
+        let previous = [0.0, 0.0, 10.0, 20.0].map(state_vector);
+        let current = [0.0, 10.0, 10.0, 20.0].map(state_vector);
+        let disjoint = [30.0].map(state_vector);
+
+        assert_eq!(state_distribution_change(&previous, &previous), 0.0);
+        // p = (1/2, 1/4, 1/4), q = (1/4, 1/2, 1/4)
+        assert!((state_distribution_change(&previous, &current) - 0.25).abs() < 1e-9);
+        assert!((state_distribution_change(&previous, &disjoint) - 1.0).abs() < 1e-9);
+    }
+
+    fn particles() -> Vec<Particle> {
+        vec![
+            Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 1.0, 100.0),
+            Particle::from_state(1, vec3(5.0, 0.0, 0.0), vec3(0.0, 2.0, 0.0), 3.0, 100.0),
+        ]
+    }
+
+    #[test]
+    fn test_total_kinetic_energy() {
+        // 0.5 * 1 * 2^2 + 0.5 * 3 * 2^2
+        assert_eq!(total_kinetic_energy(&particles()), 8.0);
+    }
+
+    #[test]
+    fn test_potential_energy() {
+        let parameters = Parameters {
+            gravity_constant: 2.0,
+            ..Parameters::default()
+        };
+        // Kinds 0 and 1 attract each other, kind 0 repels itself and kind 2 is neutral to itself
+        let particle = |index, x, mass| {
+            Particle::from_state(index, vec3(x, 0.0, 0.0), vec3(0.0, 0.0, 0.0), mass, 100.0)
+        };
+        let attracting = [particle(0, 0.0, 3.0), particle(1, 10.0, 250.0)];
+        let repelling = [particle(0, 0.0, 3.0), particle(0, 4.0, 5.0)];
+        let neutral = [particle(2, 0.0, 3.0), particle(2, 4.0, 5.0)];
+
+        // -2 * 3 * 250 / 10
+        assert_eq!(potential_energy(&attracting, &parameters), Ok(-150.0));
+        // 2 * 3 * 5 / 4
+        assert_eq!(potential_energy(&repelling, &parameters), Ok(7.5));
+        assert_eq!(potential_energy(&neutral, &parameters), Ok(0.0));
+    }
+
+    #[test]
+    fn test_total_momentum() {
+        assert_eq!(total_momentum(&particles()), vec3(2.0, 6.0, 0.0));
+    }
+
+    #[test]
+    fn test_center_of_mass_velocity() {
+        assert_eq!(center_of_mass_velocity(&particles()), vec3(0.5, 1.5, 0.0));
+    }
+
+    #[test]
+    fn test_field_grid() {
+        let grid = field_grid(2, 10.0);
+
+        assert_eq!(grid.len(), 8);
+        assert_eq!(grid[0], vec3(-2.5, -2.5, -2.5));
+        assert_eq!(grid[7], vec3(2.5, 2.5, 2.5));
+    }
+
+    #[test]
+    fn test_field_strength() {
+        let parameters = Parameters {
+            gravity_constant: 2.0,
+            ..Parameters::default()
+        };
+        // Kind 1 attracts kind 0, which repels itself
+        let particles = [
+            Particle::from_state(1, vec3(4.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 8.0, 100.0),
+            Particle::from_state(0, vec3(0.0, -2.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0, 100.0),
+        ];
+
+        // 2 * 8 / 4^2 towards the first particle and 2 * 1 / 2^2 away from the second one
+        assert_eq!(
+            field_strength(vec3(0.0, 0.0, 0.0), 0, &particles, &parameters),
+            Ok(vec3(1.0, 0.5, 0.0))
+        );
+    }
+
+    #[test]
+    fn test_cluster_sizes() {
+        let blob = |center: Vector3<f32>, offsets: &[f32]| {
+            offsets
+                .iter()
+                .map(|&offset| {
+                    Particle::from_state(
+                        0,
+                        center + vec3(offset, offset, 0.0),
+                        vec3(0.0, 0.0, 0.0),
+                        1.0,
+                        100.0,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut particles = blob(vec3(-100.0, 0.0, 0.0), &[0.0, 1.0, 2.0, 3.0]);
+        particles.append(&mut blob(vec3(100.0, 0.0, 0.0), &[0.0, 1.0, 2.0]));
+
+        let mut sizes = cluster_sizes(&particles, 5.0);
+        sizes.sort();
+
+        assert_eq!(sizes, vec![3, 4]);
+    }
+}
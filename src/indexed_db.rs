@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::rc::Rc;
+
+use js_sys::Promise;
+use rexie::{ObjectStore, Rexie, TransactionMode};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+
+use crate::{parameters::Parameters, particle::StateVector, store::ResultStore};
+
+const RUNS: &str = "runs";
+const PARTICLE_PARAMETERS: &str = "particle_parameters";
+const STATE_COUNTS: &str = "state_counts";
+const RUN_METRICS: &str = "run_metrics";
+const ELAPSED_TIMES: &str = "elapsed_times";
+const STORES: [&str; 5] = [
+    RUNS,
+    PARTICLE_PARAMETERS,
+    STATE_COUNTS,
+    RUN_METRICS,
+    ELAPSED_TIMES,
+];
+
+/// Results persisted since the last flush, waiting to be written to IndexedDB.
+#[derive(Debug, Default)]
+struct PendingResults {
+    /// Id and configuration of every new run
+    runs: Vec<(usize, String)>,
+    /// Id of every new particle kind together with the id of its run
+    particle_parameters: Vec<(usize, usize)>,
+    /// Increments of the counts of state vectors by their key
+    state_counts: HashMap<String, u64>,
+    metrics: Vec<(String, f64)>,
+    elapsed_times: Vec<(usize, f64)>,
+}
+
+/// Store persisting the results of a search to the IndexedDB of the browser, where SQLite isn't
+/// available. IndexedDB is asynchronous, so results are buffered and `commit` only starts
+/// writing them in the background, while `flush` waits until they are written. Ids are assigned
+/// on the page, which assumes no other page writes to the same database at the same time.
+pub struct IndexedDbResultStore {
+    database: Rc<Rexie>,
+    pending: PendingResults,
+    /// Settles once everything committed so far is written, rejected if a write failed
+    writes: Promise,
+    next_run_id: usize,
+    next_particle_parameters_id: usize,
+}
+
+impl IndexedDbResultStore {
+    /// Opens the database with the given name, creating it on first use.
+    pub async fn open(name: &str) -> Result<Self, Box<dyn Error>> {
+        let database = Rexie::builder(name)
+            .version(1)
+            .add_object_store(ObjectStore::new(RUNS))
+            .add_object_store(ObjectStore::new(PARTICLE_PARAMETERS))
+            .add_object_store(ObjectStore::new(STATE_COUNTS))
+            .add_object_store(ObjectStore::new(RUN_METRICS))
+            .add_object_store(ObjectStore::new(ELAPSED_TIMES))
+            .build()
+            .await?;
+
+        // Ids start at 1 like SQLite's row ids and continue after the persisted ones
+        let transaction =
+            database.transaction(&[RUNS, PARTICLE_PARAMETERS], TransactionMode::ReadOnly)?;
+        let runs = transaction.store(RUNS)?.count(None).await? as usize;
+        let particle_parameters =
+            transaction.store(PARTICLE_PARAMETERS)?.count(None).await? as usize;
+        transaction.done().await?;
+
+        Ok(Self {
+            database: Rc::new(database),
+            pending: PendingResults::default(),
+            writes: Promise::resolve(&JsValue::UNDEFINED),
+            next_run_id: runs + 1,
+            next_particle_parameters_id: particle_parameters + 1,
+        })
+    }
+
+    /// Commits everything persisted since the last commit and waits until all commits are
+    /// durable.
+    pub async fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.commit()?;
+        JsFuture::from(self.writes.clone())
+            .await
+            .map_err(|e| e.as_string().unwrap_or_else(|| format!("{:?}", e)))?;
+        Ok(())
+    }
+
+    /// Returns how often the state vector was counted over all flushed runs.
+    pub async fn state_count(&self, state_vector: &StateVector) -> Result<u64, Box<dyn Error>> {
+        let transaction = self
+            .database
+            .transaction(&[STATE_COUNTS], TransactionMode::ReadOnly)?;
+        let count = transaction
+            .store(STATE_COUNTS)?
+            .get(&JsValue::from_str(&state_vector_key(state_vector)))
+            .await?;
+        transaction.done().await?;
+        Ok(count.as_f64().unwrap_or(0.0) as u64)
+    }
+}
+
+impl ResultStore for IndexedDbResultStore {
+    fn persist_parameters(&mut self, parameters: &mut Parameters) -> Result<(), Box<dyn Error>> {
+        let run_id = self.next_run_id;
+        self.next_run_id += 1;
+        parameters.run_id = Some(run_id);
+        for particle in parameters.particle_parameters.iter_mut() {
+            let id = self.next_particle_parameters_id;
+            self.next_particle_parameters_id += 1;
+            particle.id = Some(id);
+            self.pending.particle_parameters.push((id, run_id));
+        }
+        self.pending.runs.push((run_id, parameters.to_toml()));
+        Ok(())
+    }
+
+    fn increment_state_counts(
+        &mut self,
+        state_vectors: &[StateVector],
+    ) -> Result<(), Box<dyn Error>> {
+        for state_vector in state_vectors {
+            *self
+                .pending
+                .state_counts
+                .entry(state_vector_key(state_vector))
+                .or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    fn persist_run_metric(
+        &mut self,
+        run_id: usize,
+        name: &str,
+        value: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.pending
+            .metrics
+            .push((format!("{}/{}", run_id, name), value));
+        Ok(())
+    }
+
+    fn persist_elapsed_time(&mut self, run_id: usize, seconds: f64) -> Result<(), Box<dyn Error>> {
+        self.pending.elapsed_times.push((run_id, seconds));
+        Ok(())
+    }
+
+    /// Starts writing everything persisted since the last commit without waiting for it. Each
+    /// write starts once the previous one is done, call `flush` to wait for them.
+    fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        let database = Rc::clone(&self.database);
+        let pending = std::mem::take(&mut self.pending);
+        let previous = self.writes.clone();
+        self.writes = future_to_promise(async move {
+            JsFuture::from(previous).await?;
+            write_pending(&database, pending)
+                .await
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            Ok(JsValue::UNDEFINED)
+        });
+        Ok(())
+    }
+}
+
+/// Writes the pending results in a single transaction, adding the state count increments to the
+/// persisted counts.
+async fn write_pending(database: &Rexie, pending: PendingResults) -> Result<(), Box<dyn Error>> {
+    let transaction = database.transaction(&STORES, TransactionMode::ReadWrite)?;
+
+    let runs = transaction.store(RUNS)?;
+    for (run_id, config) in &pending.runs {
+        runs.put(
+            &JsValue::from_str(config),
+            Some(&JsValue::from(*run_id as f64)),
+        )
+        .await?;
+    }
+    let particle_parameters = transaction.store(PARTICLE_PARAMETERS)?;
+    for (id, run_id) in &pending.particle_parameters {
+        particle_parameters
+            .put(
+                &JsValue::from(*run_id as f64),
+                Some(&JsValue::from(*id as f64)),
+            )
+            .await?;
+    }
+    let state_counts = transaction.store(STATE_COUNTS)?;
+    for (key, increment) in &pending.state_counts {
+        let key = JsValue::from_str(key);
+        let count = state_counts.get(&key).await?.as_f64().unwrap_or(0.0);
+        state_counts
+            .put(&JsValue::from(count + *increment as f64), Some(&key))
+            .await?;
+    }
+    let metrics = transaction.store(RUN_METRICS)?;
+    for (key, value) in &pending.metrics {
+        metrics
+            .put(&JsValue::from(*value), Some(&JsValue::from_str(key)))
+            .await?;
+    }
+    let elapsed_times = transaction.store(ELAPSED_TIMES)?;
+    for (run_id, seconds) in &pending.elapsed_times {
+        elapsed_times
+            .put(
+                &JsValue::from(*seconds),
+                Some(&JsValue::from(*run_id as f64)),
+            )
+            .await?;
+    }
+
+    transaction.done().await?;
+    Ok(())
+}
+
+/// Identifies a state vector in the state counts, like the columns of the SQLite table.
+fn state_vector_key(state_vector: &StateVector) -> String {
+    let (px, py, pz) = state_vector.position_bucket;
+    let (vx, vy, vz) = state_vector.velocity_bucket;
+    format!(
+        "{}/{}/{}/{}/{}/{}/{}/{}",
+        state_vector.particle_parameters_id, state_vector.mass, px, py, pz, vx, vy, vz
+    )
+}
@@ -1,34 +1,76 @@
-mod parameters;
-mod particle;
+mod analysis;
+#[cfg(target_arch = "wasm32")]
+pub mod indexed_db;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod logging;
+pub mod parameters;
+pub mod particle;
 #[cfg(not(target_arch = "wasm32"))]
-mod persistence;
+pub mod persistence;
+#[cfg(all(feature = "serve", not(target_arch = "wasm32")))]
+pub mod serve;
+pub mod snapshot;
 mod sphere;
+pub mod store;
+pub mod wasm;
 
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
 
+use analysis::{
+    center_of_mass_velocity, cluster_sizes, normalized_entropy, state_distribution_change,
+    total_kinetic_energy,
+};
+use analysis::{field_grid, field_strength, FieldSample};
 #[cfg(not(target_arch = "wasm32"))]
 use argh::FromArgs;
+#[cfg(not(target_arch = "wasm32"))]
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::info;
-use parameters::{Mode, Parameters};
-use particle::{Particle, StateVector};
+#[cfg(not(target_arch = "wasm32"))]
+use logging::{JsonLogger, LogFormat, RunRecord};
+#[cfg(not(target_arch = "wasm32"))]
+use parameters::SweepSpec;
+use parameters::{BoundaryShape, ForceLaw, Mode, Parameters, ParticleParameters, VelocityClamp};
+use particle::{
+    nearest_image, CartesianMapper, FrameContext, MaxVelocityTracker, Particle, StateMapper,
+    StateVector,
+};
 #[cfg(not(target_arch = "wasm32"))]
 use persistence::{
-    commit_transaction, create_transaction_provider, increment_state_count, migrate_to_latest,
-    open_database, persist_parameters, TransactionProvider,
+    commit_transaction, create_transaction_provider, delete_run, export_metrics_json,
+    export_run_csv, list_runs, load_parameters, open_and_migrate, parameters_already_run,
+    rank_runs_by_metric, retain_new_parameters, PersistenceOptions, RunSummary, SqliteResultStore,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_distr::StandardNormal;
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
-use sphere::{PositionableRender, Sphere};
+use snapshot::Snapshot;
+use sphere::{FieldRenderer, ParticleRenderer, SphereMaterial};
+#[cfg(not(target_arch = "wasm32"))]
+use store::{MemoryResultStore, ResultStore};
 use three_d::{
     degrees,
-    egui::{SidePanel, Slider},
-    vec3, Camera, ClearState, Context, DirectionalLight, FrameOutput, OrbitControl, Srgba, Window,
-    WindowSettings,
+    egui::{Button, ComboBox, SidePanel, Slider},
+    vec3, AmbientLight, Camera, ClearState, DirectionalLight, FrameOutput, InnerSpace, Light,
+    OrbitControl, Srgba, Vector3, Viewport, Window, WindowSettings,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
 const LOG_FILE_NAME: &str = "atomata.log";
 
+/// Set by ctrl-c to stop the search from starting further runs
+#[cfg(not(target_arch = "wasm32"))]
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Command line interface arguments. Parameter values given on the command line take precedence
+/// over the `--config` file, which in turn takes precedence over `Parameters::default()`.
 #[cfg(not(target_arch = "wasm32"))]
 #[derive(Debug, FromArgs)]
 #[argh(description = "command line interface arguments")]
@@ -39,15 +81,617 @@ struct Cli {
         description = "wheter to run experiements over parameter space in headless mode"
     )]
     search: bool,
+    #[argh(
+        switch,
+        description = "whether to simulate only the given parameters for --iterations without a window and print the metrics of the run"
+    )]
+    run_once: bool,
+    #[argh(
+        switch,
+        description = "whether --run-once persists its run to the database like a search run"
+    )]
+    persist: bool,
+    #[argh(
+        option,
+        description = "path to a sweep spec (TOML) whose new parameter points are simulated and appended to the existing results"
+    )]
+    append_sweep: Option<String>,
+    #[argh(
+        option,
+        description = "path to a sweep spec (TOML or JSON) defining the parameter space of the search"
+    )]
+    search_spec: Option<String>,
+    #[argh(
+        option,
+        description = "number of random interaction matrices to search instead of the parameter sweep, reproducible with --seed"
+    )]
+    random_interactions: Option<usize>,
+    #[argh(
+        option,
+        description = "number of parameter points to sample from the ranges of the sweep instead of simulating its full grid, reproducible with --seed"
+    )]
+    sample: Option<usize>,
+    #[argh(
+        option,
+        description = "path to a TOML file with the parameters to simulate, defaults are used otherwise"
+    )]
+    config: Option<String>,
+    #[argh(option, description = "amount of particles per kind")]
+    amount: Option<usize>,
+    #[argh(option, description = "radius of the boundary")]
+    border: Option<f32>,
+    #[argh(option, description = "integration timestep")]
+    timestep: Option<f32>,
+    #[argh(option, description = "gravity constant")]
+    gravity: Option<f32>,
+    #[argh(option, description = "exponent p of the 1/distance^p force law")]
+    force_exponent: Option<f32>,
+    #[argh(option, description = "factor of the force between attracting kinds")]
+    attraction_constant: Option<f32>,
+    #[argh(option, description = "factor of the force between repelling kinds")]
+    repulsion_constant: Option<f32>,
+    #[argh(option, description = "friction applied to the velocity each step")]
+    friction: Option<f32>,
+    #[argh(
+        switch,
+        description = "whether to remove the center of mass velocity after every step"
+    )]
+    recenter: bool,
+    #[argh(option, description = "velocity cap")]
+    max_velocity: Option<f32>,
+    #[argh(option, description = "size of the state vector position buckets")]
+    position_bucket_size: Option<f32>,
+    #[argh(option, description = "size of the state vector velocity buckets")]
+    velocity_bucket_size: Option<f32>,
+    #[argh(
+        option,
+        description = "record state vectors only every nth iteration in search mode"
+    )]
+    sample_every: Option<usize>,
+    #[argh(
+        option,
+        description = "length of the iteration windows compared to stop converged search runs early, zero disables early stopping"
+    )]
+    convergence_interval: Option<usize>,
+    #[argh(
+        option,
+        description = "change of the state distribution between two windows below which a search run counts as converged"
+    )]
+    convergence_threshold: Option<f32>,
+    #[argh(
+        option,
+        description = "seed of the initial particle layout, random if omitted"
+    )]
+    seed: Option<u64>,
+    #[argh(
+        option,
+        default = "10000",
+        description = "number of iterations simulated per parameter point in search mode, or frames rendered with --render-frames"
+    )]
+    iterations: usize,
+    #[argh(
+        option,
+        description = "directory to render the simulation into as numbered PNG files without opening a window, needs an OpenGL driver usable without a display"
+    )]
+    render_frames: Option<String>,
+    #[argh(
+        option,
+        description = "port on localhost on which a headless simulation serves its frames as JSON, requires the serve feature"
+    )]
+    serve: Option<u16>,
+    #[argh(
+        option,
+        description = "path to a snapshot to continue instead of starting from the parameters"
+    )]
+    resume_snapshot: Option<String>,
+    #[argh(
+        option,
+        description = "id of a run in the database whose parameters are opened in the viewer instead of the given ones"
+    )]
+    replay: Option<usize>,
+    #[argh(
+        option,
+        description = "write a snapshot of the simulation to --snapshot-path every nth iteration in default mode"
+    )]
+    snapshot_every: Option<usize>,
+    #[argh(
+        option,
+        default = "String::from(\"./snapshot.json\")",
+        description = "path of the snapshot written by --snapshot-every"
+    )]
+    snapshot_path: String,
+    #[argh(
+        option,
+        default = "DEFAULT_FIELD_OF_VIEW",
+        description = "vertical field of view of the camera in degrees"
+    )]
+    fov: f32,
+    #[argh(
+        option,
+        description = "initial distance of the camera from the center, fits the border into view if omitted"
+    )]
+    camera_distance: Option<f32>,
+    #[argh(
+        switch,
+        description = "whether to start with an orthographic instead of a perspective camera"
+    )]
+    orthographic: bool,
+    #[argh(
+        switch,
+        description = "whether to scale the timestep by the frame time in default mode for playback independent of the frame rate"
+    )]
+    real_time: bool,
+    #[argh(
+        option,
+        default = "String::from(\"./results.db3\")",
+        description = "path to the database the search results are persisted to"
+    )]
+    db: String,
+    #[argh(
+        switch,
+        description = "whether to skip parameter points of the search that were already simulated"
+    )]
+    resume: bool,
+    #[argh(
+        switch,
+        description = "print the size, axes and first and last point of the search's parameter space, or the parameters the viewer would open, without simulating or writing to the database"
+    )]
+    dry_run: bool,
+    #[argh(
+        option,
+        default = "LogFormat::Text",
+        description = "format of the search mode's log file, text or json with one object per line and run"
+    )]
+    log_format: LogFormat,
+    #[argh(switch, description = "print a table of the runs in the database")]
+    list_runs: bool,
+    #[argh(
+        option,
+        description = "name of a metric, e.g. entropy, to print the runs with its highest values for"
+    )]
+    top: Option<String>,
+    #[argh(
+        option,
+        default = "10",
+        description = "number of runs printed by --top"
+    )]
+    top_count: usize,
+    #[argh(
+        option,
+        description = "id of a run to delete from the database together with all its results"
+    )]
+    delete_run: Option<usize>,
+    #[argh(
+        option,
+        description = "id of a run in the database whose state vectors are exported as CSV"
+    )]
+    export_csv: Option<usize>,
+    #[argh(
+        option,
+        description = "path of a JSON file to export the parameters and metrics of all runs in the database to"
+    )]
+    export_metrics_json: Option<String>,
+    #[argh(
+        option,
+        description = "path of the file written by --export-csv, defaults to run-<id>.csv"
+    )]
+    output: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Cli {
+    /// Returns the parameters from the config file or the defaults, overridden by the values
+    /// given on the command line.
+    fn parameters(&self) -> Result<Parameters, String> {
+        let mut parameters = match &self.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+                Parameters::from_toml(&content)?
+            }
+            None => Parameters::default(),
+        };
+
+        if let Some(amount) = self.amount {
+            parameters.amount = amount;
+        }
+        if let Some(border) = self.border {
+            parameters.border = border;
+        }
+        if let Some(timestep) = self.timestep {
+            parameters.timestep = timestep;
+        }
+        if let Some(gravity) = self.gravity {
+            parameters.gravity_constant = gravity;
+        }
+        if let Some(force_exponent) = self.force_exponent {
+            parameters.force_exponent = force_exponent;
+        }
+        if let Some(attraction_constant) = self.attraction_constant {
+            parameters.attraction_constant = attraction_constant;
+        }
+        if let Some(repulsion_constant) = self.repulsion_constant {
+            parameters.repulsion_constant = repulsion_constant;
+        }
+        if let Some(friction) = self.friction {
+            parameters.friction = friction;
+        }
+        if self.recenter {
+            parameters.recenter = true;
+        }
+        if let Some(max_velocity) = self.max_velocity {
+            parameters.max_velocity = max_velocity;
+        }
+        if let Some(position_bucket_size) = self.position_bucket_size {
+            parameters.position_bucket_size = position_bucket_size;
+        }
+        if let Some(velocity_bucket_size) = self.velocity_bucket_size {
+            parameters.velocity_bucket_size = velocity_bucket_size;
+        }
+        if let Some(sample_every) = self.sample_every {
+            parameters.sample_every = sample_every;
+        }
+        if let Some(seed) = self.seed {
+            parameters.seed = Some(seed);
+        }
+        if let Some(convergence_interval) = self.convergence_interval {
+            parameters.convergence_interval = convergence_interval;
+        }
+        if let Some(convergence_threshold) = self.convergence_threshold {
+            parameters.convergence_threshold = convergence_threshold;
+        }
+
+        Ok(parameters)
+    }
+
+    /// Returns the sweep spec given by `--search-spec` or `--append-sweep`, or the default one.
+    /// Files ending in `.json` are read as JSON, everything else as TOML.
+    fn sweep_spec(&self) -> Result<SweepSpec, String> {
+        let path = match (&self.search_spec, &self.append_sweep) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of --search-spec and --append-sweep can be given".to_string())
+            }
+            (Some(path), None) | (None, Some(path)) => path,
+            (None, None) => return Ok(SweepSpec::default()),
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if path.ends_with(".json") {
+            SweepSpec::from_json(&content)
+        } else {
+            SweepSpec::from_toml(&content)
+        }
+    }
+}
+
+/// Runs the one-off database command given on the command line, if any, and returns whether
+/// one was run.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_database_command(args: &Cli) -> Result<bool, Box<dyn std::error::Error>> {
+    if !args.list_runs
+        && args.top.is_none()
+        && args.export_csv.is_none()
+        && args.export_metrics_json.is_none()
+        && args.delete_run.is_none()
+    {
+        return Ok(false);
+    }
+
+    let mut connection_provider = open_and_migrate(&args.db, &PersistenceOptions::default())?;
+    let tx_provider = create_transaction_provider(&mut connection_provider)?;
+
+    if args.list_runs {
+        print!("{}", format_run_table(&list_runs(&tx_provider)?));
+    }
+    if let Some(metric_name) = &args.top {
+        let runs = rank_runs_by_metric(metric_name, args.top_count, &tx_provider)?;
+        print!("{}", format_ranked_run_table(metric_name, &runs));
+    }
+    if let Some(run_id) = args.export_csv {
+        let path = args
+            .output
+            .clone()
+            .unwrap_or_else(|| format!("run-{}.csv", run_id));
+        export_run_csv(run_id, std::path::Path::new(&path), &tx_provider)?;
+        println!("Exported run {} to {}", run_id, path);
+    }
+    if let Some(path) = &args.export_metrics_json {
+        export_metrics_json(std::path::Path::new(path), &tx_provider)?;
+        println!("Exported the metrics of all runs to {}", path);
+    }
+    if let Some(run_id) = args.delete_run {
+        delete_run(run_id, &tx_provider)?;
+        println!("Deleted run {}", run_id);
+    }
+    commit_transaction(tx_provider)?;
+
+    Ok(true)
+}
+
+/// Loads the parameters of a persisted run, e.g. one the search flagged as interesting, to
+/// inspect it in the viewer.
+#[cfg(not(target_arch = "wasm32"))]
+fn replay_parameters(db: &str, run_id: usize) -> Result<Parameters, Box<dyn std::error::Error>> {
+    let mut connection_provider = open_and_migrate(db, &PersistenceOptions::default())?;
+    let tx_provider = create_transaction_provider(&mut connection_provider)?;
+    load_parameters(run_id, &tx_provider)
+}
+
+/// Runs search mode, failing instead of panicking if the database can't be opened or migrated
+/// or the sweep spec can't be loaded.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_search<M: StateMapper + Sync>(
+    args: &Cli,
+    default_parameters: &Parameters,
+    state_mapper: &M,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Running search mode");
+    if !args.dry_run {
+        set_log_hook(LOG_FILE_NAME, args.log_format);
+    }
+
+    info!("Loading sweep spec...");
+    let spec = args.sweep_spec()?;
+
+    let mut parameter_space: Box<dyn ExactSizeIterator<Item = Parameters> + Send> =
+        if let Some(count) = args.random_interactions {
+            let seed = default_parameters.seed.unwrap_or_else(random_seed);
+            info!(
+                "Generating {} random interaction matrices with seed {}...",
+                count, seed
+            );
+            Box::new(
+                Parameters::random_parameter_space(count, seed, default_parameters).into_iter(),
+            )
+        } else if let Some(count) = args.sample {
+            let seed = default_parameters.seed.unwrap_or_else(random_seed);
+            info!("Sampling {} parameter points with seed {}...", count, seed);
+            Box::new(
+                Parameters::latin_hypercube_sample_from_spec(
+                    &spec,
+                    default_parameters,
+                    count,
+                    seed,
+                )
+                .into_iter(),
+            )
+        } else {
+            Box::new(Parameters::parameter_space_from_spec(
+                &spec,
+                default_parameters,
+            ))
+        };
+
+    if args.dry_run {
+        let swept_spec = args.random_interactions.is_none().then_some(&spec);
+        print!("{}", format_dry_run(swept_spec, parameter_space));
+        return Ok(());
+    }
+
+    info!("Initializing database...");
+    let mut connection_provider = open_and_migrate(&args.db, &PersistenceOptions::write_heavy())?;
+
+    let handler = ctrlc::set_handler(|| {
+        if SHUTDOWN_REQUESTED.swap(true, Ordering::Relaxed) {
+            // A second ctrl-c doesn't wait for the runs in flight
+            std::process::exit(130);
+        }
+        eprintln!("Finishing the runs in flight, press ctrl-c again to exit immediately");
+    });
+    // The handler of an earlier search in the same process is just as good
+    if let Err(e) = handler {
+        if !matches!(e, ctrlc::Error::MultipleHandlers) {
+            return Err(e.into());
+        }
+    }
+
+    // Filtering needs the database for every point, so the remaining points are collected to
+    // know their number up front
+    if args.append_sweep.is_some() {
+        info!("Skipping parameter points already present in the database...");
+        let tx_provider = create_transaction_provider(&mut connection_provider)?;
+        parameter_space =
+            Box::new(retain_new_parameters(parameter_space, &tx_provider)?.into_iter());
+    }
+
+    if args.resume {
+        info!("Skipping parameter points that were already simulated...");
+        let tx_provider = create_transaction_provider(&mut connection_provider)?;
+        let mut new_parameter_space = vec![];
+        for parameters in parameter_space {
+            if !parameters_already_run(&parameters, &tx_provider)? {
+                new_parameter_space.push(parameters);
+            }
+        }
+        parameter_space = Box::new(new_parameter_space.into_iter());
+    }
+
+    search(
+        parameter_space,
+        args.iterations,
+        state_mapper,
+        SqliteResultStore::new(connection_provider),
+        args.log_format,
+        &SHUTDOWN_REQUESTED,
+    )?;
+    Ok(())
+}
+
+/// Simulates the default parameters like a single point of the search and prints the metrics of
+/// the run. The run is persisted to the database only with `--persist`.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_once<M: StateMapper>(
+    args: &Cli,
+    parameters: &Parameters,
+    state_mapper: &M,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Running a single simulation");
+    let mut parameters = parameters.clone();
+    let metrics = if args.persist {
+        let connection_provider = open_and_migrate(&args.db, &PersistenceOptions::default())?;
+        let store = Mutex::new(SqliteResultStore::new(connection_provider));
+        let metrics = persisted_search_run(&mut parameters, args.iterations, state_mapper, &store)?;
+        println!(
+            "Persisted run {} to {}",
+            parameters.run_id.unwrap(),
+            args.db
+        );
+        metrics
+    } else {
+        let store = Mutex::new(MemoryResultStore::default());
+        persisted_search_run(&mut parameters, args.iterations, state_mapper, &store)?
+    };
+    print!("{}", format_metrics(&metrics));
+    Ok(())
+}
+
+/// Formats named metrics as one `name: value` line each.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_metrics(metrics: &[(&str, f64)]) -> String {
+    metrics
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect()
+}
+
+/// Reports an error that ends the program to the log and stderr and exits with a failure code.
+#[cfg(not(target_arch = "wasm32"))]
+fn exit_with_error(message: &str) -> ! {
+    log::error!("{}", message);
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Describes the parameter space a search would simulate: its number of points, the range of
+/// every axis of the sweep spec if the points are drawn from one, and the first and last point.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_dry_run<I: ExactSizeIterator<Item = Parameters>>(
+    spec: Option<&SweepSpec>,
+    mut parameter_space: I,
+) -> String {
+    let mut output = format!("Parameter points: {}\n", parameter_space.len());
+    if let Some(spec) = spec {
+        let amounts = spec.amounts.iter().map(|a| *a as f32).collect::<Vec<_>>();
+        let axes = [
+            ("amounts", &amounts),
+            ("borders", &spec.borders),
+            ("frictions", &spec.frictions),
+            ("timesteps", &spec.timesteps),
+            ("gravity_constants", &spec.gravity_constants),
+            ("force_exponents", &spec.force_exponents),
+            ("attraction_constants", &spec.attraction_constants),
+            ("repulsion_constants", &spec.repulsion_constants),
+            ("max_velocities", &spec.max_velocities),
+            ("position_bucket_sizes", &spec.position_bucket_sizes),
+            ("velocity_bucket_sizes", &spec.velocity_bucket_sizes),
+        ];
+        for (name, values) in axes {
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            output += &format!("{}: {} to {} ({} values)\n", name, min, max, values.len());
+        }
+    }
+    if let Some(first) = parameter_space.next() {
+        output += &format!("First: {:?}\n", first);
+    }
+    if let Some(last) = parameter_space.last() {
+        output += &format!("Last: {:?}\n", last);
+    }
+    output
+}
+
+/// Formats the given runs as a table with right aligned columns.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_run_table(runs: &[RunSummary]) -> String {
+    let header = RUN_TABLE_HEADER.map(String::from).to_vec();
+    let rows = runs.iter().map(|run| run_table_row(run).to_vec());
+    format_table(std::iter::once(header).chain(rows).collect())
+}
+
+/// Formats runs ranked by a metric as a table like `format_run_table` with a leading column of
+/// their metric values.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_ranked_run_table(metric_name: &str, runs: &[(RunSummary, f64)]) -> String {
+    let header = std::iter::once(metric_name.to_string())
+        .chain(RUN_TABLE_HEADER.map(String::from))
+        .collect();
+    let rows = runs.iter().map(|(run, value)| {
+        std::iter::once(value.to_string())
+            .chain(run_table_row(run))
+            .collect()
+    });
+    format_table(std::iter::once(header).chain(rows).collect())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const RUN_TABLE_HEADER: [&str; 11] = [
+    "run_id",
+    "amount",
+    "border",
+    "timestep",
+    "gravity",
+    "friction",
+    "max_velocity",
+    "pos_bucket",
+    "vel_bucket",
+    "created_at",
+    "rows",
+];
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_table_row(run: &RunSummary) -> [String; 11] {
+    [
+        run.run_id.to_string(),
+        run.amount.to_string(),
+        run.border.to_string(),
+        run.timestep.to_string(),
+        run.gravity_constant.to_string(),
+        run.friction.to_string(),
+        run.max_velocity.to_string(),
+        run.position_bucket_size.to_string(),
+        run.velocity_bucket_size.to_string(),
+        run.created_at.clone(),
+        run.state_vector_rows.to_string(),
+    ]
+}
+
+/// Right aligns every column of the table, whose first row is the header, to its widest cell.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_table(table: Vec<Vec<String>>) -> String {
+    let widths = (0..table[0].len())
+        .map(|column| table.iter().map(|row| row[column].len()).max().unwrap())
+        .collect::<Vec<_>>();
+    table
+        .iter()
+        .map(|row| {
+            let cells = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:>width$}", cell, width = width))
+                .collect::<Vec<_>>();
+            format!("{}\n", cells.join("  "))
+        })
+        .collect()
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn set_log_hook(log_file_path: &str) {
+fn set_log_hook(log_file_path: &str, log_format: LogFormat) {
     use log::{error, LevelFilter};
     use std::{ops::Deref, panic};
 
-    simple_logging::log_to_file(log_file_path, LevelFilter::Info)
-        .expect("Can't initialize logging");
+    match log_format {
+        LogFormat::Text => simple_logging::log_to_file(log_file_path, LevelFilter::Info)
+            .expect("Can't initialize logging"),
+        LogFormat::Json => {
+            // Appends, so the lines logged by earlier runs are kept
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file_path)
+                .expect("Can't initialize logging");
+            let logger = Box::new(JsonLogger::new(std::io::LineWriter::new(file)));
+            log::set_logger(Box::leak(logger)).expect("Can't initialize logging");
+            log::set_max_level(LevelFilter::Info);
+        }
+    }
 
     panic::set_hook(Box::new(|panic_info| {
         let (filename, line) = panic_info
@@ -89,14 +733,57 @@ pub fn start() -> Result<(), JsValue> {
 }
 
 pub fn run() {
-    let mut default_parameters = Parameters::default();
+    run_with_state_mapper(CartesianMapper);
+}
 
+/// Runs atomata, bucketing particles in search mode with the given state mapper.
+pub fn run_with_state_mapper<M: StateMapper + Sync>(state_mapper: M) {
     #[cfg(not(target_arch = "wasm32"))]
     let args = argh::from_env::<Cli>();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    match run_database_command(&args) {
+        Ok(true) => return,
+        Ok(false) => {}
+        Err(e) => exit_with_error(&format!("Database command failed: {}", e)),
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
-    let mode = match args.search {
-        true => Mode::Search,
-        false => Mode::Default,
+    let resume_snapshot = args.resume_snapshot.as_ref().map(|path| {
+        Snapshot::load(std::path::Path::new(path)).unwrap_or_else(|e| exit_with_error(&e))
+    });
+    #[cfg(not(target_arch = "wasm32"))]
+    let default_parameters = match (&resume_snapshot, args.replay) {
+        (Some(snapshot), _) => snapshot.parameters.clone(),
+        (None, Some(run_id)) => replay_parameters(&args.db, run_id)
+            .unwrap_or_else(|e| exit_with_error(&format!("Can't replay run {}: {}", run_id, e))),
+        (None, None) => args
+            .parameters()
+            .unwrap_or_else(|e| exit_with_error(&format!("Can't load parameters: {}", e))),
+    };
+    #[cfg(target_arch = "wasm32")]
+    let default_parameters = wasm::WASM_HANDLE.parameters();
+    if let Err(e) = default_parameters.validate() {
+        #[cfg(not(target_arch = "wasm32"))]
+        exit_with_error(&format!("Invalid parameters: {}", e));
+        #[cfg(target_arch = "wasm32")]
+        {
+            log::error!("Invalid parameters: {}", e);
+            return;
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mode = if args.render_frames.is_some() {
+        Mode::RenderFrames
+    } else if args.serve.is_some() {
+        Mode::Serve
+    } else if args.search || args.append_sweep.is_some() {
+        Mode::Search
+    } else if args.run_once {
+        Mode::RunOnce
+    } else {
+        Mode::Default
     };
     #[cfg(target_arch = "wasm32")]
     let mode = Mode::Default;
@@ -104,127 +791,190 @@ pub fn run() {
     match mode {
         #[cfg(not(target_arch = "wasm32"))]
         Mode::Search => {
-            info!("Running search mode");
-            set_log_hook(LOG_FILE_NAME);
-            info!("Initializing database...");
-            let connection_provider = Arc::new(Mutex::new(open_database("./results.db3").unwrap()));
-
-            info!("Migrating database...");
-            {
-                let mut connection = connection_provider.lock().unwrap();
-                migrate_to_latest(&mut connection).unwrap();
-            }
-
-            let mut parameter_space = Parameters::parameter_space();
-
-            info!("Persisting parameter space...");
-            {
-                let mut guard = connection_provider.lock().unwrap();
-                let tx_provider = create_transaction_provider(&mut guard).unwrap();
-
-                for parameters in parameter_space.iter_mut() {
-                    persist_parameters(parameters, &tx_provider).unwrap();
-                }
-
-                tx_provider.commit().unwrap();
+            if let Err(e) = run_search(&args, &default_parameters, &state_mapper) {
+                exit_with_error(&format!("Search failed: {}", e));
             }
-
-            let size_parameter_space = parameter_space.len();
-            let counter: Arc<Mutex<i32>> = Arc::new(Mutex::new(0));
-            let average_run_time = Arc::new(Mutex::new(0.0));
-
-            // Iterate over parameters and perform the search in parallel
-            parameter_space.par_iter().for_each(|parameters| {
-                {
-                    let counter = counter.lock().unwrap();
-                    let average_run_time = average_run_time.lock().unwrap();
-                    info!("Run {} / {}", *counter, size_parameter_space);
-                    info!("Average run time: {:.2} s", *average_run_time);
-
-                    let remaining_time_s =
-                        *average_run_time * (size_parameter_space - *counter as usize) as f64;
-                    // Print in HH:SS format
-                    info!(
-                        "Expected remaining time: {}:{} HH:MM",
-                        (remaining_time_s / 3600.0) as u32,
-                        ((remaining_time_s % 3600.0) / 60.0) as u32
-                    );
-                    info!("Parameters: {:?}", parameters);
-                }
-                let start_time = std::time::Instant::now();
-
-                let mut particles = create_particles(None, &default_parameters);
-                let iterations = 10000;
-
-                // Perform the computation and persistence for each iteration
-                let mut results: Vec<StateVector> = vec![];
-                for _ in 0..iterations {
-                    update_particles(&mut particles, &default_parameters).unwrap();
-                    let mut state_vectors = particles
-                        .iter()
-                        .map(|p| {
-                            let particle_parameters_id = parameters
-                                .particle_parameters_by_index(p.index)
-                                .unwrap()
-                                .id
-                                .unwrap();
-                            p.to_state_vector(parameters.bucket_size, particle_parameters_id)
-                        })
-                        .collect::<Vec<_>>();
-                    results.append(&mut state_vectors);
-                }
-                // Persist results sequentially/synchronous on the main thread
-                let connection = Arc::clone(&connection_provider);
-                let mut guard = connection.lock().unwrap();
-                let tx_provider = create_transaction_provider(&mut guard).unwrap();
-                for result in results {
-                    increment_state_count(&result, &tx_provider).unwrap();
-                }
-                commit_transaction(tx_provider).unwrap();
-
-                let mut counter = counter.lock().unwrap();
-                *counter += 1;
-
-                let elapsed_time = start_time.elapsed().as_secs_f64();
-                let mut average_run_time = average_run_time.lock().unwrap();
-                *average_run_time =
-                    *average_run_time + (elapsed_time - *average_run_time) / (*counter as f64);
-            });
         }
         #[cfg(target_arch = "wasm32")]
         Mode::Search => {
-            // Search logic not supported in wasm architecture
-            // Add appropriate error handling or fallback logic here
+            // The page has no command line, searches are started from JavaScript through
+            // `wasm::search` and persisted to IndexedDB
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Mode::RunOnce => {
+            if let Err(e) = run_once(&args, &default_parameters, &state_mapper) {
+                exit_with_error(&format!("Run failed: {}", e));
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        Mode::RunOnce => {
+            // Headless runs are started from the command line, which wasm doesn't have
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Mode::RenderFrames => {
+            let directory = args.render_frames.as_ref().unwrap();
+            info!("Rendering {} frames to {}", args.iterations, directory);
+            if let Err(e) = render_frames(
+                &default_parameters,
+                args.iterations,
+                std::path::Path::new(directory),
+            ) {
+                exit_with_error(&format!("Can't render frames: {}", e));
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        Mode::RenderFrames => {
+            // Headless rendering is not supported in wasm architecture
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Mode::Serve => {
+            #[cfg(feature = "serve")]
+            if let Err(e) = serve::serve(
+                args.serve.unwrap(),
+                resume_snapshot
+                    .map(Simulation::restore)
+                    .unwrap_or_else(|| Simulation::new(default_parameters)),
+            ) {
+                exit_with_error(&format!("Can't serve frames: {}", e));
+            }
+            #[cfg(not(feature = "serve"))]
+            exit_with_error("Serve mode requires atomata to be built with the serve feature");
+        }
+        #[cfg(target_arch = "wasm32")]
+        Mode::Serve => {
+            // Serving frames over HTTP is not supported in wasm architecture
         }
         Mode::Default => {
-            let window = Window::new(WindowSettings {
-                title: "atomata".to_string(),
-                max_size: Some((1280, 720)),
-                ..Default::default()
-            })
-            .unwrap();
+            #[cfg(not(target_arch = "wasm32"))]
+            if args.dry_run {
+                print!("{}", default_parameters.to_toml());
+                return;
+            }
+            let window = match create_window(|| {
+                Window::new(WindowSettings {
+                    title: "atomata".to_string(),
+                    max_size: Some((1280, 720)),
+                    ..Default::default()
+                })
+            }) {
+                Ok(window) => window,
+                #[cfg(not(target_arch = "wasm32"))]
+                Err(message) => exit_with_error(&message),
+                #[cfg(target_arch = "wasm32")]
+                Err(message) => {
+                    log::error!("{}", message);
+                    return;
+                }
+            };
             let context = window.gl();
-            let light0 = DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, -0.5, -0.5));
-            let light1 = DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, 0.5, 0.5));
+            let mut render_settings = RenderSettings::default();
+            #[cfg(not(target_arch = "wasm32"))]
+            if args.orthographic {
+                render_settings.projection = Projection::Orthographic;
+            }
+            let mut light0 =
+                DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, -0.5, -0.5));
+            let mut light1 =
+                DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, 0.5, 0.5));
+            let mut ambient_light = AmbientLight::new(&context, 1.0, Srgba::WHITE);
 
-            let mut camera = Camera::new_perspective(
+            #[cfg(not(target_arch = "wasm32"))]
+            let (mut field_of_view, initial_camera_distance) = (args.fov, args.camera_distance);
+            #[cfg(target_arch = "wasm32")]
+            let (mut field_of_view, initial_camera_distance) = (DEFAULT_FIELD_OF_VIEW, None);
+            let initial_camera_distance = initial_camera_distance
+                .unwrap_or_else(|| camera_distance(default_parameters.border, field_of_view));
+            let mut camera = default_camera(
                 window.viewport(),
-                vec3(5.0, 2.0, 2.5),
-                vec3(0.0, 0.0, -0.5),
-                vec3(0.0, 1.0, 0.0),
-                degrees(45.0),
-                0.1,
-                1000.0,
+                default_parameters.border,
+                field_of_view,
+                initial_camera_distance,
+            );
+            set_projection(
+                &mut camera,
+                render_settings.projection,
+                field_of_view,
+                default_parameters.border,
             );
-            let mut control = OrbitControl::new(*camera.target(), 1.0, 1000.0);
+            let mut control = orbit_control(*camera.target(), initial_camera_distance);
             let mut gui = three_d::GUI::new(&context);
 
-            let mut particles = create_particles(Some(&context), &default_parameters);
+            let mut active_seed = default_parameters.seed.unwrap_or_else(random_seed);
+            let mut seed_text = active_seed.to_string();
+            #[cfg(not(target_arch = "wasm32"))]
+            let restored_simulation = resume_snapshot.map(Simulation::restore);
+            #[cfg(target_arch = "wasm32")]
+            let restored_simulation = None;
+            let mut simulation = restored_simulation.unwrap_or_else(|| {
+                Simulation::with_rng(default_parameters, ChaCha12Rng::seed_from_u64(active_seed))
+            });
+            let mut particle_renderer =
+                ParticleRenderer::new(&context, simulation.parameters.sphere_subdivisions);
+            let mut max_velocity_tracker = MaxVelocityTracker::default();
+            #[cfg(not(target_arch = "wasm32"))]
+            let mut time_mode = if args.real_time {
+                TimeMode::RealTime
+            } else {
+                TimeMode::Fixed
+            };
+            #[cfg(target_arch = "wasm32")]
+            let mut time_mode = TimeMode::Fixed;
+            let mut paused = false;
+            let mut step = false;
+            let mut iteration_step: usize = 0;
+            // Trails are off by default as they multiply the rendered instances by their length
+            let mut show_trails = false;
+            let mut trail_length: usize = 20;
+            // The force field overlay is off by default as sampling it costs
+            // `field_resolution³ × amount` force evaluations per frame
+            let mut field_renderer = FieldRenderer::new(&context);
+            let mut show_field = false;
+            let mut field_resolution: usize = 8;
+            let mut field_probe: usize = 0;
+            let mut fps = 0.0;
+            #[cfg(not(target_arch = "wasm32"))]
+            let mut take_screenshot = false;
+            #[cfg(not(target_arch = "wasm32"))]
+            let (snapshot_every, snapshot_path) = (
+                args.snapshot_every.filter(|every| *every > 0),
+                std::path::PathBuf::from(&args.snapshot_path),
+            );
+            #[cfg(target_arch = "wasm32")]
+            let wasm_handle = wasm::WASM_HANDLE.clone();
             window.render_loop(move |mut frame_input| {
                 camera.set_viewport(frame_input.viewport);
+                #[cfg(target_arch = "wasm32")]
+                if let Some(parameters) = wasm_handle.take_changed_parameters() {
+                    simulation.parameters = parameters;
+                }
+                #[cfg(target_arch = "wasm32")]
+                let reset_requested = wasm_handle.take_reset();
+                #[cfg(not(target_arch = "wasm32"))]
+                let reset_requested = false;
+                fps = smooth_fps(fps, frame_input.elapsed_time);
                 control.handle_events(&mut camera, &mut frame_input.events);
 
-                update_particles(&mut particles, &default_parameters).unwrap();
+                particle_renderer.set_trail_length(if show_trails { trail_length } else { 0 });
+
+                if !paused || step {
+                    let timestep = time_mode
+                        .timestep(simulation.parameters.timestep, frame_input.elapsed_time);
+                    simulation.step_by(timestep).unwrap();
+                    max_velocity_tracker.record(
+                        simulation.particles.len(),
+                        simulation.last_step_clamp_events(),
+                    );
+                    particle_renderer.push_trails(&simulation.particles);
+                    iteration_step += 1;
+                    step = false;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if snapshot_every.is_some_and(|every| iteration_step.is_multiple_of(every)) {
+                        match simulation.snapshot().save(&snapshot_path) {
+                            Ok(()) => info!("Saved snapshot to {}", snapshot_path.display()),
+                            Err(e) => log::error!("{}", e),
+                        }
+                    }
+                }
 
                 let mut panel_width = 0.0;
                 gui.update(
@@ -236,52 +986,447 @@ pub fn run() {
                         SidePanel::left("side_panel").show(gui_context, |ui| {
                             ui.heading("Parameters");
                             ui.add(
-                                Slider::new(&mut default_parameters.amount, 1..=500).text("Amount"),
+                                Slider::new(&mut simulation.parameters.amount, 1..=500)
+                                    .text("Amount"),
                             );
-                            if ui.button("Reset").clicked() {
-                                particles = create_particles(Some(&context), &default_parameters);
+                            let mut kinds_changed = false;
+                            ui.horizontal(|ui| {
+                                if ui.button("+ Add kind").clicked() {
+                                    simulation
+                                        .parameters
+                                        .add_particle_kind(NEW_PARTICLE_KIND_MASS);
+                                    kinds_changed = true;
+                                }
+                                let removable = simulation.parameters.particle_parameters.len() > 1;
+                                if ui
+                                    .add_enabled(removable, Button::new("− Remove kind"))
+                                    .clicked()
+                                {
+                                    simulation.parameters.remove_particle_kind();
+                                    kinds_changed = true;
+                                }
+                            });
+                            if ui.button("Reset").clicked() || reset_requested || kinds_changed {
+                                active_seed =
+                                    simulation.parameters.seed.unwrap_or_else(random_seed);
+                                seed_text = active_seed.to_string();
+                                simulation = Simulation::with_rng(
+                                    simulation.parameters.clone(),
+                                    ChaCha12Rng::seed_from_u64(active_seed),
+                                );
+                                particle_renderer.clear_trails();
+                                max_velocity_tracker = MaxVelocityTracker::default();
+                                iteration_step = 0;
                             };
+                            ui.horizontal(|ui| {
+                                ui.label("Seed");
+                                ui.text_edit_singleline(&mut seed_text);
+                            });
+                            ui.horizontal(|ui| {
+                                let seed = seed_text.trim().parse::<u64>();
+                                if ui
+                                    .add_enabled(seed.is_ok(), Button::new("Reset with seed"))
+                                    .clicked()
+                                {
+                                    active_seed = seed.unwrap();
+                                    simulation = Simulation::with_rng(
+                                        simulation.parameters.clone(),
+                                        ChaCha12Rng::seed_from_u64(active_seed),
+                                    );
+                                    particle_renderer.clear_trails();
+                                    max_velocity_tracker = MaxVelocityTracker::default();
+                                    iteration_step = 0;
+                                }
+                                if ui.button("Randomize seed").clicked() {
+                                    seed_text = random_seed().to_string();
+                                }
+                            });
+                            ui.label(format!("Active seed: {}", active_seed));
+                            ui.horizontal(|ui| {
+                                ui.toggle_value(&mut paused, "Pause");
+                                if ui.add_enabled(paused, Button::new("Step")).clicked() {
+                                    step = true;
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                for mode in [TimeMode::Fixed, TimeMode::RealTime] {
+                                    ui.radio_value(&mut time_mode, mode, mode.to_string());
+                                }
+                            });
+                            ui.label(format!("Iteration step: {}", iteration_step));
+                            ui.label(format!("FPS: {:.0}", fps));
+                            ui.label(format!("Particles: {}", simulation.particles.len()));
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut show_trails, "Trails");
+                                ui.add_enabled(
+                                    show_trails,
+                                    Slider::new(&mut trail_length, 1..=100).text("Trail length"),
+                                );
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut show_field, "Force field");
+                                ui.add_enabled(
+                                    show_field,
+                                    Slider::new(&mut field_resolution, 2..=16)
+                                        .text("Field resolution"),
+                                );
+                            });
+                            if show_field {
+                                let kinds = simulation.parameters.particle_parameters.len();
+                                field_probe = field_probe.min(kinds.saturating_sub(1));
+                                ui.add(
+                                    Slider::new(&mut field_probe, 0..=kinds.saturating_sub(1))
+                                        .text("Field of kind"),
+                                );
+                            }
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add(
+                                        Slider::new(&mut field_of_view, 10.0..=120.0)
+                                            .text("Field of view"),
+                                    )
+                                    .changed()
+                                {
+                                    set_projection(
+                                        &mut camera,
+                                        render_settings.projection,
+                                        field_of_view,
+                                        simulation.parameters.border,
+                                    );
+                                }
+                                if ui.button("Fit to bounds").clicked() {
+                                    let positions = simulation
+                                        .particles
+                                        .iter()
+                                        .map(|p| p.position)
+                                        .collect::<Vec<_>>();
+                                    let (center, radius) = bounding_sphere(&positions);
+                                    let distance = camera_distance(radius, field_of_view);
+                                    let direction =
+                                        (camera.position() - camera.target()).normalize();
+                                    camera.set_view(
+                                        center + direction * distance,
+                                        center,
+                                        vec3(0.0, 1.0, 0.0),
+                                    );
+                                    set_projection(
+                                        &mut camera,
+                                        render_settings.projection,
+                                        field_of_view,
+                                        radius,
+                                    );
+                                    control = orbit_control(center, distance);
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                let mut changed = false;
+                                for projection in
+                                    [Projection::Perspective, Projection::Orthographic]
+                                {
+                                    changed |= ui
+                                        .radio_value(
+                                            &mut render_settings.projection,
+                                            projection,
+                                            projection.to_string(),
+                                        )
+                                        .changed();
+                                }
+                                if changed {
+                                    set_projection(
+                                        &mut camera,
+                                        render_settings.projection,
+                                        field_of_view,
+                                        simulation.parameters.border,
+                                    );
+                                }
+                            });
+                            ui.collapsing("Rendering", |ui| {
+                                let background = &mut render_settings.background;
+                                let mut rgb = [background.r, background.g, background.b];
+                                ui.horizontal(|ui| {
+                                    ui.label("Background");
+                                    if ui.color_edit_button_srgb(&mut rgb).changed() {
+                                        *background = Srgba::new_opaque(rgb[0], rgb[1], rgb[2]);
+                                    }
+                                });
+                                ui.checkbox(
+                                    &mut render_settings.ambient_only,
+                                    "Ambient light only",
+                                );
+                                if render_settings.ambient_only {
+                                    ui.add(
+                                        Slider::new(
+                                            &mut render_settings.ambient_intensity,
+                                            0.0..=3.0,
+                                        )
+                                        .text("Ambient light"),
+                                    );
+                                } else {
+                                    for (i, intensity) in
+                                        render_settings.light_intensities.iter_mut().enumerate()
+                                    {
+                                        ui.add(
+                                            Slider::new(intensity, 0.0..=3.0)
+                                                .text(format!("Light {}", i + 1)),
+                                        );
+                                    }
+                                }
+                                if ui
+                                    .add(
+                                        Slider::new(
+                                            &mut simulation.parameters.sphere_subdivisions,
+                                            4..=32,
+                                        )
+                                        .text("Sphere subdivisions"),
+                                    )
+                                    .changed()
+                                {
+                                    particle_renderer.set_subdivisions(
+                                        &context,
+                                        simulation.parameters.sphere_subdivisions,
+                                    );
+                                }
+                                let material = &mut render_settings.material;
+                                ui.add(
+                                    Slider::new(&mut material.opacity, 0.05..=1.0).text("Opacity"),
+                                );
+                                ui.add(
+                                    Slider::new(&mut material.metallic, 0.0..=1.0).text("Metallic"),
+                                );
+                                ui.add(
+                                    Slider::new(&mut material.roughness, 0.0..=1.0)
+                                        .text("Roughness"),
+                                );
+                            });
+                            #[cfg(not(target_arch = "wasm32"))]
+                            ui.horizontal(|ui| {
+                                if ui.button("Save config").clicked() {
+                                    save_parameters_dialog(&simulation.parameters);
+                                }
+                                if ui.button("Load config").clicked() {
+                                    if let Some(parameters) = load_parameters_dialog() {
+                                        active_seed = parameters.seed.unwrap_or_else(random_seed);
+                                        seed_text = active_seed.to_string();
+                                        simulation = Simulation::with_rng(
+                                            parameters,
+                                            ChaCha12Rng::seed_from_u64(active_seed),
+                                        );
+                                        particle_renderer.clear_trails();
+                                        max_velocity_tracker = MaxVelocityTracker::default();
+                                        iteration_step = 0;
+                                    }
+                                }
+                                if ui.button("Screenshot").clicked() {
+                                    take_screenshot = true;
+                                }
+                            });
                             ui.add(
-                                Slider::new(&mut default_parameters.max_velocity, 50.0..=50000.0)
-                                    .text("Max. velocity"),
+                                Slider::new(
+                                    &mut simulation.parameters.max_velocity,
+                                    50.0..=50000.0,
+                                )
+                                .text("Max. velocity"),
                             );
+                            ComboBox::from_label("Velocity clamp")
+                                .selected_text(simulation.parameters.velocity_clamp.to_string())
+                                .show_ui(ui, |ui| {
+                                    for velocity_clamp in
+                                        [VelocityClamp::PerAxis, VelocityClamp::Magnitude]
+                                    {
+                                        ui.selectable_value(
+                                            &mut simulation.parameters.velocity_clamp,
+                                            velocity_clamp,
+                                            velocity_clamp.to_string(),
+                                        );
+                                    }
+                                });
                             ui.add(
-                                Slider::new(&mut default_parameters.friction, 0.0..=0.01)
+                                Slider::new(&mut simulation.parameters.friction, 0.0..=0.01)
                                     .text("Friction"),
                             );
                             ui.add(
-                                Slider::new(&mut default_parameters.border, 50.0..=500.0)
+                                Slider::new(&mut simulation.parameters.border, 50.0..=500.0)
                                     .text("Border"),
                             );
+                            ComboBox::from_label("Boundary")
+                                .selected_text(simulation.parameters.boundary_shape.to_string())
+                                .show_ui(ui, |ui| {
+                                    for boundary_shape in [
+                                        BoundaryShape::Sphere,
+                                        BoundaryShape::Box,
+                                        BoundaryShape::Periodic,
+                                    ] {
+                                        ui.selectable_value(
+                                            &mut simulation.parameters.boundary_shape,
+                                            boundary_shape,
+                                            boundary_shape.to_string(),
+                                        );
+                                    }
+                                });
                             ui.add(
-                                Slider::new(&mut default_parameters.timestep, 0.0001..=0.001)
+                                Slider::new(&mut simulation.parameters.timestep, 0.0001..=0.001)
                                     .text("Timestep"),
                             );
                             ui.add(
-                                Slider::new(&mut default_parameters.gravity_constant, 0.1..=20.0)
-                                    .text("Gravity constant"),
+                                Slider::new(
+                                    &mut simulation.parameters.gravity_constant,
+                                    0.1..=20.0,
+                                )
+                                .text("Gravity constant"),
                             );
-                            for particle in default_parameters.particle_parameters.iter_mut() {
-                                ui.collapsing(format!("Particle {}", particle.index), |ui| {
-                                    ui.add(
+                            ui.add(
+                                Slider::new(&mut simulation.parameters.force_exponent, 1.0..=3.0)
+                                    .text("Force exponent"),
+                            );
+                            ComboBox::from_label("Force law")
+                                .selected_text(simulation.parameters.force_law.to_string())
+                                .show_ui(ui, |ui| {
+                                    for force_law in [
+                                        ForceLaw::InversePower,
+                                        ForceLaw::LennardJones {
+                                            epsilon: 100.0,
+                                            sigma: 10.0,
+                                        },
+                                    ] {
+                                        // Keep the current epsilon and sigma when reselecting
+                                        let selected = std::mem::discriminant(&force_law)
+                                            == std::mem::discriminant(
+                                                &simulation.parameters.force_law,
+                                            );
+                                        if ui
+                                            .selectable_label(selected, force_law.to_string())
+                                            .clicked()
+                                            && !selected
+                                        {
+                                            simulation.parameters.force_law = force_law;
+                                        }
+                                    }
+                                });
+                            match &mut simulation.parameters.force_law {
+                                ForceLaw::InversePower => {
+                                    ui.add(
+                                        Slider::new(
+                                            &mut simulation.parameters.attraction_constant,
+                                            0.0..=10.0,
+                                        )
+                                        .text("Attraction constant"),
+                                    );
+                                    ui.add(
+                                        Slider::new(
+                                            &mut simulation.parameters.repulsion_constant,
+                                            0.0..=10.0,
+                                        )
+                                        .text("Repulsion constant"),
+                                    );
+                                }
+                                ForceLaw::LennardJones { epsilon, sigma } => {
+                                    ui.add(
+                                        Slider::new(epsilon, 1.0..=10000.0)
+                                            .logarithmic(true)
+                                            .text("Epsilon"),
+                                    );
+                                    ui.add(Slider::new(sigma, 1.0..=50.0).text("Sigma"));
+                                }
+                            }
+                            ui.add(
+                                Slider::new(&mut simulation.parameters.temperature, 0.0..=1.0e8)
+                                    .logarithmic(true)
+                                    .text("Temperature"),
+                            );
+                            ui.checkbox(
+                                &mut simulation.parameters.recenter,
+                                "Center of mass frame",
+                            );
+                            ui.label(format!(
+                                "At max. velocity: {:.1} %",
+                                max_velocity_tracker.fraction() * 100.0
+                            ));
+                            let mut reset_kind = None;
+                            for particle in simulation.parameters.particle_parameters.iter_mut() {
+                                ui.collapsing(format!("Particle {}", particle.index), |ui| {
+                                    ui.add(
                                         Slider::new(&mut particle.mass, 1.0..=10000.0).text("Mass"),
                                     );
+                                    ui.horizontal(|ui| {
+                                        if ui.button("Reset").clicked() {
+                                            reset_kind = Some(particle.index);
+                                        }
+                                        ui.checkbox(&mut particle.frozen, "Frozen");
+                                    });
+                                    let Some(color) = particle
+                                        .color
+                                        .or_else(|| simulation.colors.get(particle.index).copied())
+                                    else {
+                                        return;
+                                    };
+                                    let mut rgb = [color.r, color.g, color.b];
+                                    ui.horizontal(|ui| {
+                                        ui.label("Color");
+                                        if ui.color_edit_button_srgb(&mut rgb).changed() {
+                                            let color = Srgba::new_opaque(rgb[0], rgb[1], rgb[2]);
+                                            particle.color = Some(color);
+                                            if let Some(kind_color) =
+                                                simulation.colors.get_mut(particle.index)
+                                            {
+                                                *kind_color = color;
+                                            }
+                                        }
+                                    });
                                 });
                             }
+                            if let Some(index) = reset_kind {
+                                simulation.reset_kind(index).unwrap();
+                                particle_renderer.clear_trails();
+                            }
                         });
                         panel_width = gui_context.used_rect().width();
                     },
                 );
+                #[cfg(target_arch = "wasm32")]
+                wasm_handle.sync(&simulation.parameters);
 
-                let spheres = particles
-                    .iter()
-                    .map(|p| p.positionable.as_ref().unwrap().get_geometry())
-                    .collect::<Vec<_>>();
-                frame_input
-                    .screen()
-                    .clear(ClearState::color_and_depth(0.8, 0.8, 0.8, 1.0, 1.0))
-                    .render(&camera, &spheres, &[&light0, &light1])
-                    .write(|| gui.render());
+                particle_renderer.set_material(&context, render_settings.material);
+                particle_renderer.update(&simulation.particles, &simulation.colors);
+                if show_field {
+                    let samples =
+                        field_samples(&simulation, field_resolution, field_probe).unwrap();
+                    field_renderer.update(&samples);
+                }
+                light0.intensity = render_settings.light_intensities[0];
+                light1.intensity = render_settings.light_intensities[1];
+                ambient_light.intensity = render_settings.ambient_intensity;
+                let lights: Vec<&dyn Light> = if render_settings.ambient_only {
+                    vec![&ambient_light]
+                } else {
+                    vec![&light0, &light1]
+                };
+                let screen = frame_input.screen();
+                screen
+                    .clear(render_settings.clear_state())
+                    .render(&camera, [particle_renderer.spheres()], &lights)
+                    .render(&camera, [particle_renderer.trails()], &lights);
+                if show_field {
+                    screen.render(&camera, [field_renderer.points()], &lights);
+                }
+
+                // Read back before the GUI is drawn so the side panel is not captured
+                #[cfg(not(target_arch = "wasm32"))]
+                if take_screenshot {
+                    take_screenshot = false;
+                    let pixels = screen.read_color::<[u8; 4]>();
+                    let viewport = frame_input.viewport;
+                    match save_screenshot(
+                        &pixels,
+                        viewport.width,
+                        viewport.height,
+                        std::path::Path::new("."),
+                    ) {
+                        Ok(path) => info!("Saved screenshot to {}", path.display()),
+                        Err(e) => log::error!("Can't save screenshot: {}", e),
+                    }
+                }
+
+                screen.write(|| gui.render());
 
                 FrameOutput::default()
             });
@@ -289,108 +1434,2296 @@ pub fn run() {
     }
 }
 
-/// Generates rgb n rgb color with the maximum possible contrast
-fn generate_colors(num_colors: usize) -> Vec<Srgba> {
+/// Creates the window of default mode with the given factory. Its failure, typically on a
+/// machine without a display or a usable GPU, is turned into a message pointing to the headless
+/// modes.
+fn create_window<W, E: std::fmt::Display>(
+    factory: impl FnOnce() -> Result<W, E>,
+) -> Result<W, String> {
+    factory().map_err(|e| {
+        format!(
+            "Can't open a window: {}. Without a display or GPU, simulate headlessly with \
+             --search or --run-once, or render to PNG files with --render-frames.",
+            e
+        )
+    })
+}
+
+/// How the scene is lit and cleared in default mode, adjustable from the GUI without resetting
+/// the simulation.
+#[derive(Debug, Clone, PartialEq)]
+struct RenderSettings {
+    background: Srgba,
+    /// Intensities of the two directional lights
+    light_intensities: [f32; 2],
+    /// Whether a single ambient light replaces the directional lights, which shows the particle
+    /// colors without shading
+    ambient_only: bool,
+    ambient_intensity: f32,
+    projection: Projection,
+    /// Material of the particle spheres, opaque, non-metallic and fully rough by default
+    material: SphereMaterial,
+}
+
+/// Projection of the camera, orthographic projection shows symmetric structures undistorted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+impl std::fmt::Display for Projection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How far the simulation advances per rendered frame in default mode. Search mode always steps
+/// by the fixed timestep of the parameters, so its runs are reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeMode {
+    /// One `timestep` per frame, so the playback speed follows the frame rate
+    Fixed,
+    /// `timestep` scaled by the frame time relative to `REFERENCE_FRAME_TIME`, so the playback
+    /// speed is the same at any frame rate
+    RealTime,
+}
+
+impl std::fmt::Display for TimeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Frame time in milliseconds at which the real-time step equals the fixed one, i.e. 60 FPS
+const REFERENCE_FRAME_TIME: f64 = 1000.0 / 60.0;
+/// Longest frame time in milliseconds a real-time step accounts for, so a stalled frame does not
+/// cause a single huge and unstable step
+const MAX_FRAME_TIME: f64 = 100.0;
+
+impl TimeMode {
+    /// Returns the timestep of a frame that took `elapsed_time` milliseconds.
+    fn timestep(self, timestep: f32, elapsed_time: f64) -> f32 {
+        match self {
+            TimeMode::Fixed => timestep,
+            TimeMode::RealTime => {
+                let scale = elapsed_time.clamp(0.0, MAX_FRAME_TIME) / REFERENCE_FRAME_TIME;
+                timestep * scale as f32
+            }
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            background: Srgba::new_opaque(204, 204, 204),
+            light_intensities: [1.0, 1.0],
+            ambient_only: false,
+            ambient_intensity: 1.0,
+            projection: Projection::Perspective,
+            material: SphereMaterial::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Returns the background as RGBA components from zero to one.
+    fn clear_color(&self) -> [f32; 4] {
+        let Srgba { r, g, b, a } = self.background;
+        [r, g, b, a].map(|component| component as f32 / 255.0)
+    }
+
+    fn clear_state(&self) -> ClearState {
+        let [r, g, b, a] = self.clear_color();
+        ClearState::color_and_depth(r, g, b, a, 1.0)
+    }
+}
+
+/// Vertical field of view of the camera in degrees, unless given on the command line
+const DEFAULT_FIELD_OF_VIEW: f32 = 45.0;
+
+/// Returns the camera the simulation is initially viewed from, looking at the center from the
+/// given distance.
+fn default_camera(viewport: Viewport, border: f32, field_of_view: f32, distance: f32) -> Camera {
+    Camera::new_perspective(
+        viewport,
+        vec3(5.0, 2.0, 2.5).normalize() * distance,
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        degrees(field_of_view),
+        0.1,
+        far_plane(distance, border),
+    )
+}
+
+/// Sets the projection of the camera for its current distance to the target. The orthographic
+/// view covers what the perspective view covers at the target, so switching between them keeps
+/// the framing.
+fn set_projection(camera: &mut Camera, projection: Projection, field_of_view: f32, radius: f32) {
+    let distance = (camera.position() - camera.target()).magnitude();
+    let z_far = far_plane(distance, radius);
+    match projection {
+        Projection::Perspective => {
+            camera.set_perspective_projection(degrees(field_of_view), 0.1, z_far)
+        }
+        Projection::Orthographic => camera.set_orthographic_projection(
+            orthographic_height(distance, field_of_view),
+            0.1,
+            z_far,
+        ),
+    }
+}
+
+/// Returns the height of the view of a perspective camera with the given vertical field of view
+/// in degrees at the given distance.
+fn orthographic_height(distance: f32, field_of_view: f32) -> f32 {
+    2.0 * distance * (field_of_view.to_radians() / 2.0).tan()
+}
+
+/// Returns the distance from which a camera with the given vertical field of view in degrees
+/// sees the whole sphere of the given radius around its target.
+fn camera_distance(radius: f32, field_of_view: f32) -> f32 {
+    radius / (field_of_view.to_radians() / 2.0).sin()
+}
+
+/// Returns a far plane behind everything within the given radius around the camera target.
+fn far_plane(distance: f32, radius: f32) -> f32 {
+    distance + 2.0 * radius
+}
+
+/// Returns an orbit control around the given target that can zoom out well beyond the given
+/// distance.
+fn orbit_control(target: Vector3<f32>, distance: f32) -> OrbitControl {
+    OrbitControl::new(target, 1.0, 10.0 * distance.max(100.0))
+}
+
+/// Returns the center and radius of a sphere containing all given positions. The center is their
+/// mean, so the sphere is not minimal but close to it for evenly spread positions.
+fn bounding_sphere(positions: &[Vector3<f32>]) -> (Vector3<f32>, f32) {
+    if positions.is_empty() {
+        return (vec3(0.0, 0.0, 0.0), 1.0);
+    }
+    let center = positions
+        .iter()
+        .fold(vec3(0.0, 0.0, 0.0), |sum, position| sum + position)
+        / positions.len() as f32;
+    let radius = positions
+        .iter()
+        .map(|position| (position - center).magnitude())
+        .fold(0.0, f32::max);
+    // Leave room for the spheres themselves
+    (center, radius + 1.0)
+}
+
+/// Simulates the given number of iterations and renders each of them from the default camera
+/// into `frame-<iteration>.png` files in the given directory.
+///
+/// Rendering happens offscreen in a headless OpenGL context, so no window or display is needed.
+/// It still needs an OpenGL 3.3 capable driver that can create a context without a display,
+/// e.g. Mesa via EGL on Linux servers, which falls back to rendering on the CPU without a GPU.
+#[cfg(not(target_arch = "wasm32"))]
+fn render_frames(
+    parameters: &Parameters,
+    iterations: usize,
+    directory: &std::path::Path,
+) -> Result<(), String> {
+    use three_d::{
+        DepthTexture2D, HeadlessContext, Interpolation, RenderTarget, Texture2D, Wrapping,
+    };
+
+    let (width, height) = (1280, 720);
+    let context = HeadlessContext::new().map_err(|e| e.to_string())?;
+    let camera = default_camera(
+        Viewport::new_at_origo(width, height),
+        parameters.border,
+        DEFAULT_FIELD_OF_VIEW,
+        camera_distance(parameters.border, DEFAULT_FIELD_OF_VIEW),
+    );
+    let light0 = DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, -0.5, -0.5));
+    let light1 = DirectionalLight::new(&context, 1.0, Srgba::WHITE, &vec3(0.0, 0.5, 0.5));
+    let mut texture = Texture2D::new_empty::<[u8; 4]>(
+        &context,
+        width,
+        height,
+        Interpolation::Nearest,
+        Interpolation::Nearest,
+        None,
+        Wrapping::ClampToEdge,
+        Wrapping::ClampToEdge,
+    );
+    let mut depth_texture = DepthTexture2D::new::<f32>(
+        &context,
+        width,
+        height,
+        Wrapping::ClampToEdge,
+        Wrapping::ClampToEdge,
+    );
+
+    std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    let mut simulation = Simulation::with_rng(parameters.clone(), seeded_rng(parameters.seed));
+    let mut particle_renderer = ParticleRenderer::new(&context, parameters.sphere_subdivisions);
+    let progress_bar = create_progress_bar(iterations);
+    for iteration in 0..iterations {
+        simulation.step()?;
+
+        particle_renderer.update(&simulation.particles, &simulation.colors);
+        let pixels = RenderTarget::new(
+            texture.as_color_target(None),
+            depth_texture.as_depth_target(),
+        )
+        .clear(RenderSettings::default().clear_state())
+        .render(&camera, [particle_renderer.spheres()], &[&light0, &light1])
+        .read_color::<[u8; 4]>();
+
+        let path = directory.join(format!("frame-{:05}.png", iteration));
+        save_png(&pixels, width, height, &path)?;
+        progress_bar.inc(1);
+    }
+    progress_bar.finish();
+
+    Ok(())
+}
+
+/// Weight of the latest frame in the smoothed frames per second
+const FPS_SMOOTHING: f64 = 0.05;
+
+/// Returns the exponential moving average of the frames per second given the previous average
+/// and the duration of the latest frame in milliseconds.
+fn smooth_fps(fps: f64, elapsed_time: f64) -> f64 {
+    if elapsed_time <= 0.0 {
+        return fps;
+    }
+    let current_fps = 1000.0 / elapsed_time;
+    if fps == 0.0 {
+        return current_fps;
+    }
+    fps + FPS_SMOOTHING * (current_fps - fps)
+}
+
+/// Samples the force field a particle of the kind `probe_index` would feel on a lattice of
+/// `resolution³` points spanning the border.
+fn field_samples(
+    simulation: &Simulation,
+    resolution: usize,
+    probe_index: usize,
+) -> Result<Vec<FieldSample>, String> {
+    field_grid(resolution, simulation.parameters.border)
+        .into_iter()
+        .map(|point| {
+            let field = field_strength(
+                point,
+                probe_index,
+                &simulation.particles,
+                &simulation.parameters,
+            )?;
+            Ok(FieldSample {
+                position: point,
+                field,
+            })
+        })
+        .collect()
+}
+
+/// Simulates every point of the parameter space in parallel, persisting the results to the
+/// given store, which is returned afterwards. Once `shutdown` is set no further runs are
+/// started, while the runs in flight are completed and committed. A run failing to persist its
+/// results sets `shutdown` and fails the search once the runs in flight are done.
+#[cfg(not(target_arch = "wasm32"))]
+fn search<I, M, S>(
+    parameter_space: I,
+    iterations: usize,
+    state_mapper: &M,
+    store: S,
+    log_format: LogFormat,
+    shutdown: &AtomicBool,
+) -> Result<S, Box<dyn std::error::Error>>
+where
+    I: ExactSizeIterator<Item = Parameters> + Send,
+    M: StateMapper + Sync,
+    S: ResultStore + Send,
+{
+    let size_parameter_space = parameter_space.len();
+    let progress = SearchProgress::default();
+    let progress_bar = create_progress_bar(size_parameter_space);
+    let store = Mutex::new(store);
+    let error: Mutex<Option<String>> = Mutex::new(None);
+
+    // Iterate over parameters and perform the search in parallel. The parameters are generated
+    // and persisted as their runs start, so the parameter space is never held in memory at once
+    let parameter_space = parameter_space.take_while(|_| !shutdown.load(Ordering::Relaxed));
+    parameter_space.par_bridge().for_each(|mut parameters| {
+        let (completed_runs, average_run_time) = progress.snapshot();
+        info!("Run {} / {}", completed_runs, size_parameter_space);
+        info!("Average run time: {:.2} s", average_run_time);
+        info!(
+            "Expected remaining time: {} HH:MM",
+            format_remaining_time(remaining_time_s(
+                average_run_time,
+                completed_runs,
+                size_parameter_space
+            ))
+        );
+        info!("Parameters: {:?}", parameters);
+        let start_time = std::time::Instant::now();
+
+        let metrics = match persisted_search_run(&mut parameters, iterations, state_mapper, &store)
+        {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                shutdown.store(true, Ordering::Relaxed);
+                error.lock().unwrap().get_or_insert(e.to_string());
+                return;
+            }
+        };
+        let elapsed_time_s = start_time.elapsed().as_secs_f64();
+        match log_format {
+            LogFormat::Text => {
+                for (name, value) in &metrics {
+                    info!("Metric {}: {:.3}", name, value);
+                }
+            }
+            LogFormat::Json => RunRecord {
+                run_id: parameters.run_id.unwrap(),
+                parameters: &parameters,
+                elapsed_time_s,
+                metrics: metrics.iter().copied().collect(),
+            }
+            .log(),
+        }
+
+        let average_run_time = progress.record(elapsed_time_s);
+        progress_bar.set_message(format!("avg. {:.2} s/run", average_run_time));
+        progress_bar.inc(1);
+    });
+    progress_bar.finish();
+
+    if let Some(message) = error.into_inner().unwrap() {
+        return Err(message.into());
+    }
+
+    let (completed_runs, _) = progress.snapshot();
+    if shutdown.load(Ordering::Relaxed) {
+        let message = format!(
+            "Search interrupted after {} of {} runs, continue it with --resume",
+            completed_runs, size_parameter_space
+        );
+        info!("{}", message);
+        println!("{}", message);
+    } else {
+        info!("Search completed {} runs", completed_runs);
+    }
+
+    Ok(store.into_inner().unwrap())
+}
+
+/// Simulates a single parameter point like the search does and persists its parameters, state
+/// vectors, metrics and elapsed time to the store, which may be shared with other runs. Returns
+/// the metrics, the run id is assigned to the parameters. Fails if the store can't persist the
+/// results.
+#[cfg(not(target_arch = "wasm32"))]
+fn persisted_search_run<M, S>(
+    parameters: &mut Parameters,
+    iterations: usize,
+    state_mapper: &M,
+    store: &Mutex<S>,
+) -> Result<Vec<(&'static str, f64)>, Box<dyn std::error::Error>>
+where
+    M: StateMapper,
+    S: ResultStore,
+{
+    {
+        let mut store = store.lock().unwrap();
+        store.persist_parameters(parameters)?;
+        store.commit()?;
+    }
+
+    // State vectors are persisted in short transactions while the run goes on, so the memory of
+    // a run doesn't grow with the number of iterations
+    let start_time = std::time::Instant::now();
+    let search_run = simulate_search_run(
+        parameters,
+        iterations,
+        state_mapper,
+        FLUSH_INTERVAL,
+        |state_vectors| {
+            let mut store = store.lock().unwrap();
+            store.increment_state_counts(state_vectors)?;
+            store.commit()
+        },
+    )?;
+    let metrics = search_run.metrics();
+    let run_id = parameters.run_id.unwrap();
+    {
+        let mut store = store.lock().unwrap();
+        for (name, value) in &metrics {
+            store.persist_run_metric(run_id, name, *value)?;
+        }
+        store.persist_elapsed_time(run_id, start_time.elapsed().as_secs_f64())?;
+        store.commit()?;
+    }
+    Ok(metrics)
+}
+
+/// Number of completed runs of the search and their average run time, shared by the threads of
+/// the search. Reading the number of completed runs doesn't lock, the average is only locked
+/// briefly.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct SearchProgress {
+    completed_runs: AtomicUsize,
+    average_run_time: Mutex<f64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SearchProgress {
+    /// Returns the number of completed runs and their average run time in seconds.
+    fn snapshot(&self) -> (usize, f64) {
+        let completed_runs = self.completed_runs.load(Ordering::Relaxed);
+        (completed_runs, *self.average_run_time.lock().unwrap())
+    }
+
+    /// Records a completed run with the given run time in seconds and returns the new average.
+    fn record(&self, run_time: f64) -> f64 {
+        let mut average_run_time = self.average_run_time.lock().unwrap();
+        // Incrementing under the lock keeps the count consistent with the running average
+        let completed_runs = self.completed_runs.fetch_add(1, Ordering::Relaxed) + 1;
+        *average_run_time += (run_time - *average_run_time) / completed_runs as f64;
+        *average_run_time
+    }
+}
+
+/// Returns the expected remaining time of the search in seconds from the number of completed
+/// runs. Runs completing in parallel can't push the number of remaining runs below zero.
+#[cfg(not(target_arch = "wasm32"))]
+fn remaining_time_s(average_run_time: f64, completed_runs: usize, total_runs: usize) -> f64 {
+    average_run_time * total_runs.saturating_sub(completed_runs) as f64
+}
+
+/// Formats a duration in seconds as `HH:MM`.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_remaining_time(remaining_time_s: f64) -> String {
+    let minutes = (remaining_time_s / 60.0) as u64;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Creates the progress bar of the search, which is hidden if stdout is not a terminal.
+#[cfg(not(target_arch = "wasm32"))]
+fn create_progress_bar(len: usize) -> ProgressBar {
+    use std::io::IsTerminal;
+
+    if !std::io::stdout().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let progress_bar =
+        ProgressBar::with_draw_target(Some(len as u64), ProgressDrawTarget::stdout());
+    progress_bar.set_style(
+        ProgressStyle::with_template("[{elapsed_precise}] {wide_bar} {pos}/{len} ETA {eta} {msg}")
+            .unwrap(),
+    );
+    progress_bar
+}
+
+/// Outcome of simulating a single parameter point of the search.
+struct SearchRun {
+    /// Number of visits of every state vector over all sampled iterations
+    state_counts: HashMap<StateVector, usize>,
+    max_velocity_fraction: f32,
+    /// Fraction of the particle updates whose velocity was clamped, see
+    /// `Simulation::clamp_events`
+    clamp_rate: f32,
+    /// Total kinetic energy after the last iteration
+    kinetic_energy: f32,
+    /// Total potential energy after the last iteration
+    potential_energy: f32,
+    /// Change of the center of mass velocity between the first and the last iteration
+    center_of_mass_drift: f32,
+    /// Change of the total momentum between the first and the last iteration, which is only
+    /// conserved without friction, velocity clamping and thermal noise
+    momentum_drift: f32,
+    /// Sizes of the particle clusters after the last iteration
+    cluster_sizes: Vec<usize>,
+    /// Number of iterations simulated, less than requested if the run converged early
+    iterations: usize,
+}
+
+impl SearchRun {
+    /// Returns the named scalar metrics of the run that get persisted.
+    fn metrics(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("entropy", normalized_entropy(&self.state_counts)),
+            ("max_velocity_fraction", self.max_velocity_fraction as f64),
+            ("clamp_rate", self.clamp_rate as f64),
+            ("kinetic_energy", self.kinetic_energy as f64),
+            ("potential_energy", self.potential_energy as f64),
+            ("center_of_mass_drift", self.center_of_mass_drift as f64),
+            ("momentum_drift", self.momentum_drift as f64),
+            ("cluster_count", self.cluster_sizes.len() as f64),
+            (
+                "largest_cluster_size",
+                self.cluster_sizes.iter().max().copied().unwrap_or(0) as f64,
+            ),
+            ("iterations", self.iterations as f64),
+        ]
+    }
+}
+
+/// Clamp rate of a search run above which it is reported as saturated
+const CLAMP_RATE_WARNING_THRESHOLD: f32 = 0.05;
+
+/// Number of iterations of a search run whose state vectors are persisted together
+const FLUSH_INTERVAL: usize = 500;
+
+/// Simulates a single parameter point of the search. The sampled state vectors are handed to
+/// `flush` every `flush_interval` iterations and once more after the last iteration. Fails if a
+/// step or a flush fails.
+fn simulate_search_run<M, F>(
+    parameters: &Parameters,
+    iterations: usize,
+    state_mapper: &M,
+    flush_interval: usize,
+    mut flush: F,
+) -> Result<SearchRun, Box<dyn std::error::Error>>
+where
+    M: StateMapper,
+    F: FnMut(&[StateVector]) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let mut simulation = Simulation::new(parameters.clone());
+    let initial_center_of_mass_velocity = center_of_mass_velocity(&simulation.particles);
+    let initial_momentum = simulation.total_momentum();
+
+    let particle_parameters_ids = parameters.particle_parameters_ids();
+    let mut max_velocity_tracker = MaxVelocityTracker::default();
+    let mut state_counts: HashMap<StateVector, usize> = HashMap::new();
+    let mut state_vectors: Vec<StateVector> = vec![];
+    let mut previous_window: Vec<StateVector> = vec![];
+    let mut window: Vec<StateVector> = vec![];
+    let mut simulated_iterations = iterations;
+    for iteration in 0..iterations {
+        simulation.step()?;
+        max_velocity_tracker.record(
+            simulation.particles.len(),
+            simulation.last_step_clamp_events(),
+        );
+        let sampled = iteration % parameters.sample_every.max(1) == 0;
+        if !sampled && parameters.convergence_interval == 0 {
+            continue;
+        }
+        let frame_context =
+            FrameContext::new(parameters, &particle_parameters_ids, &simulation.particles);
+        let iteration_state_vectors = simulation
+            .particles
+            .iter()
+            .map(|p| state_mapper.map(p, &frame_context))
+            .collect::<Vec<_>>();
+        if parameters.convergence_interval > 0 {
+            window.extend(iteration_state_vectors.iter().cloned());
+        }
+        if sampled {
+            for state_vector in &iteration_state_vectors {
+                *state_counts.entry(state_vector.clone()).or_default() += 1;
+            }
+            state_vectors.extend(iteration_state_vectors);
+        }
+        if (iteration + 1) % flush_interval.max(1) == 0 && !state_vectors.is_empty() {
+            flush(&state_vectors)?;
+            state_vectors.clear();
+        }
+
+        // Stop once the states of a window barely differ from the ones of the previous window
+        if parameters.convergence_interval > 0
+            && (iteration + 1) % parameters.convergence_interval == 0
+        {
+            let change = (!previous_window.is_empty())
+                .then(|| state_distribution_change(&previous_window, &window));
+            previous_window = std::mem::take(&mut window);
+            if change.is_some_and(|change| change < parameters.convergence_threshold as f64) {
+                simulated_iterations = iteration + 1;
+                break;
+            }
+        }
+    }
+
+    if !state_vectors.is_empty() {
+        flush(&state_vectors)?;
+    }
+
+    let particle_updates = simulated_iterations * simulation.particles.len();
+    let clamp_rate = simulation.clamp_events() as f32 / particle_updates.max(1) as f32;
+    if clamp_rate > CLAMP_RATE_WARNING_THRESHOLD {
+        log::warn!(
+            "Velocities were clamped in {:.1} % of the particle updates, the results are \
+             dominated by max_velocity. Consider a lower gravity_constant or timestep.",
+            clamp_rate * 100.0
+        );
+    }
+
+    Ok(SearchRun {
+        state_counts,
+        max_velocity_fraction: max_velocity_tracker.fraction(),
+        clamp_rate,
+        kinetic_energy: total_kinetic_energy(&simulation.particles),
+        potential_energy: simulation.potential_energy()?,
+        center_of_mass_drift: (center_of_mass_velocity(&simulation.particles)
+            - initial_center_of_mass_velocity)
+            .magnitude(),
+        momentum_drift: (simulation.total_momentum() - initial_momentum).magnitude(),
+        // Particles closer than the state space resolution count as clustered
+        cluster_sizes: cluster_sizes(&simulation.particles, parameters.position_bucket_size),
+        iterations: simulated_iterations,
+    })
+}
+
+/// Lets the user pick a JSON file and writes the given parameters to it.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_parameters_dialog(parameters: &Parameters) {
+    let Some(path) = rfd::FileDialog::new()
+        .add_filter("json", &["json"])
+        .set_file_name("atomata.json")
+        .save_file()
+    else {
+        return;
+    };
+
+    if let Err(e) = std::fs::write(&path, parameters.to_json()) {
+        log::error!("Can't save config to {}: {}", path.display(), e);
+    }
+}
+
+/// Lets the user pick a JSON file and reads parameters from it.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_parameters_dialog() -> Option<Parameters> {
+    let path = rfd::FileDialog::new()
+        .add_filter("json", &["json"])
+        .pick_file()?;
+
+    let parameters = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| Parameters::from_json(&content));
+    match parameters {
+        Ok(parameters) => Some(parameters),
+        Err(e) => {
+            log::error!("Can't load config from {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Writes the given RGBA pixels as `atomata-<unixtime>.png` into the given directory and
+/// returns the path of the written file.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(
+    pixels: &[[u8; 4]],
+    width: u32,
+    height: u32,
+    directory: &std::path::Path,
+) -> Result<std::path::PathBuf, String> {
+    let unix_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs();
+    let path = directory.join(format!("atomata-{}.png", unix_time));
+    save_png(pixels, width, height, &path)?;
+
+    Ok(path)
+}
+
+/// Writes the given RGBA pixels, given row by row from the top, as PNG.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_png(
+    pixels: &[[u8; 4]],
+    width: u32,
+    height: u32,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    let image = image::RgbaImage::from_raw(width, height, pixels.concat())
+        .ok_or_else(|| "Pixel count doesn't match the viewport".to_string())?;
+    image.save(path).map_err(|e| e.to_string())
+}
+
+/// Mass of the particle kinds added from the GUI
+const NEW_PARTICLE_KIND_MASS: f32 = 100.0;
+
+/// Saturation of the generated particle colors
+const COLOR_SATURATION: f32 = 0.5;
+/// Value (brightness) of the generated particle colors
+const COLOR_VALUE: f32 = 0.95;
+
+/// Generates exactly `num_colors` colors with equal saturation and value whose hues are spread
+/// by the golden ratio, so that consecutive colors are far apart on the color wheel. The seed
+/// picks the starting hue.
+pub fn generate_colors(num_colors: usize, seed: u64) -> Vec<Srgba> {
     let golden_ratio_conjugate = 0.618_034;
-    let mut h = rand::random::<f32>(); // Start with a random hue
+    let mut h = StdRng::seed_from_u64(seed).gen::<f32>(); // Start with a random hue
     let mut colors = Vec::with_capacity(num_colors);
 
     for _ in 0..num_colors {
         h += golden_ratio_conjugate;
         h %= 1.0;
+        colors.push(hsv_to_srgba(h, COLOR_SATURATION, COLOR_VALUE));
+    }
 
-        // HSV to RGB conversion
-        let i = (h * 6.0).floor();
-        let f = h * 6.0 - i;
-        let p = 0.95 * (1.0 - 0.5);
-        let q = 0.95 * (1.0 - f * 0.5);
-        let t = 0.95 * (1.0 - (1.0 - f) * 0.5);
+    colors
+}
 
-        let (r, g, b) = match i as u32 % 6 {
-            0 => (0.95, t, p),
-            1 => (q, 0.95, p),
-            2 => (p, 0.95, t),
-            3 => (p, q, 0.95),
-            4 => (t, p, 0.95),
-            _ => (0.95, p, q),
-        };
+/// Converts a color given by hue, saturation and value, all in the range of 0 to 1, to RGB.
+fn hsv_to_srgba(h: f32, s: f32, v: f32) -> Srgba {
+    let i = (h * 6.0).floor();
+    let f = h * 6.0 - i;
+    // The darkest channel only depends on saturation and value, the hue blends the other two
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - f * s);
+    let t = v * (1.0 - (1.0 - f) * s);
+
+    let (r, g, b) = match i as u32 % 6 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+
+    Srgba::new(
+        (r * 255.0).round() as u8,
+        (g * 255.0).round() as u8,
+        (b * 255.0).round() as u8,
+        255,
+    )
+}
+
+/// Returns a new seed, limited to the range TOML integers can represent so it can be saved
+/// with the config.
+fn random_seed() -> u64 {
+    random_seed_from(&mut rand::thread_rng())
+}
+
+fn random_seed_from<R: Rng + ?Sized>(rng: &mut R) -> u64 {
+    rng.gen_range(0..=i64::MAX as u64)
+}
+
+/// Returns the random number generator of a run, which is seeded from entropy if no seed is
+/// given. The same generator first places the particles and then drives the thermal noise.
+fn seeded_rng(seed: Option<u64>) -> ChaCha12Rng {
+    match seed {
+        Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+        None => ChaCha12Rng::from_entropy(),
+    }
+}
+
+/// Particles of a simulation together with its parameters and the random number generator
+/// driving it. It holds no render state, so it also runs headless, e.g. embedded in another
+/// program.
+pub struct Simulation {
+    pub parameters: Parameters,
+    pub particles: Vec<Particle>,
+    /// Color of each particle kind by its index
+    pub colors: Vec<Srgba>,
+    rng: ChaCha12Rng,
+    clamp_events: usize,
+    last_step_clamp_events: usize,
+}
+
+impl Simulation {
+    /// Creates a headless simulation, seeded by the seed of the parameters if there is one.
+    pub fn new(parameters: Parameters) -> Self {
+        let rng = seeded_rng(parameters.seed);
+        Self::with_rng(parameters, rng)
+    }
+
+    /// Creates a simulation driven by the given random number generator.
+    fn with_rng(parameters: Parameters, mut rng: ChaCha12Rng) -> Self {
+        let colors = kind_colors(&parameters, &mut rng);
+        let particles = create_particles(&parameters, &mut rng);
+        Self {
+            parameters,
+            particles,
+            colors,
+            rng,
+            clamp_events: 0,
+            last_step_clamp_events: 0,
+        }
+    }
+
+    /// Captures the exact state of the simulation, including the position of the random number
+    /// generator in its stream, so a restored simulation draws the same thermal noise as this
+    /// one.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            parameters: self.parameters.clone(),
+            particles: self.particles.iter().map(Into::into).collect(),
+            colors: self
+                .colors
+                .iter()
+                .map(|color| [color.r, color.g, color.b, color.a])
+                .collect(),
+            rng_seed: self.rng.get_seed(),
+            rng_word_pos: self.rng.get_word_pos(),
+        }
+    }
+
+    /// Continues the simulation a snapshot was taken from.
+    pub fn restore(snapshot: Snapshot) -> Self {
+        let mut rng = ChaCha12Rng::from_seed(snapshot.rng_seed);
+        rng.set_word_pos(snapshot.rng_word_pos);
+        Self {
+            particles: snapshot.particles.iter().map(Into::into).collect(),
+            colors: snapshot.colors(),
+            parameters: snapshot.parameters,
+            rng,
+            clamp_events: 0,
+            last_step_clamp_events: 0,
+        }
+    }
+
+    /// Recreates the particles of a single kind from its parameters, e.g. to reseed one color.
+    /// The particles of all other kinds keep their exact state.
+    pub fn reset_kind(&mut self, index: usize) -> Result<(), String> {
+        let particle_params = self
+            .parameters
+            .particle_parameters_by_index(index)
+            .ok_or_else(|| format!("No particle kind with index {}", index))?;
+        reset_particle_kind(
+            &mut self.particles,
+            particle_params,
+            &self.parameters,
+            &mut self.rng,
+        );
+        Ok(())
+    }
+
+    /// Advances all particles by one timestep.
+    pub fn step(&mut self) -> Result<(), String> {
+        self.last_step_clamp_events =
+            update_particles(&mut self.particles, &self.parameters, &mut self.rng)?;
+        self.clamp_events += self.last_step_clamp_events;
+        if self.parameters.recenter {
+            self.recenter();
+        }
+        Ok(())
+    }
+
+    /// Moves into the center of mass frame by subtracting the center of mass velocity of the
+    /// mobile particles from each of them, which zeroes the total momentum. Frozen particles are
+    /// at rest and stay so.
+    fn recenter(&mut self) {
+        let frozen = frozen_kinds(&self.parameters);
+        let (momentum, mass) = self
+            .particles
+            .iter()
+            .filter(|p| !frozen[p.index])
+            .fold((vec3(0.0, 0.0, 0.0), 0.0), |(momentum, mass), p| {
+                (momentum + p.velocity() * p.mass, mass + p.mass)
+            });
+        if mass <= 0.0 {
+            return;
+        }
+        let drift = momentum / mass;
+        for particle in self.particles.iter_mut().filter(|p| !frozen[p.index]) {
+            particle.kick(-drift);
+        }
+    }
+
+    /// Returns how often a particle's velocity had to be clamped to `max_velocity` in a step so
+    /// far, counting every particle at most once per step. A high rate means the velocities are
+    /// mostly dictated by the clamp, i.e. `gravity_constant` or `timestep` is too aggressive.
+    pub fn clamp_events(&self) -> usize {
+        self.clamp_events
+    }
 
-        colors.push(Srgba::new(
-            (r * 255.0) as u8,
-            (g * 255.0) as u8,
-            (b * 255.0) as u8,
-            255,
-        ));
+    /// Returns how many particles had their velocity clamped to `max_velocity` in the last step.
+    pub fn last_step_clamp_events(&self) -> usize {
+        self.last_step_clamp_events
     }
 
+    /// Advances all particles by the given timestep instead of the one of the parameters.
+    pub fn step_by(&mut self, timestep: f32) -> Result<(), String> {
+        let fixed_timestep = std::mem::replace(&mut self.parameters.timestep, timestep);
+        let result = self.step();
+        self.parameters.timestep = fixed_timestep;
+        result
+    }
+
+    /// Returns the sum of the momenta of all particles.
+    pub fn total_momentum(&self) -> Vector3<f32> {
+        analysis::total_momentum(&self.particles)
+    }
+
+    /// Returns the gravitational potential energy of all pairs of particles, which together with
+    /// the kinetic energy gives the total energy of the system.
+    pub fn potential_energy(&self) -> Result<f32, String> {
+        analysis::potential_energy(&self.particles, &self.parameters)
+    }
+
+    /// Returns the current state vectors of all particles. Particle kinds that were not
+    /// persisted yet, i.e. have no id, are identified by their index instead.
+    pub fn state_vectors(
+        &self,
+        position_bucket_size: f32,
+        velocity_bucket_size: f32,
+    ) -> Vec<StateVector> {
+        let particle_parameters_ids = self.parameters.particle_parameters_ids();
+        self.particles
+            .iter()
+            .map(|particle| {
+                let particle_parameters_id = particle_parameters_ids
+                    .get(particle.index)
+                    .copied()
+                    .flatten()
+                    .unwrap_or(particle.index);
+                particle.to_state_vector(
+                    position_bucket_size,
+                    velocity_bucket_size,
+                    particle_parameters_id,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Returns the color of each particle kind by its index, generated unless the kind has one.
+fn kind_colors<R: Rng + ?Sized>(parameters: &Parameters, rng: &mut R) -> Vec<Srgba> {
+    let mut colors = generate_colors(parameters.particle_parameters.len(), rng.gen());
+    for particle_params in parameters.particle_parameters.iter() {
+        if let (Some(color), Some(kind_color)) =
+            (particle_params.color, colors.get_mut(particle_params.index))
+        {
+            *kind_color = color;
+        }
+    }
     colors
 }
 
-fn create_particles(context: Option<&Context>, parameters: &Parameters) -> Vec<Particle> {
+fn create_particles<R: Rng + ?Sized>(parameters: &Parameters, rng: &mut R) -> Vec<Particle> {
     let mut particles: Vec<Particle> = Vec::new();
-    let colors = generate_colors(parameters.particle_parameters.len());
 
-    for (particle_params, color) in parameters.particle_parameters.iter().zip(colors) {
-        let mut particle_kind = initialize_particle_kind(
-            particle_params.index,
-            context,
-            parameters.border,
-            particle_params.mass,
-            color,
-            parameters.amount,
-            parameters.max_velocity,
-        );
+    for particle_params in parameters.particle_parameters.iter() {
+        let mut particle_kind = initialize_particle_kind(particle_params, parameters, rng);
         particles.append(&mut particle_kind);
     }
 
     particles
 }
 
-fn initialize_particle_kind(
-    id: usize,
-    context: Option<&Context>,
-    border: f32,
-    mass: f32,
-    color: Srgba,
-    amount: usize,
-    max_velocity: f32,
+/// Replaces the particles of one kind by newly initialized ones, which take the place of the old
+/// ones so the particles stay grouped by kind like `create_particles` makes them.
+fn reset_particle_kind<R: Rng + ?Sized>(
+    particles: &mut Vec<Particle>,
+    particle_params: &ParticleParameters,
+    parameters: &Parameters,
+    rng: &mut R,
+) {
+    let start = particles
+        .iter()
+        .position(|p| p.index == particle_params.index)
+        .unwrap_or(particles.len());
+    particles.retain(|p| p.index != particle_params.index);
+    let new_particles = initialize_particle_kind(particle_params, parameters, rng);
+    particles.splice(start..start, new_particles);
+}
+
+fn initialize_particle_kind<R: Rng + ?Sized>(
+    particle_params: &ParticleParameters,
+    parameters: &Parameters,
+    rng: &mut R,
 ) -> Vec<Particle> {
     let mut particles = Vec::new();
-    for _ in 0..amount {
-        let positionable: Option<Box<dyn PositionableRender>> = match context {
-            Some(context) => {
-                let sphere = Sphere::new(context, color);
-                Some(Box::new(sphere) as Box<dyn PositionableRender>)
-            }
-            None => None,
-        };
-        particles.push(Particle::new(id, positionable, border, mass, max_velocity));
+    for i in 0..parameters.amount {
+        let position = Particle::initial_position(
+            &particle_params.placement,
+            i,
+            parameters.amount,
+            parameters.border,
+            rng,
+        );
+        let mut particle = Particle::new(
+            particle_params.index,
+            position,
+            &particle_params.velocity_init,
+            particle_params.sample_mass(rng),
+            parameters.max_velocity,
+            rng,
+        );
+        // The initial velocity is still drawn, so freezing a kind doesn't change the others
+        if particle_params.frozen {
+            particle.stop();
+        }
+        particles.push(particle);
     }
     particles
 }
 
-fn update_particles(particles: &mut [Particle], parameters: &Parameters) -> Result<(), String> {
+/// Advances the particles by one timestep and returns the number of particles whose velocity was
+/// clamped to `max_velocity` on the way.
+fn update_particles<R: Rng + ?Sized>(
+    particles: &mut [Particle],
+    parameters: &Parameters,
+    rng: &mut R,
+) -> Result<usize, String> {
     let id_clones = particles.iter().map(|p| p.index).collect::<Vec<_>>();
-    let postion_clones = particles.iter().map(|p| p.position).collect::<Vec<_>>();
+    let position_clones = particles.iter().map(|p| p.position).collect::<Vec<_>>();
     let mass_clones = particles.iter().map(|p| p.mass).collect::<Vec<_>>();
     let len = particles.len();
-    for (i, particle) in particles.iter_mut().enumerate() {
+    let frozen = frozen_kinds(parameters);
+    // Other particles are only read from the clones, so the velocities can be accumulated in
+    // parallel without changing the result
+    let accumulate_velocity = |(i, particle): (usize, &mut Particle)| {
+        if frozen[particle.index] {
+            return Ok(0);
+        }
+        let mut clamped = false;
         for j in 0..len {
             if i == j {
                 continue;
             }
             let interaction_type =
                 parameters.interaction_by_indices(particle.index, id_clones[j])?;
-            particle.update_velocity(
-                postion_clones[j],
+            clamped |= particle.update_velocity(
+                nearest_image(particle.position, position_clones[j], parameters),
                 mass_clones[j],
                 interaction_type,
-                parameters.gravity_constant,
+                parameters,
             );
-            particle.apply_friction(parameters.friction);
-            particle.update_position(parameters);
         }
-    }
-
-    Ok(())
+        Ok(clamped as usize)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let clamp_events = particles
+        .par_iter_mut()
+        .enumerate()
+        .map(accumulate_velocity)
+        .sum::<Result<usize, String>>()?;
+    #[cfg(target_arch = "wasm32")]
+    let clamp_events = particles
+        .iter_mut()
+        .enumerate()
+        .map(accumulate_velocity)
+        .sum::<Result<usize, String>>()?;
+
+    // Sequential, so the thermal kicks are drawn in the same order every run
+    for particle in particles.iter_mut() {
+        // Frozen particles still act on the others through the clones above. They are at rest,
+        // even if their kind was only frozen since the last step.
+        if frozen[particle.index] {
+            particle.stop();
+            continue;
+        }
+        if parameters.temperature > 0.0 {
+            particle.kick(thermal_kick(parameters, rng));
+        }
+        // Integrate once per step after the velocity has been accumulated over all pairs
+        particle.apply_friction(parameters.friction);
+        particle.update_position(parameters);
+    }
+
+    resolve_collisions(particles, parameters);
+
+    Ok(clamp_events)
+}
+
+/// Returns whether each particle kind is frozen by its index.
+fn frozen_kinds(parameters: &Parameters) -> Vec<bool> {
+    let mut frozen = vec![false; parameters.particle_parameters.len()];
+    for particle_params in &parameters.particle_parameters {
+        frozen[particle_params.index] = particle_params.frozen;
+    }
+    frozen
+}
+
+/// Lets every pair of overlapping particles whose kinds both have a collision radius collide
+/// elastically. Frozen particles are immovable, mobile ones bounce off them. Does nothing unless
+/// some kind collides.
+fn resolve_collisions(particles: &mut [Particle], parameters: &Parameters) {
+    let mut collision_radii = vec![None; parameters.particle_parameters.len()];
+    for particle_params in &parameters.particle_parameters {
+        collision_radii[particle_params.index] = particle_params.collision_radius;
+    }
+    if collision_radii.iter().all(Option::is_none) {
+        return;
+    }
+    let frozen = frozen_kinds(parameters);
+
+    for i in 0..particles.len() {
+        let (head, tail) = particles.split_at_mut(i + 1);
+        let particle = &mut head[i];
+        let Some(radius) = collision_radii[particle.index] else {
+            continue;
+        };
+        for other in tail.iter_mut() {
+            let Some(other_radius) = collision_radii[other.index] else {
+                continue;
+            };
+            let other_position = nearest_image(particle.position, other.position, parameters);
+            if (other_position - particle.position).magnitude() >= radius + other_radius {
+                continue;
+            }
+            match (frozen[particle.index], frozen[other.index]) {
+                (false, false) => particle.collide(other, other_position),
+                (false, true) => particle.bounce_off(other_position),
+                // The frozen particle as seen from the other one
+                (true, false) => {
+                    other.bounce_off(other.position + particle.position - other_position)
+                }
+                (true, true) => {}
+            }
+        }
+    }
+}
+
+/// Returns a random velocity change with a standard deviation of `sqrt(temperature * timestep)`
+/// per axis.
+fn thermal_kick<R: Rng + ?Sized>(parameters: &Parameters, rng: &mut R) -> Vector3<f32> {
+    let scale = (parameters.temperature * parameters.timestep).sqrt();
+    vec3(
+        rng.sample::<f32, _>(StandardNormal),
+        rng.sample::<f32, _>(StandardNormal),
+        rng.sample::<f32, _>(StandardNormal),
+    ) * scale
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use parameters::MassDistribution;
+    use persistence::{increment_state_counts, persist_parameters, TransactionProvider};
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_cli_parameters() {
+        let cli = Cli::from_args(
+            &["atomata"],
+            &[
+                "--amount",
+                "42",
+                "--gravity",
+                "2.5",
+                "--max-velocity",
+                "100",
+                "--seed",
+                "7",
+            ],
+        )
+        .unwrap();
+
+        let parameters = cli.parameters().unwrap();
+
+        assert_eq!(
+            parameters,
+            Parameters {
+                amount: 42,
+                gravity_constant: 2.5,
+                max_velocity: 100.0,
+                seed: Some(7),
+                ..Parameters::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_cli_sweep_spec() {
+        let path = std::env::temp_dir().join(format!("atomata-spec-{}.json", std::process::id()));
+        let spec = SweepSpec {
+            amounts: vec![5],
+            ..SweepSpec::default()
+        };
+        std::fs::write(&path, spec.to_json()).unwrap();
+        let path = path.to_str().unwrap();
+
+        let cli = Cli::from_args(&["atomata"], &["--search-spec", path]).unwrap();
+        assert_eq!(cli.sweep_spec(), Ok(spec));
+
+        let cli = Cli::from_args(&["atomata"], &[]).unwrap();
+        assert_eq!(cli.sweep_spec(), Ok(SweepSpec::default()));
+
+        let cli = Cli::from_args(
+            &["atomata"],
+            &["--search-spec", path, "--append-sweep", path],
+        )
+        .unwrap();
+        assert!(cli.sweep_spec().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_does_not_touch_the_database() {
+        let path = std::env::temp_dir().join(format!("atomata-dry-run-{}.db3", std::process::id()));
+        let cli = Cli::from_args(
+            &["atomata"],
+            &["--search", "--dry-run", "--db", path.to_str().unwrap()],
+        )
+        .unwrap();
+
+        run_search(&cli, &cli.parameters().unwrap(), &CartesianMapper).unwrap();
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_format_dry_run() {
+        let spec = SweepSpec {
+            amounts: vec![2],
+            frictions: vec![0.01, 0.0],
+            ..SweepSpec::default()
+        };
+        let output = format_dry_run(
+            Some(&spec),
+            Parameters::parameter_space_from_spec(&spec, &Parameters::default()),
+        );
+
+        assert!(output.starts_with(&format!("Parameter points: {}\n", spec.size())));
+        assert!(output.contains("frictions: 0 to 0.01 (2 values)\n"));
+        assert_eq!(output.matches("First: ").count(), 1);
+        assert_eq!(output.matches("Last: ").count(), 1);
+    }
+
+    #[test]
+    fn test_small_iteration_count_persists_state_vectors() {
+        let cli = Cli::from_args(&["atomata"], &["--iterations", "3", "--db", ":memory:"]).unwrap();
+        let mut connection_provider =
+            open_and_migrate(&cli.db, &PersistenceOptions::default()).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters {
+            amount: 2,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+
+        simulate_search_run(
+            &parameters,
+            cli.iterations,
+            &CartesianMapper,
+            FLUSH_INTERVAL,
+            |state_vectors| increment_state_counts(state_vectors, &tx_provider),
+        )
+        .unwrap();
+
+        let num_particles = parameters.amount * parameters.particle_parameters.len();
+        let count: usize = tx_provider
+            .prepare("SELECT sum(count) FROM state_vectors;")
+            .unwrap()
+            .query_row([], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, cli.iterations * num_particles);
+    }
+
+    #[test]
+    fn test_seed_reproduces_initial_state() {
+        let parameters = Parameters {
+            seed: Some(42),
+            ..Parameters::default()
+        };
+
+        let first = create_particles(&parameters, &mut seeded_rng(parameters.seed));
+        let second = create_particles(&parameters, &mut seeded_rng(parameters.seed));
+
+        let state = |particles: &[Particle]| {
+            particles
+                .iter()
+                .map(|p| (p.index, p.position, p.velocity()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(state(&first), state(&second));
+    }
+
+    #[test]
+    fn test_reset_kind_keeps_other_kinds() {
+        let mut simulation = Simulation::new(Parameters {
+            amount: 5,
+            seed: Some(3),
+            ..Parameters::default()
+        });
+        for _ in 0..10 {
+            simulation.step().unwrap();
+        }
+        let states = |simulation: &Simulation| {
+            simulation
+                .particles
+                .iter()
+                .map(snapshot::ParticleSnapshot::from)
+                .collect::<Vec<_>>()
+        };
+        let before = states(&simulation);
+
+        simulation.reset_kind(1).unwrap();
+
+        let after = states(&simulation);
+        assert_eq!(after.len(), before.len());
+        for (old, new) in before.iter().zip(&after) {
+            // The reset kind stays in its place between the other kinds
+            assert_eq!(new.index, old.index);
+            if old.index == 1 {
+                assert_ne!(new.position, old.position);
+            } else {
+                assert_eq!(new, old);
+            }
+        }
+        assert_eq!(
+            simulation.reset_kind(3),
+            Err("No particle kind with index 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sampled_masses_fall_within_their_distribution() {
+        let mut parameters = Parameters {
+            amount: 200,
+            seed: Some(5),
+            ..Parameters::default()
+        };
+        parameters.particle_parameters[0].mass_distribution =
+            Some(MassDistribution::Uniform { min: 2.0, max: 5.0 });
+        parameters.particle_parameters[1].mass_distribution = Some(MassDistribution::Normal {
+            mean: 1.0,
+            std: 2.0,
+        });
+        assert_eq!(parameters.validate(), Ok(()));
+
+        let particles = create_particles(&parameters, &mut seeded_rng(parameters.seed));
+        let masses = |index| {
+            particles
+                .iter()
+                .filter(move |p| p.index == index)
+                .map(|p| p.mass)
+        };
+
+        assert!(masses(0).all(|mass| (2.0..=5.0).contains(&mass)));
+        assert!(masses(0).any(|mass| mass != masses(0).next().unwrap()));
+        assert!(masses(1).all(|mass| mass > 0.0));
+        let kind_2_mass = parameters.particle_parameters[2].mass;
+        assert!(masses(2).all(|mass| mass == kind_2_mass));
+    }
+
+    #[test]
+    fn test_restored_snapshot_continues_like_the_simulation() {
+        let parameters = Parameters {
+            amount: 4,
+            temperature: 1.0e6,
+            seed: Some(9),
+            ..Parameters::default()
+        };
+        let state = |simulation: &Simulation| {
+            simulation
+                .particles
+                .iter()
+                .map(|p| (p.index, p.position, p.velocity()))
+                .collect::<Vec<_>>()
+        };
+        let mut simulation = Simulation::new(parameters.clone());
+        let mut never_snapshotted = Simulation::new(parameters);
+        for _ in 0..5 {
+            simulation.step().unwrap();
+            never_snapshotted.step().unwrap();
+        }
+
+        let snapshot = simulation.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut restored = Simulation::restore(serde_json::from_str(&json).unwrap());
+        for _ in 0..5 {
+            simulation.step().unwrap();
+            restored.step().unwrap();
+            never_snapshotted.step().unwrap();
+        }
+
+        // Taking the snapshot doesn't change the trajectory
+        assert_eq!(state(&simulation), state(&never_snapshotted));
+        assert_eq!(state(&restored), state(&never_snapshotted));
+        assert_eq!(restored.colors, never_snapshotted.colors);
+    }
+
+    #[test]
+    fn test_only_colliding_kinds_collide() {
+        let mut parameters = Parameters::default();
+        parameters.particle_parameters[0].collision_radius = Some(1.0);
+        parameters.particle_parameters[1].collision_radius = Some(1.0);
+        let particle = |index, x: f32, vx: f32| {
+            Particle::from_state(index, vec3(x, 0.0, 0.0), vec3(vx, 0.0, 0.0), 1.0, 1000.0)
+        };
+        let mut particles = vec![
+            particle(0, 0.0, 10.0),
+            particle(1, 1.5, -10.0),
+            particle(2, 0.5, -10.0),
+        ];
+
+        resolve_collisions(&mut particles, &parameters);
+
+        assert_eq!(particles[0].velocity(), vec3(-10.0, 0.0, 0.0));
+        assert_eq!(particles[1].velocity(), vec3(10.0, 0.0, 0.0));
+        assert_eq!(particles[2].velocity(), vec3(-10.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_max_velocity_tracker_detects_saturation_with_friction() {
+        for velocity_clamp in [VelocityClamp::PerAxis, VelocityClamp::Magnitude] {
+            let mut simulation = Simulation::new(Parameters {
+                amount: 5,
+                seed: Some(1),
+                gravity_constant: 1.0e9,
+                friction: 0.005,
+                velocity_clamp,
+                ..Parameters::default()
+            });
+            let mut tracker = MaxVelocityTracker::default();
+
+            for _ in 0..20 {
+                simulation.step().unwrap();
+                tracker.record(
+                    simulation.particles.len(),
+                    simulation.last_step_clamp_events(),
+                );
+            }
+
+            // Friction moves the clamped velocities below the cap before the step ends, so the
+            // clamp can't be read off the velocities afterwards
+            assert!(simulation
+                .particles
+                .iter()
+                .all(|p| p.velocity().x.abs() < p.max_velocity()));
+            assert!(tracker.fraction() > 0.5, "{:?}", velocity_clamp);
+        }
+    }
+
+    #[test]
+    fn test_extreme_gravity_counts_clamp_events() {
+        let parameters = Parameters {
+            amount: 5,
+            seed: Some(1),
+            ..Parameters::default()
+        };
+        let mut calm = Simulation::new(Parameters {
+            gravity_constant: 0.0,
+            ..parameters.clone()
+        });
+        let mut extreme = Simulation::new(Parameters {
+            gravity_constant: 1.0e9,
+            ..parameters
+        });
+
+        for _ in 0..3 {
+            calm.step().unwrap();
+            extreme.step().unwrap();
+        }
+
+        assert_eq!(calm.clamp_events(), 0);
+        assert!(extreme.clamp_events() > 0);
+        assert!(extreme.clamp_events() <= 3 * extreme.particles.len());
+    }
+
+    #[test]
+    fn test_fixed_time_mode_is_frame_rate_independent() {
+        for elapsed_time in [0.0, 5.0, REFERENCE_FRAME_TIME, 40.0, MAX_FRAME_TIME, 1000.0] {
+            assert_eq!(TimeMode::Fixed.timestep(0.0002, elapsed_time), 0.0002);
+        }
+
+        let parameters = Parameters {
+            amount: 5,
+            seed: Some(3),
+            ..Parameters::default()
+        };
+        let run = |frame_times: &[f64]| {
+            let mut simulation = Simulation::new(parameters.clone());
+            for &elapsed_time in frame_times.iter().cycle().take(20) {
+                let timestep = TimeMode::Fixed.timestep(parameters.timestep, elapsed_time);
+                simulation.step_by(timestep).unwrap();
+            }
+            assert_eq!(simulation.parameters.timestep, parameters.timestep);
+            simulation
+                .particles
+                .iter()
+                .map(|p| p.position)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(&[REFERENCE_FRAME_TIME]), run(&[5.0, 40.0, 1000.0]));
+    }
+
+    #[test]
+    fn test_real_time_mode_scales_with_frame_time() {
+        let timestep = 0.0002;
+
+        assert_eq!(
+            TimeMode::RealTime.timestep(timestep, REFERENCE_FRAME_TIME),
+            timestep
+        );
+        assert_eq!(
+            TimeMode::RealTime.timestep(timestep, 2.0 * REFERENCE_FRAME_TIME),
+            2.0 * timestep
+        );
+        assert_eq!(
+            TimeMode::RealTime.timestep(timestep, 10.0 * MAX_FRAME_TIME),
+            TimeMode::RealTime.timestep(timestep, MAX_FRAME_TIME)
+        );
+    }
+
+    #[test]
+    fn test_update_particles_integrates_once_per_step() {
+        let parameters = Parameters {
+            friction: 0.0,
+            timestep: 0.5,
+            ..Parameters::default()
+        };
+        // Kind 2 is neutral towards itself, so the velocities stay constant
+        let mut particles = (0..3)
+            .map(|i| {
+                Particle::from_state(
+                    2,
+                    vec3(i as f32 * 10.0, 0.0, 0.0),
+                    vec3(1.0, 2.0, -4.0),
+                    1.0,
+                    parameters.max_velocity,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        update_particles(&mut particles, &parameters, &mut seeded_rng(Some(0))).unwrap();
+
+        assert_eq!(
+            particles.iter().map(|p| p.position).collect::<Vec<_>>(),
+            vec![
+                vec3(0.5, 1.0, -2.0),
+                vec3(10.5, 1.0, -2.0),
+                vec3(20.5, 1.0, -2.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parallel_update_particles_matches_sequential() {
+        let simulate = |num_threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let mut simulation = Simulation::new(Parameters {
+                    amount: 20,
+                    temperature: 50.0,
+                    seed: Some(5),
+                    ..Parameters::default()
+                });
+                for _ in 0..50 {
+                    simulation.step().unwrap();
+                }
+                simulation
+                    .particles
+                    .iter()
+                    .map(snapshot::ParticleSnapshot::from)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // A single thread runs the parallel iterator in order
+        assert_eq!(simulate(4), simulate(1));
+    }
+
+    /// Returns the variance of the x coordinate of an initially resting particle after some
+    /// iterations, taken over several noise seeds.
+    fn position_variance(temperature: f32) -> f32 {
+        let parameters = Parameters {
+            temperature,
+            friction: 0.0,
+            border: 1000.0,
+            ..Parameters::default()
+        };
+        let positions = (0..50)
+            .map(|seed| {
+                // Kind 2 is neutral towards itself, so only the noise moves the particles
+                let mut particles = [0.0, 500.0]
+                    .into_iter()
+                    .map(|y| {
+                        Particle::from_state(
+                            2,
+                            vec3(0.0, y, 0.0),
+                            vec3(0.0, 0.0, 0.0),
+                            1.0,
+                            parameters.max_velocity,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let mut rng = StdRng::seed_from_u64(seed);
+                for _ in 0..100 {
+                    update_particles(&mut particles, &parameters, &mut rng).unwrap();
+                }
+                particles[0].position.x
+            })
+            .collect::<Vec<_>>();
+
+        let mean = positions.iter().sum::<f32>() / positions.len() as f32;
+        positions.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / positions.len() as f32
+    }
+
+    #[test]
+    fn test_temperature_increases_position_variance() {
+        assert_eq!(position_variance(0.0), 0.0);
+        assert!(position_variance(100.0) > 0.0);
+        assert!(position_variance(10000.0) > position_variance(100.0));
+    }
+
+    #[test]
+    fn test_generate_colors_pairwise_distinct() {
+        for seed in 0..20 {
+            for num_colors in 1..=12 {
+                let colors = generate_colors(num_colors, seed);
+
+                assert_eq!(colors.len(), num_colors);
+                for (i, color) in colors.iter().enumerate() {
+                    assert!(
+                        !colors[i + 1..].contains(color),
+                        "{:?} repeats for {} colors with seed {}",
+                        color,
+                        num_colors,
+                        seed
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_colors_seed() {
+        assert_eq!(generate_colors(5, 42), generate_colors(5, 42));
+        assert_ne!(generate_colors(5, 42), generate_colors(5, 43));
+    }
+
+    #[test]
+    fn test_generate_colors_length() {
+        for num_colors in [0, 1, 3, 100] {
+            assert_eq!(generate_colors(num_colors, 7).len(), num_colors);
+        }
+    }
+
+    #[test]
+    fn test_hsv_to_srgba() {
+        assert_eq!(hsv_to_srgba(0.0, 1.0, 1.0), Srgba::new(255, 0, 0, 255));
+        assert_eq!(
+            hsv_to_srgba(1.0 / 3.0, 1.0, 1.0),
+            Srgba::new(0, 255, 0, 255)
+        );
+        assert_eq!(hsv_to_srgba(0.5, 0.5, 1.0), Srgba::new(128, 255, 255, 255));
+    }
+
+    #[test]
+    fn test_format_run_table() {
+        let run = RunSummary {
+            run_id: 12,
+            amount: 100,
+            border: 400.0,
+            timestep: 0.0002,
+            gravity_constant: 1.0,
+            friction: 0.005,
+            max_velocity: 20000.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 1000.0,
+            created_at: "2024-05-01 12:00:00".to_string(),
+            state_vector_rows: 4711,
+        };
+
+        assert_eq!(
+            format_run_table(&[run]),
+            concat!(
+                "run_id  amount  border  timestep  gravity  friction  max_velocity  pos_bucket  ",
+                "vel_bucket           created_at  rows\n",
+                "    12     100     400    0.0002        1     0.005         20000          10  ",
+                "      1000  2024-05-01 12:00:00  4711\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_ranked_run_table() {
+        let run = RunSummary {
+            run_id: 3,
+            amount: 10,
+            border: 200.0,
+            timestep: 0.0002,
+            gravity_constant: 1.0,
+            friction: 0.0,
+            max_velocity: 100.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 10.0,
+            created_at: "2024-05-01 12:00:00".to_string(),
+            state_vector_rows: 7,
+        };
+
+        assert_eq!(
+            format_ranked_run_table("entropy", &[(run, 1.25)]),
+            concat!(
+                "entropy  run_id  amount  border  timestep  gravity  friction  max_velocity  ",
+                "pos_bucket  vel_bucket           created_at  rows\n",
+                "   1.25       3      10     200    0.0002        1         0           100  ",
+                "        10          10  2024-05-01 12:00:00     7\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_create_window_failure_suggests_headless_modes() {
+        let result = create_window(|| Err::<(), _>("no display"));
+
+        let message = result.unwrap_err();
+        assert!(message.starts_with("Can't open a window: no display."));
+        for option in ["--search", "--run-once", "--render-frames"] {
+            assert!(
+                message.contains(option),
+                "{} missing in {}",
+                option,
+                message
+            );
+        }
+        assert_eq!(create_window(|| Ok::<_, String>(42)), Ok(42));
+    }
+
+    #[test]
+    fn test_render_settings_clear_color() {
+        let render_settings = RenderSettings::default();
+        assert_eq!(render_settings.clear_color(), [0.8, 0.8, 0.8, 1.0]);
+
+        let render_settings = RenderSettings {
+            background: Srgba::new_opaque(0, 255, 51),
+            ..render_settings
+        };
+        assert_eq!(render_settings.clear_color(), [0.0, 1.0, 0.2, 1.0]);
+    }
+
+    #[test]
+    fn test_camera_distance() {
+        // sin(30°) = 0.5
+        assert!((camera_distance(100.0, 60.0) - 200.0).abs() < 1e-3);
+        assert!(camera_distance(2000.0, 45.0) > camera_distance(200.0, 45.0));
+        assert!(camera_distance(200.0, 30.0) > camera_distance(200.0, 90.0));
+    }
+
+    #[test]
+    fn test_orthographic_height() {
+        // tan(45°) = 1
+        assert!((orthographic_height(100.0, 90.0) - 200.0).abs() < 1e-3);
+        // The orthographic view of a fitted camera contains the whole sphere
+        let distance = camera_distance(50.0, 45.0);
+        assert!(orthographic_height(distance, 45.0) >= 100.0);
+    }
+
+    #[test]
+    fn test_bounding_sphere() {
+        let positions = [
+            vec3(-10.0, 2.0, 0.0),
+            vec3(10.0, 2.0, 0.0),
+            vec3(0.0, 2.0, 5.0),
+            vec3(0.0, 2.0, -5.0),
+        ];
+
+        let (center, radius) = bounding_sphere(&positions);
+
+        assert_eq!(center, vec3(0.0, 2.0, 0.0));
+        assert_eq!(radius, 11.0);
+    }
+
+    #[test]
+    fn test_search_progress_from_many_threads() {
+        let progress = SearchProgress::default();
+        let threads = 16;
+        let runs_per_thread = 1000;
+
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                let progress = &progress;
+                scope.spawn(move || {
+                    for _ in 0..runs_per_thread {
+                        progress.record(thread as f64);
+                        progress.snapshot();
+                    }
+                });
+            }
+        });
+
+        let (completed_runs, average_run_time) = progress.snapshot();
+        assert_eq!(completed_runs, threads * runs_per_thread);
+        // Every thread contributes the same number of runs, so the average is the mean thread
+        assert!((average_run_time - (threads - 1) as f64 / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_remaining_time() {
+        assert_eq!(remaining_time_s(2.0, 0, 10), 20.0);
+        assert_eq!(remaining_time_s(2.0, 4, 10), 12.0);
+        assert_eq!(remaining_time_s(2.0, 10, 10), 0.0);
+        // More completions than runs must not underflow
+        assert_eq!(remaining_time_s(2.0, 11, 10), 0.0);
+        assert_eq!(remaining_time_s(0.0, 0, 10), 0.0);
+    }
+
+    #[test]
+    fn test_format_remaining_time() {
+        assert_eq!(format_remaining_time(0.0), "00:00");
+        assert_eq!(format_remaining_time(59.0), "00:00");
+        assert_eq!(
+            format_remaining_time(3.0 * 3600.0 + 7.0 * 60.0 + 30.0),
+            "03:07"
+        );
+        assert_eq!(format_remaining_time(100.0 * 3600.0), "100:00");
+    }
+
+    #[test]
+    fn test_smooth_fps() {
+        let fps = smooth_fps(0.0, 20.0);
+        assert_eq!(fps, 50.0);
+
+        let fps = smooth_fps(fps, 10.0);
+        assert_eq!(fps, 52.5);
+
+        assert_eq!(smooth_fps(fps, 0.0), fps);
+    }
+
+    #[test]
+    fn test_save_screenshot() {
+        let directory = std::env::temp_dir();
+        let pixels = vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [0; 4]];
+
+        let path = save_screenshot(&pixels, 2, 2, &directory).unwrap();
+
+        let image = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_simulation_steps_headless() {
+        let parameters = Parameters {
+            amount: 3,
+            seed: Some(9),
+            ..Parameters::default()
+        };
+        let mut simulation = Simulation::new(parameters.clone());
+        let initial_state_vectors = simulation.state_vectors(1.0, 1.0);
+
+        for _ in 0..10 {
+            simulation.step().unwrap();
+        }
+        let state_vectors = simulation.state_vectors(1.0, 1.0);
+
+        assert_eq!(
+            state_vectors.len(),
+            parameters.amount * parameters.particle_parameters.len()
+        );
+        assert!(state_vectors
+            .iter()
+            .all(|state_vector| state_vector.particle_parameters_id < 3));
+        assert_ne!(state_vectors, initial_state_vectors);
+
+        // The same seed reproduces the same trajectory
+        let mut other_simulation = Simulation::new(parameters);
+        for _ in 0..10 {
+            other_simulation.step().unwrap();
+        }
+        assert_eq!(other_simulation.state_vectors(1.0, 1.0), state_vectors);
+    }
+
+    #[test]
+    fn test_two_body_attraction_conserves_momentum() {
+        let parameters = Parameters {
+            friction: 0.0,
+            max_velocity: f32::MAX,
+            ..Parameters::default()
+        };
+        let mut simulation = Simulation::new(parameters);
+        // Kinds 0 and 1 attract each other
+        simulation.particles = vec![
+            Particle::from_state(0, vec3(-10.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 3.0, f32::MAX),
+            Particle::from_state(
+                1,
+                vec3(10.0, 0.0, 0.0),
+                vec3(0.0, -1.0, 0.0),
+                250.0,
+                f32::MAX,
+            ),
+        ];
+        let initial_momentum = simulation.total_momentum();
+
+        for _ in 0..100 {
+            let momentum = simulation.total_momentum();
+            simulation.step().unwrap();
+            assert!((simulation.total_momentum() - momentum).magnitude() < 1e-3);
+        }
+        assert!(simulation.particles[0].velocity().x > 0.0);
+        assert!((simulation.total_momentum() - initial_momentum).magnitude() < 1e-2);
+    }
+
+    #[test]
+    fn test_frozen_kind_stays_in_place_but_exerts_forces() {
+        let mut parameters = Parameters::default();
+        parameters.particle_parameters[1].frozen = true;
+        let mut simulation = Simulation::new(parameters);
+        // Kinds 0 and 1 attract each other
+        let frozen_position = vec3(10.0, 0.0, 0.0);
+        simulation.particles = vec![
+            Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 3.0, 20000.0),
+            Particle::from_state(1, frozen_position, vec3(0.0, 0.0, 0.0), 250.0, 20000.0),
+        ];
+
+        for _ in 0..20 {
+            simulation.step().unwrap();
+            assert_eq!(simulation.particles[1].position, frozen_position);
+        }
+        assert!(simulation.particles[0].velocity().x > 0.0);
+        assert!(simulation.particles[0].position.x > 0.0);
+    }
+
+    #[test]
+    fn test_recenter_keeps_frozen_kind_at_rest() {
+        let mut parameters = Parameters {
+            amount: 5,
+            recenter: true,
+            seed: Some(8),
+            ..Parameters::default()
+        };
+        parameters.particle_parameters[1].frozen = true;
+        let mut simulation = Simulation::new(parameters);
+        let frozen_positions = |simulation: &Simulation| {
+            simulation
+                .particles
+                .iter()
+                .filter(|p| p.index == 1)
+                .map(|p| p.position)
+                .collect::<Vec<_>>()
+        };
+        let initial_frozen_positions = frozen_positions(&simulation);
+        simulation.particles[0].kick(vec3(500.0, -200.0, 100.0));
+
+        for _ in 0..10 {
+            simulation.step().unwrap();
+            assert_eq!(frozen_positions(&simulation), initial_frozen_positions);
+            for particle in simulation.particles.iter().filter(|p| p.index == 1) {
+                assert_eq!(particle.velocity(), vec3(0.0, 0.0, 0.0));
+            }
+            let momentum = simulation.total_momentum();
+            let scale = simulation
+                .particles
+                .iter()
+                .map(|p| p.mass * p.velocity().magnitude())
+                .sum::<f32>();
+            assert!(momentum.magnitude() <= 1e-5 * scale, "{:?}", momentum);
+        }
+    }
+
+    #[test]
+    fn test_frozen_particles_are_immovable_in_collisions() {
+        let mut parameters = Parameters::default();
+        parameters.particle_parameters[0].collision_radius = Some(1.0);
+        parameters.particle_parameters[1].collision_radius = Some(1.0);
+        parameters.particle_parameters[1].frozen = true;
+        let particle = |index, x: f32, vx: f32| {
+            Particle::from_state(index, vec3(x, 0.0, 0.0), vec3(vx, 0.0, 0.0), 1.0, 1000.0)
+        };
+        let mut particles = vec![particle(1, 0.0, 0.0), particle(0, 1.5, -10.0)];
+
+        resolve_collisions(&mut particles, &parameters);
+
+        assert_eq!(particles[0].velocity(), vec3(0.0, 0.0, 0.0));
+        assert_eq!(particles[1].velocity(), vec3(10.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_recenter_zeroes_momentum() {
+        let mut simulation = Simulation::new(Parameters {
+            amount: 5,
+            recenter: true,
+            seed: Some(8),
+            ..Parameters::default()
+        });
+        simulation.particles[0].kick(vec3(500.0, -200.0, 100.0));
+        assert!(simulation.total_momentum().magnitude() > 1.0);
+
+        for _ in 0..10 {
+            simulation.step().unwrap();
+            let momentum = simulation.total_momentum();
+            let scale = simulation
+                .particles
+                .iter()
+                .map(|p| p.mass * p.velocity().magnitude())
+                .sum::<f32>();
+            assert!(momentum.magnitude() <= 1e-5 * scale, "{:?}", momentum);
+        }
+    }
+
+    #[test]
+    fn test_static_search_run_stops_early() {
+        // Without gravity and initial velocities nothing ever moves
+        let mut parameters = Parameters {
+            amount: 2,
+            gravity_constant: 0.0,
+            max_velocity: 0.0,
+            convergence_interval: 5,
+            seed: Some(1),
+            ..Parameters::default()
+        };
+        MemoryResultStore::default()
+            .persist_parameters(&mut parameters)
+            .unwrap();
+
+        let search_run =
+            simulate_search_run(&parameters, 10000, &CartesianMapper, FLUSH_INTERVAL, |_| {
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(search_run.iterations, 10);
+        assert_eq!(
+            search_run.state_counts.values().sum::<usize>(),
+            10 * parameters.amount * parameters.particle_parameters.len()
+        );
+
+        let without_early_stopping = Parameters {
+            convergence_interval: 0,
+            ..parameters
+        };
+        let search_run = simulate_search_run(
+            &without_early_stopping,
+            20,
+            &CartesianMapper,
+            FLUSH_INTERVAL,
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(search_run.iterations, 20);
+    }
+
+    #[test]
+    fn test_search_with_memory_store() {
+        let base = Parameters {
+            amount: 2,
+            ..Parameters::default()
+        };
+        let spec = SweepSpec {
+            amounts: vec![2],
+            frictions: vec![0.0, 0.01],
+            borders: vec![200.0],
+            timesteps: vec![0.0002],
+            gravity_constants: vec![1.0],
+            force_exponents: vec![2.0],
+            attraction_constants: vec![1.0],
+            repulsion_constants: vec![1.0],
+            max_velocities: vec![20000.0],
+            position_bucket_sizes: vec![10.0],
+            velocity_bucket_sizes: vec![10.0],
+        };
+        let parameter_space = Parameters::parameter_space_from_spec(&spec, &base);
+        let iterations = 3;
+
+        let store = search(
+            parameter_space,
+            iterations,
+            &CartesianMapper,
+            MemoryResultStore::default(),
+            LogFormat::Text,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(store.runs.len(), 2);
+        assert_eq!(store.elapsed_times.len(), 2);
+        assert!(store.elapsed_times.values().all(|seconds| *seconds > 0.0));
+        let num_particles = base.amount * base.particle_parameters.len();
+        assert_eq!(
+            store.state_counts.values().sum::<u64>(),
+            (2 * iterations * num_particles) as u64
+        );
+        let metric_names = SearchRun {
+            state_counts: HashMap::new(),
+            max_velocity_fraction: 0.0,
+            clamp_rate: 0.0,
+            kinetic_energy: 0.0,
+            potential_energy: 0.0,
+            center_of_mass_drift: 0.0,
+            momentum_drift: 0.0,
+            cluster_sizes: vec![],
+            iterations: 0,
+        }
+        .metrics()
+        .len();
+        assert_eq!(store.metrics.len(), 2 * metric_names);
+    }
+
+    #[test]
+    fn test_shutdown_stops_starting_runs() {
+        let base = Parameters {
+            amount: 2,
+            ..Parameters::default()
+        };
+        let spec = SweepSpec {
+            amounts: vec![2],
+            frictions: vec![0.0, 0.01, 0.02],
+            borders: vec![200.0],
+            timesteps: vec![0.0002],
+            gravity_constants: vec![1.0],
+            force_exponents: vec![2.0],
+            attraction_constants: vec![1.0],
+            repulsion_constants: vec![1.0],
+            max_velocities: vec![20000.0],
+            position_bucket_sizes: vec![10.0],
+            velocity_bucket_sizes: vec![10.0],
+        };
+        let shutdown = AtomicBool::new(false);
+        // Ctrl-c arrives while the second point is generated
+        let parameter_space = Parameters::parameter_space_from_spec(&spec, &base)
+            .enumerate()
+            .map(|(i, parameters)| {
+                if i == 1 {
+                    shutdown.store(true, Ordering::Relaxed);
+                }
+                parameters
+            });
+
+        let store = search(
+            parameter_space,
+            3,
+            &CartesianMapper,
+            MemoryResultStore::default(),
+            LogFormat::Text,
+            &shutdown,
+        )
+        .unwrap();
+
+        assert_eq!(store.runs.len(), 1);
+        assert_eq!(store.elapsed_times.len(), 1);
+    }
+
+    /// Store whose commits always fail, like a database on a full disk.
+    struct FailingResultStore;
+
+    impl ResultStore for FailingResultStore {
+        fn persist_parameters(
+            &mut self,
+            _parameters: &mut Parameters,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn increment_state_counts(
+            &mut self,
+            _state_vectors: &[StateVector],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn persist_run_metric(
+            &mut self,
+            _run_id: usize,
+            _name: &str,
+            _value: f64,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn persist_elapsed_time(
+            &mut self,
+            _run_id: usize,
+            _seconds: f64,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn commit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            Err("database or disk is full".into())
+        }
+    }
+
+    #[test]
+    fn test_search_fails_and_stops_on_store_errors() {
+        let parameter_space = (0..100).map(|_| Parameters {
+            amount: 2,
+            ..Parameters::default()
+        });
+        let shutdown = AtomicBool::new(false);
+
+        let result = search(
+            parameter_space,
+            3,
+            &CartesianMapper,
+            FailingResultStore,
+            LogFormat::Text,
+            &shutdown,
+        );
+
+        assert_eq!(
+            result.err().map(|e| e.to_string()),
+            Some("database or disk is full".to_string())
+        );
+        assert!(shutdown.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_sample_every() {
+        let mut parameters = Parameters {
+            amount: 2,
+            sample_every: 10,
+            ..Parameters::default()
+        };
+        for (id, particle_parameters) in parameters.particle_parameters.iter_mut().enumerate() {
+            particle_parameters.id = Some(id);
+        }
+        let iterations = 100;
+
+        let search_run = simulate_search_run(
+            &parameters,
+            iterations,
+            &CartesianMapper,
+            FLUSH_INTERVAL,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        let num_particles = parameters.amount * parameters.particle_parameters.len();
+        assert_eq!(
+            search_run.state_counts.values().sum::<usize>(),
+            iterations / 10 * num_particles
+        );
+    }
+
+    #[test]
+    fn test_flushed_and_buffered_search_runs_count_alike() {
+        let mut parameters = Parameters {
+            amount: 3,
+            seed: Some(4),
+            ..Parameters::default()
+        };
+        MemoryResultStore::default()
+            .persist_parameters(&mut parameters)
+            .unwrap();
+        let iterations = 50;
+        let simulate = |flush_interval| {
+            let mut store = MemoryResultStore::default();
+            let mut flushes = 0;
+            let search_run = simulate_search_run(
+                &parameters,
+                iterations,
+                &CartesianMapper,
+                flush_interval,
+                |state_vectors| {
+                    flushes += 1;
+                    store.increment_state_counts(state_vectors)
+                },
+            )
+            .unwrap();
+            (store.state_counts, search_run.metrics(), flushes)
+        };
+
+        let (flushed_counts, flushed_metrics, flushes) = simulate(7);
+        let (buffered_counts, buffered_metrics, single_flush) = simulate(usize::MAX);
+
+        assert_eq!(flushes, 8);
+        assert_eq!(single_flush, 1);
+        assert_eq!(flushed_counts, buffered_counts);
+        // The entropy sums over hash maps, so it may differ in the order of the summands
+        for ((name, flushed), (other_name, buffered)) in
+            flushed_metrics.iter().zip(&buffered_metrics)
+        {
+            assert_eq!(name, other_name);
+            assert!((flushed - buffered).abs() < 1e-9, "{} differs", name);
+        }
+        assert_eq!(
+            flushed_counts.values().sum::<u64>(),
+            (iterations * parameters.amount * parameters.particle_parameters.len()) as u64
+        );
+    }
 }
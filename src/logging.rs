@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log::{Log, Metadata, Record};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::parameters::Parameters;
+
+/// Target of the log records that describe a completed search run. Their message is already a
+/// JSON object, see `RunRecord`.
+pub const RUN_LOG_TARGET: &str = "atomata::run";
+
+/// Format of the search mode's log file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with one `RunRecord` per completed run
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Expected log format text or json, found {}", s)),
+        }
+    }
+}
+
+/// Summary of a completed search run, logged as one JSON object.
+#[derive(Debug, Serialize)]
+pub struct RunRecord<'a> {
+    pub run_id: usize,
+    pub parameters: &'a Parameters,
+    pub elapsed_time_s: f64,
+    pub metrics: BTreeMap<&'a str, f64>,
+}
+
+impl RunRecord<'_> {
+    /// Logs the record as JSON to the run target.
+    pub fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => log::info!(target: RUN_LOG_TARGET, "{}", json),
+            Err(e) => log::error!("Can't serialize run {}: {}", self.run_id, e),
+        }
+    }
+}
+
+/// Logger writing one JSON object per record. Run records are written as they are, all other
+/// records are wrapped into an object with their level, target and message.
+pub struct JsonLogger<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLogger<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = if record.target() == RUN_LOG_TARGET {
+            record.args().to_string()
+        } else {
+            json!({
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        };
+        let mut writer = self.writer.lock().unwrap();
+        // A failing log write has nowhere to be reported to
+        let _ = writeln!(writer, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_json_logger_emits_one_json_object_per_line() {
+        let logger = JsonLogger::new(Vec::new());
+        let parameters = Parameters::default();
+        let record = RunRecord {
+            run_id: 3,
+            parameters: &parameters,
+            elapsed_time_s: 1.5,
+            metrics: BTreeMap::from([("entropy", 0.25)]),
+        };
+        let run_json = serde_json::to_string(&record).unwrap();
+
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Info)
+                .target(RUN_LOG_TARGET)
+                .args(format_args!("{}", run_json))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Info)
+                .target("atomata")
+                .args(format_args!("Run {} / {} with \"quotes\"", 1, 2))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Debug)
+                .args(format_args!("Dropped"))
+                .build(),
+        );
+
+        let output = String::from_utf8(logger.writer.into_inner().unwrap()).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["run_id"], 3);
+        assert_eq!(lines[0]["metrics"]["entropy"], 0.25);
+        assert_eq!(lines[0]["parameters"]["amount"], parameters.amount);
+        assert_eq!(lines[1]["message"], "Run 1 / 2 with \"quotes\"");
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -1,13 +1,26 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+use three_d::Srgba;
 
 #[derive(Debug)]
 pub enum Mode {
     Default, // < Default mode with graphical user interface and rendering
     #[allow(dead_code)]
     Search, // < No graphical user interface and no rendering, only simulation and persistence of data
+    #[allow(dead_code)]
+    RenderFrames, // < No window, the default simulation is rendered offscreen into PNG files
+    #[allow(dead_code)]
+    Serve, // < No window, the default simulation streams its frames over HTTP
+    #[allow(dead_code)]
+    RunOnce, // < No window, the default simulation runs like a single search run and prints its metrics
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum InteractionType {
     Attraction,
     Repulsion,
@@ -20,50 +33,299 @@ impl Display for InteractionType {
     }
 }
 
-#[derive(Debug)]
+impl FromStr for InteractionType {
+    type Err = String;
+
+    /// Parses the names written by `Display`, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "attraction" => Ok(InteractionType::Attraction),
+            "repulsion" => Ok(InteractionType::Repulsion),
+            "neutral" => Ok(InteractionType::Neutral),
+            _ => Err(format!(
+                "Expected interaction type Attraction, Repulsion or Neutral, found {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Shape of the wall particles are reflected at.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum BoundaryShape {
+    /// Reflects particles whose distance from the center exceeds the border at the surface
+    /// normal, reversing their radial velocity and keeping their tangential one
+    #[default]
+    Sphere,
+    /// Reflects each axis independently once its coordinate exceeds the border
+    Box,
+    /// Wraps each axis around so a particle leaving at `+border` enters at `-border`, forces act
+    /// across the seam along the shortest periodic displacement
+    Periodic,
+}
+
+impl Display for BoundaryShape {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How `max_velocity` limits the velocity of a particle.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum VelocityClamp {
+    /// Caps every axis independently, allowing speeds of up to `sqrt(3) * max_velocity`
+    #[default]
+    PerAxis,
+    /// Rescales the velocity to `max_velocity` if its norm exceeds it, keeping the direction
+    Magnitude,
+}
+
+impl Display for VelocityClamp {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Law of the force between two interacting particles.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum ForceLaw {
+    /// `gravity_constant * m1 * m2 / distance^force_exponent`, towards each other for attracting
+    /// pairs and away from each other for repelling ones. The force is scaled by
+    /// `attraction_constant` or `repulsion_constant` depending on the pair.
+    #[default]
+    InversePower,
+    /// Repels at short range and attracts at mid range with a potential well of depth `epsilon`
+    /// at `2^(1/6) * sigma`, so attracting kinds settle into clusters with that spacing.
+    /// Repelling pairs only feel the short-range repulsion. The force is cut off beyond
+    /// `particle::LENNARD_JONES_CUTOFF * sigma` and doesn't depend on the masses.
+    LennardJones { epsilon: f32, sigma: f32 },
+}
+
+impl Display for ForceLaw {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ForceLaw::InversePower => write!(f, "InversePower"),
+            ForceLaw::LennardJones { .. } => write!(f, "LennardJones"),
+        }
+    }
+}
+
+/// Where the particles of a kind start, relative to the center of the simulation.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Placement {
+    /// Uniformly at random within the cube of edge length `border`
+    #[default]
+    Uniform,
+    /// Uniformly at random on the surface of a sphere with the given radius
+    Shell { radius: f32 },
+    /// Uniformly at random within a ball with the given radius around `center`
+    Cluster { center: [f32; 3], radius: f32 },
+    /// On the nodes of a cubic lattice spanning the cube of edge length `border`, filled row by
+    /// row if the amount of particles isn't a cube number
+    Grid,
+}
+
+/// How the particles of a kind start moving.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum VelocityInit {
+    /// At rest
+    Zero,
+    /// Uniformly at random within `[-max_velocity / 2, max_velocity / 2]` per axis
+    #[default]
+    Uniform,
+    /// With the given speed away from the center, along the position vector
+    Radial { speed: f32 },
+    /// With the given speed around the z axis through the center, perpendicular to the position
+    /// vector, e.g. for a rotating disk
+    Tangential { speed: f32 },
+}
+
+/// How the masses of the particles of a kind are spread.
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum MassDistribution {
+    /// Every particle has the given mass
+    Fixed(f32),
+    /// Uniformly at random within `[min, max]`
+    Uniform { min: f32, max: f32 },
+    /// Normally distributed, truncated to positive masses
+    Normal { mean: f32, std: f32 },
+}
+
+impl MassDistribution {
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f32 {
+        match *self {
+            MassDistribution::Fixed(mass) => mass,
+            MassDistribution::Uniform { min, max } => rng.gen_range(min..=max),
+            MassDistribution::Normal { mean, std } => {
+                // Redraw non-positive masses, which takes less than two draws on average as the
+                // mean is positive
+                loop {
+                    let mass = mean + std * rng.sample::<f32, _>(StandardNormal);
+                    if mass > 0.0 {
+                        return mass;
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid = match *self {
+            MassDistribution::Fixed(mass) => mass > 0.0,
+            MassDistribution::Uniform { min, max } => min > 0.0 && min <= max,
+            MassDistribution::Normal { mean, std } => mean > 0.0 && std >= 0.0,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("Expected positive masses, found {:?}", self))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ParticleParameters {
+    #[serde(skip)]
     pub id: Option<usize>,
     pub mass: f32,
     pub index: usize,
+    /// Overrides the generated color of this particle kind
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_srgba"
+    )]
+    pub color: Option<Srgba>,
+    #[serde(default)]
+    pub placement: Placement,
+    #[serde(default)]
+    pub velocity_init: VelocityInit,
+    /// Radius within which particles of this kind collide elastically with other colliding
+    /// particles instead of passing through them. Particles without one never collide.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collision_radius: Option<f32>,
+    /// Spreads the masses of the particles of this kind, all of them have `mass` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mass_distribution: Option<MassDistribution>,
+    /// Keeps the particles of this kind in place while they still exert forces on the others,
+    /// e.g. to watch mobile kinds respond to a static scaffold
+    #[serde(default)]
+    pub frozen: bool,
 }
 
-#[derive(Debug)]
+impl ParticleParameters {
+    /// Returns a uniformly placed, non-colliding and mobile particle kind with the given mass.
+    pub fn new(index: usize, mass: f32) -> Self {
+        Self {
+            id: None,
+            mass,
+            index,
+            color: None,
+            placement: Placement::Uniform,
+            velocity_init: VelocityInit::Uniform,
+            collision_radius: None,
+            mass_distribution: None,
+            frozen: false,
+        }
+    }
+
+    /// Draws the mass of a new particle of this kind. Without a mass distribution no random
+    /// number is drawn, so seeded simulations stay as they were.
+    pub fn sample_mass<R: Rng + ?Sized>(&self, rng: &mut R) -> f32 {
+        self.mass_distribution
+            .map_or(self.mass, |distribution| distribution.sample(rng))
+    }
+}
+
+/// (De)serializes an optional color as `[r, g, b, a]`.
+mod optional_srgba {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use three_d::Srgba;
+
+    pub fn serialize<S: Serializer>(
+        color: &Option<Srgba>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        color
+            .map(|color| [color.r, color.g, color.b, color.a])
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Srgba>, D::Error> {
+        let color = Option::<[u8; 4]>::deserialize(deserializer)?;
+        Ok(color.map(|[r, g, b, a]| Srgba::new(r, g, b, a)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Parameters {
+    #[serde(skip)]
+    pub run_id: Option<usize>,
     pub amount: usize,
     pub border: f32,
+    pub boundary_shape: BoundaryShape,
     pub timestep: f32,
     pub gravity_constant: f32,
+    /// Exponent `p` of the force law `gravity_constant * m1 * m2 / distance^p`, where 2 is
+    /// Newtonian gravity. Other exponents are unphysical but give rise to other behaviors.
+    pub force_exponent: f32,
+    /// Scales the inverse power force between attracting pairs, so attraction can dominate
+    /// repulsion or vice versa
+    pub attraction_constant: f32,
+    /// Scales the inverse power force between repelling pairs
+    pub repulsion_constant: f32,
+    pub force_law: ForceLaw,
+    /// Fraction of the velocity lost per step, zero is frictionless and one stops particles
+    /// instantly
     pub friction: f32,
+    /// Strength of the random velocity kick each particle gets per step, zero disables the noise
+    pub temperature: f32,
+    /// Removes the center of mass velocity after every step, so a drifting cloud stays framed
+    /// instead of piling up at the boundary
+    pub recenter: bool,
     pub particle_parameters: Vec<ParticleParameters>,
     pub interactions: Vec<InteractionType>,
     pub max_velocity: f32,
-    pub bucket_size: f32,
+    pub velocity_clamp: VelocityClamp,
+    /// Edge length of the cubes positions are bucketed into for the state vectors.
+    #[serde(alias = "bucket_size")]
+    pub position_bucket_size: f32,
+    /// Edge length of the cubes velocities are bucketed into for the state vectors. Velocities
+    /// range up to `max_velocity`, so this is usually much larger than `position_bucket_size`.
+    #[serde(default = "default_velocity_bucket_size")]
+    pub velocity_bucket_size: f32,
+    /// Only every nth iteration of a search run contributes state vectors.
+    pub sample_every: usize,
+    /// Length of the windows of iterations whose state distributions a search run compares to
+    /// detect convergence, zero disables early stopping.
+    pub convergence_interval: usize,
+    /// A search run stops early once the state distribution changes less than this between two
+    /// consecutive windows, see `analysis::state_distribution_change`.
+    pub convergence_threshold: f32,
+    /// Seeds the initial particle layout and colors, a fresh seed is drawn per reset if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Angle subdivisions of the rendered particle spheres. A sphere mesh has about `2 * n²`
+    /// vertices, so halving the subdivisions quarters the geometry drawn per particle.
+    pub sphere_subdivisions: u32,
 }
 
 impl Default for Parameters {
     fn default() -> Self {
         Parameters {
+            run_id: None,
             amount: 10,
             border: 200.0,
             friction: 0.005,
             timestep: 0.0002,
             gravity_constant: 1.0,
             particle_parameters: vec![
-                ParticleParameters {
-                    id: None,
-                    mass: 3.0,
-                    index: 0,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 250.0,
-                    index: 1,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 1000.0,
-                    index: 2,
-                },
+                ParticleParameters::new(0, 3.0),
+                ParticleParameters::new(1, 250.0),
+                ParticleParameters::new(2, 1000.0),
             ],
             interactions: vec![
                 InteractionType::Repulsion,  // 0 <-> 0
@@ -74,12 +336,153 @@ impl Default for Parameters {
                 InteractionType::Neutral,    // 2 <-> 2
             ],
             max_velocity: 20000.0,
-            bucket_size: 10.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 10.0,
+            sample_every: 1,
+            seed: None,
+            convergence_interval: 0,
+            convergence_threshold: 0.01,
+            temperature: 0.0,
+            velocity_clamp: VelocityClamp::PerAxis,
+            boundary_shape: BoundaryShape::Sphere,
+            sphere_subdivisions: 16,
+            force_exponent: 2.0,
+            attraction_constant: 1.0,
+            repulsion_constant: 1.0,
+            force_law: ForceLaw::InversePower,
+            recenter: false,
         }
     }
 }
 
 impl Parameters {
+    /// Returns a builder starting from the default parameters without any particle kinds.
+    pub fn builder() -> ParametersBuilder {
+        ParametersBuilder::default()
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Parameters are always representable as TOML")
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let parameters: Parameters = toml::from_str(content).map_err(|e| e.to_string())?;
+        parameters.validate()?;
+        Ok(parameters)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Parameters are always representable as JSON")
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let parameters: Parameters = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        parameters.validate()?;
+        Ok(parameters)
+    }
+
+    /// Checks that the particle kinds are indexed `0..n` and that the interactions matrix fits
+    /// them, so `interaction_by_indices` can't go out of bounds during the simulation. Friction
+    /// has to be within `[0, 1]`, otherwise it would amplify or flip the velocities.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_friction(self.friction)?;
+        if !self.force_exponent.is_finite() {
+            return Err(format!(
+                "Expected a finite force exponent, found {}",
+                self.force_exponent
+            ));
+        }
+        for (name, constant) in [
+            ("attraction", self.attraction_constant),
+            ("repulsion", self.repulsion_constant),
+        ] {
+            if !(constant >= 0.0 && constant.is_finite()) {
+                return Err(format!(
+                    "Expected a non-negative {} constant, found {}",
+                    name, constant
+                ));
+            }
+        }
+        if let ForceLaw::LennardJones { epsilon, sigma } = self.force_law {
+            if !(epsilon >= 0.0 && epsilon.is_finite()) {
+                return Err(format!(
+                    "Expected a non-negative Lennard-Jones epsilon, found {}",
+                    epsilon
+                ));
+            }
+            if !(sigma > 0.0 && sigma.is_finite()) {
+                return Err(format!(
+                    "Expected a positive Lennard-Jones sigma, found {}",
+                    sigma
+                ));
+            }
+        }
+        if self.sphere_subdivisions < MIN_SPHERE_SUBDIVISIONS {
+            return Err(format!(
+                "Expected at least {} sphere subdivisions, found {}",
+                MIN_SPHERE_SUBDIVISIONS, self.sphere_subdivisions
+            ));
+        }
+
+        let mut indices = self
+            .particle_parameters
+            .iter()
+            .map(|p| p.index)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        if indices.iter().enumerate().any(|(i, index)| i != *index) {
+            return Err(format!(
+                "Expected particle kind indices 0 to {}, found {:?}",
+                self.particle_parameters.len() as i64 - 1,
+                indices
+            ));
+        }
+
+        for particle in &self.particle_parameters {
+            if let Placement::Shell { radius } | Placement::Cluster { radius, .. } =
+                particle.placement
+            {
+                if !(radius > 0.0 && radius.is_finite()) {
+                    return Err(format!(
+                        "Expected a positive placement radius for particle kind {}, found {}",
+                        particle.index, radius
+                    ));
+                }
+            }
+            if let Some(radius) = particle.collision_radius {
+                if !(radius > 0.0 && radius.is_finite()) {
+                    return Err(format!(
+                        "Expected a positive collision radius for particle kind {}, found {}",
+                        particle.index, radius
+                    ));
+                }
+            }
+            if let Some(distribution) = &particle.mass_distribution {
+                distribution
+                    .validate()
+                    .map_err(|e| format!("{} for particle kind {}", e, particle.index))?;
+            }
+        }
+
+        self.validate_interactions()
+    }
+
+    /// Checks that the flat interactions matrix holds exactly one entry per pair of particle
+    /// kinds, i.e. the upper triangle including the diagonal.
+    fn validate_interactions(&self) -> Result<(), String> {
+        let num_particle_kinds = self.particle_parameters.len();
+        let expected = num_particle_kinds * (num_particle_kinds + 1) / 2;
+        if self.interactions.len() != expected {
+            return Err(format!(
+                "Expected {} interactions for {} particle kinds, found {}",
+                expected,
+                num_particle_kinds,
+                self.interactions.len()
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns the interaction type between two particles given their indices from the
     /// flat symmetric triangle interactions matrix.
     ///
@@ -90,6 +493,9 @@ impl Parameters {
     ///                       2   5 7 8
     pub fn interaction_by_indices(&self, i: usize, j: usize) -> Result<InteractionType, String> {
         let num_particle_kinds = self.particle_parameters.len();
+        if num_particle_kinds == 0 {
+            return Err("No particle kinds".to_string());
+        }
         if i > num_particle_kinds - 1 || j > num_particle_kinds - 1 {
             return Err("Index out of bounds".to_string());
         }
@@ -104,76 +510,517 @@ impl Parameters {
         self.particle_parameters.iter().find(|p| p.index == index)
     }
 
-    pub fn parameter_space() -> Vec<Self> {
-        let mut parameter_space = vec![];
-
-        let amounts = vec![10, 100, 500, 1000];
-        let borders = vec![400.0, 600.0, 2000.0];
-        let frictions = vec![0.0, 0.005, 0.01];
-        let timesteps = vec![0.0002, 0.0004];
-        let gravity_constants = vec![0.5, 1.0, 3.0];
-        let max_velocities = vec![20000.0, 40000.0, 60000.0];
-        let bucket_sizes = vec![2.0, 5.0, 10.0, 20.0, 30.0];
-
-        for amount in amounts {
-            for border in &borders {
-                for friction in &frictions {
-                    for timestep in &timesteps {
-                        for gravity_constant in &gravity_constants {
-                            for max_velocity in &max_velocities {
-                                for bucket_size in &bucket_sizes {
-                                    let particle_parameters = vec![
-                                        ParticleParameters {
-                                            id: None,
-                                            mass: 3.0,
-                                            index: 0,
-                                        },
-                                        ParticleParameters {
-                                            id: None,
-                                            mass: 250.0,
-                                            index: 1,
-                                        },
-                                        ParticleParameters {
-                                            id: None,
-                                            mass: 1000.0,
-                                            index: 2,
-                                        },
-                                    ];
-
-                                    let interactions = vec![
-                                        InteractionType::Repulsion,  // 0 <-> 0
-                                        InteractionType::Attraction, // 1 <-> 0
-                                        InteractionType::Attraction, // 2 <-> 0
-                                        InteractionType::Repulsion,  // 1 <-> 1
-                                        InteractionType::Attraction, // 1 <-> 2
-                                        InteractionType::Neutral,    // 2 <-> 2
-                                    ];
-
-                                    let parameters = Parameters {
-                                        amount,
-                                        border: *border,
-                                        friction: *friction,
-                                        timestep: *timestep,
-                                        gravity_constant: *gravity_constant,
-                                        particle_parameters,
-                                        interactions,
-                                        max_velocity: *max_velocity,
-                                        bucket_size: *bucket_size,
-                                    };
-
-                                    parameter_space.push(parameters);
-                                }
-                            }
-                        }
-                    }
+    /// Returns the persisted id of each particle kind by its index, which saves the linear
+    /// search of `particle_parameters_by_index` in loops over all particles.
+    pub fn particle_parameters_ids(&self) -> Vec<Option<usize>> {
+        let mut ids = vec![None; self.particle_parameters.len()];
+        for particle_params in &self.particle_parameters {
+            if let Some(id) = ids.get_mut(particle_params.index) {
+                *id = particle_params.id;
+            }
+        }
+        ids
+    }
+
+    /// Appends a particle kind with the given mass and the next free index. It is neutral to
+    /// all kinds, including itself.
+    pub fn add_particle_kind(&mut self, mass: f32) {
+        let num_particle_kinds = self.particle_parameters.len();
+        self.interactions = resize_interactions(
+            &self.interactions,
+            num_particle_kinds,
+            num_particle_kinds + 1,
+        );
+        self.particle_parameters
+            .push(ParticleParameters::new(num_particle_kinds, mass));
+    }
+
+    /// Removes the particle kind with the highest index together with its interactions.
+    pub fn remove_particle_kind(&mut self) -> Option<ParticleParameters> {
+        let num_particle_kinds = self.particle_parameters.len();
+        let position = self
+            .particle_parameters
+            .iter()
+            .position(|p| p.index + 1 == num_particle_kinds)?;
+        self.interactions = resize_interactions(
+            &self.interactions,
+            num_particle_kinds,
+            num_particle_kinds - 1,
+        );
+        Some(self.particle_parameters.remove(position))
+    }
+
+    pub fn parameter_space() -> ParameterSpace {
+        Self::parameter_space_from_spec(&SweepSpec::default(), &Parameters::default())
+    }
+
+    /// Returns a random flat symmetric interactions matrix for `n` particle kinds, see
+    /// `interaction_by_indices` for the layout.
+    pub fn random_interactions(n: usize, seed: u64) -> Vec<InteractionType> {
+        const INTERACTION_TYPES: [InteractionType; 3] = [
+            InteractionType::Attraction,
+            InteractionType::Repulsion,
+            InteractionType::Neutral,
+        ];
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n * (n + 1) / 2)
+            .map(|_| INTERACTION_TYPES[rng.gen_range(0..INTERACTION_TYPES.len())])
+            .collect()
+    }
+
+    /// Returns `count` points sampled from the ranges of the default sweep spec, see
+    /// `latin_hypercube_sample_from_spec`.
+    pub fn latin_hypercube_sample(count: usize, seed: u64) -> Vec<Self> {
+        Self::latin_hypercube_sample_from_spec(
+            &SweepSpec::default(),
+            &Parameters::default(),
+            count,
+            seed,
+        )
+    }
+
+    /// Returns `count` points spread over the ranges spanned by the axes of the given sweep
+    /// spec. Each range is split into `count` equally sized strata and every stratum of every
+    /// axis is sampled exactly once, so few points still cover each axis evenly, unlike a
+    /// random subset of the cartesian product. Everything that is not swept is taken from `base`.
+    pub fn latin_hypercube_sample_from_spec(
+        spec: &SweepSpec,
+        base: &Parameters,
+        count: usize,
+        seed: u64,
+    ) -> Vec<Self> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let amounts = spec
+            .amounts
+            .iter()
+            .map(|amount| *amount as f32)
+            .collect::<Vec<_>>();
+        let amounts = latin_hypercube_axis(&amounts, count, &mut rng);
+        let borders = latin_hypercube_axis(&spec.borders, count, &mut rng);
+        let frictions = latin_hypercube_axis(&spec.frictions, count, &mut rng);
+        let timesteps = latin_hypercube_axis(&spec.timesteps, count, &mut rng);
+        let gravity_constants = latin_hypercube_axis(&spec.gravity_constants, count, &mut rng);
+        let max_velocities = latin_hypercube_axis(&spec.max_velocities, count, &mut rng);
+        let position_bucket_sizes =
+            latin_hypercube_axis(&spec.position_bucket_sizes, count, &mut rng);
+        let velocity_bucket_sizes =
+            latin_hypercube_axis(&spec.velocity_bucket_sizes, count, &mut rng);
+        let force_exponents = latin_hypercube_axis(&spec.force_exponents, count, &mut rng);
+        let attraction_constants =
+            latin_hypercube_axis(&spec.attraction_constants, count, &mut rng);
+        let repulsion_constants = latin_hypercube_axis(&spec.repulsion_constants, count, &mut rng);
+
+        (0..count)
+            .map(|i| Parameters {
+                amount: amounts[i].round() as usize,
+                border: borders[i],
+                friction: frictions[i],
+                timestep: timesteps[i],
+                gravity_constant: gravity_constants[i],
+                force_exponent: force_exponents[i],
+                attraction_constant: attraction_constants[i],
+                repulsion_constant: repulsion_constants[i],
+                max_velocity: max_velocities[i],
+                position_bucket_size: position_bucket_sizes[i],
+                velocity_bucket_size: velocity_bucket_sizes[i],
+                ..base.clone()
+            })
+            .collect()
+    }
+
+    /// Returns `count` copies of `base`, each with its own random interactions matrix. The seed
+    /// of every copy generates its matrix and also seeds its run, so a persisted run can be
+    /// reproduced from its seed alone.
+    pub fn random_parameter_space(count: usize, seed: u64, base: &Parameters) -> Vec<Self> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                // Seeds are persisted as SQLite integers, which are signed
+                let seed = rng.gen_range(0..=i64::MAX as u64);
+                Parameters {
+                    interactions: Self::random_interactions(base.particle_parameters.len(), seed),
+                    seed: Some(seed),
+                    ..base.clone()
                 }
+            })
+            .collect()
+    }
+
+    /// Returns the cartesian product over all axes of the given sweep spec. Everything that is
+    /// not swept, like the particle kinds and their interactions, is taken from `base`.
+    pub fn parameter_space_from_spec(spec: &SweepSpec, base: &Parameters) -> ParameterSpace {
+        ParameterSpace {
+            spec: spec.clone(),
+            base: base.clone(),
+            next: 0,
+            size: spec.size(),
+        }
+    }
+}
+
+/// Builds `Parameters` from a list of particle kinds and the interaction of every pair of them,
+/// laying out the flat interactions matrix the way `interaction_by_indices` reads it.
+pub struct ParametersBuilder {
+    parameters: Parameters,
+    interaction_fn: Option<Box<dyn Fn(usize, usize) -> InteractionType>>,
+    interaction_map: HashMap<(usize, usize), InteractionType>,
+}
+
+impl Default for ParametersBuilder {
+    fn default() -> Self {
+        Self {
+            parameters: Parameters {
+                particle_parameters: vec![],
+                interactions: vec![],
+                ..Parameters::default()
+            },
+            interaction_fn: None,
+            interaction_map: HashMap::new(),
+        }
+    }
+}
+
+impl ParametersBuilder {
+    /// Adds a particle kind with the given mass, indexed in the order the kinds are added.
+    pub fn kind(mut self, mass: f32) -> Self {
+        let index = self.parameters.particle_parameters.len();
+        self.parameters
+            .particle_parameters
+            .push(ParticleParameters::new(index, mass));
+        self
+    }
+
+    /// Adds a particle kind with the given mass and color.
+    pub fn colored_kind(self, mass: f32, color: Srgba) -> Self {
+        let mut builder = self.kind(mass);
+        builder
+            .parameters
+            .particle_parameters
+            .last_mut()
+            .unwrap()
+            .color = Some(color);
+        builder
+    }
+
+    /// Sets the interaction of every pair of kinds not given by `interaction`. The function has
+    /// to be symmetric, i.e. return the same for `(i, j)` and `(j, i)`.
+    pub fn interactions<F: Fn(usize, usize) -> InteractionType + 'static>(mut self, f: F) -> Self {
+        self.interaction_fn = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the interaction between the kinds with indices `i` and `j` in both directions.
+    pub fn interaction(mut self, i: usize, j: usize, interaction: InteractionType) -> Self {
+        self.interaction_map
+            .insert((i.min(j), i.max(j)), interaction);
+        self
+    }
+
+    pub fn amount(mut self, amount: usize) -> Self {
+        self.parameters.amount = amount;
+        self
+    }
+
+    pub fn border(mut self, border: f32) -> Self {
+        self.parameters.border = border;
+        self
+    }
+
+    pub fn timestep(mut self, timestep: f32) -> Self {
+        self.parameters.timestep = timestep;
+        self
+    }
+
+    pub fn gravity_constant(mut self, gravity_constant: f32) -> Self {
+        self.parameters.gravity_constant = gravity_constant;
+        self
+    }
+
+    pub fn friction(mut self, friction: f32) -> Self {
+        self.parameters.friction = friction;
+        self
+    }
+
+    pub fn max_velocity(mut self, max_velocity: f32) -> Self {
+        self.parameters.max_velocity = max_velocity;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.parameters.seed = Some(seed);
+        self
+    }
+
+    /// Computes the interactions matrix and validates the resulting parameters. Fails if the
+    /// interaction of a pair of kinds is missing or the interaction function isn't symmetric.
+    pub fn build(self) -> Result<Parameters, String> {
+        let num_particle_kinds = self.parameters.particle_parameters.len();
+        let interaction = |i: usize, j: usize| -> Result<InteractionType, String> {
+            if let Some(interaction) = self.interaction_map.get(&(i, j)) {
+                return Ok(*interaction);
+            }
+            let f = self.interaction_fn.as_ref().ok_or_else(|| {
+                format!("No interaction given for particle kinds {} and {}", i, j)
+            })?;
+            if f(i, j) != f(j, i) {
+                return Err(format!(
+                    "Interaction of particle kinds {} and {} is not symmetric",
+                    i, j
+                ));
+            }
+            Ok(f(i, j))
+        };
+
+        let interactions = (0..num_particle_kinds)
+            .flat_map(|i| (i..num_particle_kinds).map(move |j| (i, j)))
+            .map(|(i, j)| interaction(i, j))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parameters = Parameters {
+            interactions,
+            ..self.parameters
+        };
+        parameters.validate()?;
+        Ok(parameters)
+    }
+}
+
+/// Fewest angle subdivisions that still give a closed sphere mesh
+pub const MIN_SPHERE_SUBDIVISIONS: u32 = 2;
+
+fn validate_friction(friction: f32) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&friction) {
+        return Err(format!(
+            "Expected friction between 0 and 1, found {}",
+            friction
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the flat interactions matrix of `old_len` particle kinds resized to `new_len` kinds.
+/// Interactions between kinds present in both are kept, pairs with a new kind are neutral.
+fn resize_interactions(
+    interactions: &[InteractionType],
+    old_len: usize,
+    new_len: usize,
+) -> Vec<InteractionType> {
+    let flat_index = |i: usize, j: usize| (i * (2 * old_len - i + 1)) / 2 + (j - i);
+    (0..new_len)
+        .flat_map(|i| (i..new_len).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            if j < old_len {
+                interactions[flat_index(i, j)]
+            } else {
+                InteractionType::Neutral
             }
+        })
+        .collect()
+}
+
+/// Velocity bucket size of parameters and sweep specs written before positions and velocities
+/// were bucketed separately, when one size was used for both.
+fn default_velocity_bucket_size() -> f32 {
+    10.0
+}
+
+fn default_velocity_bucket_sizes() -> Vec<f32> {
+    vec![default_velocity_bucket_size()]
+}
+
+/// Force exponents of sweep specs written before the exponent could be swept, i.e. gravity
+fn default_force_exponents() -> Vec<f32> {
+    vec![2.0]
+}
+
+/// Attraction or repulsion constants of sweep specs written before they could be swept
+fn default_force_constants() -> Vec<f32> {
+    vec![1.0]
+}
+
+/// Returns one value from each of `count` equal strata of the range spanned by `values`, in
+/// random order.
+fn latin_hypercube_axis<R: Rng>(values: &[f32], count: usize, rng: &mut R) -> Vec<f32> {
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut samples = (0..count)
+        .map(|stratum| {
+            let fraction = (stratum as f32 + rng.gen::<f32>()) / count as f32;
+            min + fraction * (max - min)
+        })
+        .collect::<Vec<_>>();
+    samples.shuffle(rng);
+    samples
+}
+
+/// Values swept per axis in search mode. The parameter space is the cartesian product of all
+/// axes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SweepSpec {
+    pub amounts: Vec<usize>,
+    pub borders: Vec<f32>,
+    pub frictions: Vec<f32>,
+    pub timesteps: Vec<f32>,
+    pub gravity_constants: Vec<f32>,
+    #[serde(default = "default_force_exponents")]
+    pub force_exponents: Vec<f32>,
+    #[serde(default = "default_force_constants")]
+    pub attraction_constants: Vec<f32>,
+    #[serde(default = "default_force_constants")]
+    pub repulsion_constants: Vec<f32>,
+    pub max_velocities: Vec<f32>,
+    #[serde(alias = "bucket_sizes")]
+    pub position_bucket_sizes: Vec<f32>,
+    #[serde(default = "default_velocity_bucket_sizes")]
+    pub velocity_bucket_sizes: Vec<f32>,
+}
+
+impl Default for SweepSpec {
+    fn default() -> Self {
+        SweepSpec {
+            amounts: vec![10, 100, 500, 1000],
+            borders: vec![400.0, 600.0, 2000.0],
+            frictions: vec![0.0, 0.005, 0.01],
+            timesteps: vec![0.0002, 0.0004],
+            gravity_constants: vec![0.5, 1.0, 3.0],
+            force_exponents: vec![1.0, 2.0, 3.0],
+            attraction_constants: vec![1.0, 2.0],
+            repulsion_constants: vec![1.0, 2.0],
+            max_velocities: vec![20000.0, 40000.0, 60000.0],
+            position_bucket_sizes: vec![2.0, 5.0, 10.0, 20.0, 30.0],
+            velocity_bucket_sizes: default_velocity_bucket_sizes(),
         }
+    }
+}
+
+impl SweepSpec {
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Sweep specs are always representable as TOML")
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let spec: SweepSpec = toml::from_str(content).map_err(|e| e.to_string())?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Sweep specs are always representable as JSON")
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let spec: SweepSpec = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Checks that every axis has at least one value, otherwise the parameter space would be
+    /// empty, and that all frictions are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        let axes = [
+            ("amounts", self.amounts.len()),
+            ("borders", self.borders.len()),
+            ("frictions", self.frictions.len()),
+            ("timesteps", self.timesteps.len()),
+            ("gravity_constants", self.gravity_constants.len()),
+            ("force_exponents", self.force_exponents.len()),
+            ("attraction_constants", self.attraction_constants.len()),
+            ("repulsion_constants", self.repulsion_constants.len()),
+            ("max_velocities", self.max_velocities.len()),
+            ("position_bucket_sizes", self.position_bucket_sizes.len()),
+            ("velocity_bucket_sizes", self.velocity_bucket_sizes.len()),
+        ];
+        if let Some((name, _)) = axes.iter().find(|(_, len)| *len == 0) {
+            return Err(format!("Sweep axis {} has no values", name));
+        }
+        self.frictions
+            .iter()
+            .try_for_each(|friction| validate_friction(*friction))
+    }
+
+    /// Number of points in the parameter space, i.e. the product of the axis lengths.
+    pub fn size(&self) -> usize {
+        self.amounts.len()
+            * self.borders.len()
+            * self.frictions.len()
+            * self.timesteps.len()
+            * self.gravity_constants.len()
+            * self.force_exponents.len()
+            * self.attraction_constants.len()
+            * self.repulsion_constants.len()
+            * self.max_velocities.len()
+            * self.position_bucket_sizes.len()
+            * self.velocity_bucket_sizes.len()
+    }
+}
+
+/// Cartesian product over the axes of a sweep spec, generated lazily so that large sweeps are
+/// never held in memory at once. Points come in the order of nested loops over the axes, from
+/// `amounts` in the outermost to `velocity_bucket_sizes` in the innermost loop.
+#[derive(Debug, Clone)]
+pub struct ParameterSpace {
+    spec: SweepSpec,
+    base: Parameters,
+    next: usize,
+    size: usize,
+}
+
+impl Iterator for ParameterSpace {
+    type Item = Parameters;
+
+    fn next(&mut self) -> Option<Parameters> {
+        if self.next >= self.size {
+            return None;
+        }
+        // Decode the index of the point into one index per axis, innermost axis first
+        let mut rest = self.next;
+        self.next += 1;
+        let mut axis_index = |len: usize| {
+            let index = rest % len;
+            rest /= len;
+            index
+        };
+        let spec = &self.spec;
+        let velocity_bucket_size =
+            spec.velocity_bucket_sizes[axis_index(spec.velocity_bucket_sizes.len())];
+        let position_bucket_size =
+            spec.position_bucket_sizes[axis_index(spec.position_bucket_sizes.len())];
+        let max_velocity = spec.max_velocities[axis_index(spec.max_velocities.len())];
+        let repulsion_constant =
+            spec.repulsion_constants[axis_index(spec.repulsion_constants.len())];
+        let attraction_constant =
+            spec.attraction_constants[axis_index(spec.attraction_constants.len())];
+        let force_exponent = spec.force_exponents[axis_index(spec.force_exponents.len())];
+        let gravity_constant = spec.gravity_constants[axis_index(spec.gravity_constants.len())];
+        let timestep = spec.timesteps[axis_index(spec.timesteps.len())];
+        let friction = spec.frictions[axis_index(spec.frictions.len())];
+        let border = spec.borders[axis_index(spec.borders.len())];
+        let amount = spec.amounts[axis_index(spec.amounts.len())];
+
+        Some(Parameters {
+            amount,
+            border,
+            friction,
+            timestep,
+            gravity_constant,
+            force_exponent,
+            attraction_constant,
+            repulsion_constant,
+            max_velocity,
+            position_bucket_size,
+            velocity_bucket_size,
+            ..self.base.clone()
+        })
+    }
 
-        parameter_space
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.size - self.next;
+        (remaining, Some(remaining))
     }
 }
 
+impl ExactSizeIterator for ParameterSpace {}
+
 #[cfg(test)]
 mod tests {
     pub use super::*;
@@ -181,32 +1028,20 @@ mod tests {
 
     fn test_parameters() -> Parameters {
         Parameters {
+            run_id: None,
             amount: 10,
             border: 200.0,
             friction: 0.0,
             timestep: 0.0002,
             gravity_constant: 1.0,
             particle_parameters: vec![
+                ParticleParameters::new(0, 3.0),
                 ParticleParameters {
-                    id: None,
-                    mass: 3.0,
-                    index: 0,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 250.0,
-                    index: 1,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 10000.0,
-                    index: 2,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 10000.0,
-                    index: 3,
+                    color: Some(Srgba::new(10, 20, 30, 255)),
+                    ..ParticleParameters::new(1, 250.0)
                 },
+                ParticleParameters::new(2, 10000.0),
+                ParticleParameters::new(3, 10000.0),
             ],
             interactions: vec![
                 InteractionType::Attraction, // 0 <-> 0
@@ -221,8 +1056,233 @@ mod tests {
                 InteractionType::Repulsion,  // 3 <-> 3
             ],
             max_velocity: 20000.0,
-            bucket_size: 10.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 10.0,
+            sample_every: 1,
+            seed: None,
+            convergence_interval: 0,
+            convergence_threshold: 0.01,
+            temperature: 0.0,
+            velocity_clamp: VelocityClamp::PerAxis,
+            boundary_shape: BoundaryShape::Sphere,
+            sphere_subdivisions: 16,
+            force_exponent: 2.0,
+            attraction_constant: 1.0,
+            repulsion_constant: 1.0,
+            force_law: ForceLaw::InversePower,
+            recenter: false,
+        }
+    }
+
+    #[test]
+    fn test_random_interactions() {
+        for n in 0..5 {
+            let interactions = Parameters::random_interactions(n, 3);
+
+            assert_eq!(interactions.len(), n * (n + 1) / 2);
+            assert_eq!(interactions, Parameters::random_interactions(n, 3));
+        }
+    }
+
+    #[test]
+    fn test_random_parameter_space() {
+        let base = test_parameters();
+
+        let parameter_space = Parameters::random_parameter_space(20, 5, &base);
+
+        assert_eq!(parameter_space.len(), 20);
+        assert_eq!(
+            parameter_space,
+            Parameters::random_parameter_space(20, 5, &base)
+        );
+        for parameters in &parameter_space {
+            assert_eq!(parameters.validate(), Ok(()));
+            assert_eq!(
+                parameters.interactions,
+                Parameters::random_interactions(
+                    base.particle_parameters.len(),
+                    parameters.seed.unwrap()
+                )
+            );
+        }
+        assert!(parameter_space
+            .iter()
+            .any(|parameters| parameters.interactions != parameter_space[0].interactions));
+    }
+
+    #[test]
+    fn test_latin_hypercube_sample() {
+        let spec = SweepSpec::default();
+        let count = 50;
+
+        let sample = Parameters::latin_hypercube_sample(count, 11);
+
+        assert_eq!(sample.len(), count);
+        assert_eq!(sample, Parameters::latin_hypercube_sample(count, 11));
+        for (i, parameters) in sample.iter().enumerate() {
+            assert!(sample[i + 1..].iter().all(|other| other != parameters));
         }
+
+        let within = |value: f32, values: &[f32]| {
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            (min..=max).contains(&value)
+        };
+        let amounts = spec.amounts.iter().map(|a| *a as f32).collect::<Vec<_>>();
+        for parameters in &sample {
+            assert!(within(parameters.amount as f32, &amounts));
+            assert!(within(parameters.border, &spec.borders));
+            assert!(within(parameters.friction, &spec.frictions));
+            assert!(within(parameters.timestep, &spec.timesteps));
+            assert!(within(parameters.gravity_constant, &spec.gravity_constants));
+            assert!(within(parameters.force_exponent, &spec.force_exponents));
+            assert!(within(
+                parameters.attraction_constant,
+                &spec.attraction_constants
+            ));
+            assert!(within(
+                parameters.repulsion_constant,
+                &spec.repulsion_constants
+            ));
+            assert!(within(parameters.max_velocity, &spec.max_velocities));
+            assert!(within(
+                parameters.position_bucket_size,
+                &spec.position_bucket_sizes
+            ));
+            assert!(within(
+                parameters.velocity_bucket_size,
+                &spec.velocity_bucket_sizes
+            ));
+        }
+
+        // Every stratum of an axis is hit exactly once
+        let mut strata = sample
+            .iter()
+            .map(|p| (p.friction / 0.01 * count as f32) as usize)
+            .collect::<Vec<_>>();
+        strata.sort_unstable();
+        assert_eq!(strata, (0..count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sweep_spec_serde_round_trip() {
+        let spec = SweepSpec {
+            amounts: vec![10, 20],
+            borders: vec![300.0],
+            frictions: vec![0.0, 0.001, 0.5],
+            timesteps: vec![0.0001],
+            gravity_constants: vec![1.5],
+            force_exponents: vec![1.0, 2.5],
+            attraction_constants: vec![0.5, 1.0],
+            repulsion_constants: vec![2.0],
+            max_velocities: vec![1000.0, 2000.0],
+            position_bucket_sizes: vec![4.0],
+            velocity_bucket_sizes: vec![100.0, 1000.0],
+        };
+
+        assert_eq!(SweepSpec::from_toml(&spec.to_toml()), Ok(spec.clone()));
+        assert_eq!(SweepSpec::from_json(&spec.to_json()), Ok(spec));
+    }
+
+    #[test]
+    fn test_parameter_space_iterates_in_nested_loop_order() {
+        let spec = SweepSpec::default();
+        let base = test_parameters();
+
+        let mut parameter_space = Parameters::parameter_space_from_spec(&spec, &base);
+
+        assert_eq!(parameter_space.len(), spec.size());
+        let first = Parameters {
+            amount: spec.amounts[0],
+            border: spec.borders[0],
+            friction: spec.frictions[0],
+            timestep: spec.timesteps[0],
+            gravity_constant: spec.gravity_constants[0],
+            force_exponent: spec.force_exponents[0],
+            attraction_constant: spec.attraction_constants[0],
+            repulsion_constant: spec.repulsion_constants[0],
+            max_velocity: spec.max_velocities[0],
+            position_bucket_size: spec.position_bucket_sizes[0],
+            velocity_bucket_size: spec.velocity_bucket_sizes[0],
+            ..base.clone()
+        };
+        assert_eq!(parameter_space.next(), Some(first.clone()));
+        // The default sweep has a single velocity bucket size, so the position bucket size is
+        // the fastest varying axis
+        assert_eq!(
+            parameter_space.next(),
+            Some(Parameters {
+                position_bucket_size: spec.position_bucket_sizes[1],
+                ..first
+            })
+        );
+        assert_eq!(parameter_space.len(), spec.size() - 2);
+        let last = parameter_space.last().unwrap();
+        assert_eq!(
+            last,
+            Parameters {
+                amount: *spec.amounts.last().unwrap(),
+                border: *spec.borders.last().unwrap(),
+                friction: *spec.frictions.last().unwrap(),
+                timestep: *spec.timesteps.last().unwrap(),
+                gravity_constant: *spec.gravity_constants.last().unwrap(),
+                force_exponent: *spec.force_exponents.last().unwrap(),
+                attraction_constant: *spec.attraction_constants.last().unwrap(),
+                repulsion_constant: *spec.repulsion_constants.last().unwrap(),
+                max_velocity: *spec.max_velocities.last().unwrap(),
+                position_bucket_size: *spec.position_bucket_sizes.last().unwrap(),
+                velocity_bucket_size: *spec.velocity_bucket_sizes.last().unwrap(),
+                ..base
+            }
+        );
+    }
+
+    #[test]
+    fn test_sweep_spec_size_matches_parameter_space() {
+        let spec = SweepSpec::default();
+
+        let parameter_space = Parameters::parameter_space_from_spec(&spec, &test_parameters());
+
+        assert_eq!(spec.size(), 4 * 3 * 3 * 2 * 3 * 3 * 2 * 2 * 3 * 5);
+        assert_eq!(parameter_space.len(), spec.size());
+    }
+
+    #[test]
+    fn test_sweep_spec_rejects_empty_axis() {
+        let spec = SweepSpec {
+            timesteps: vec![],
+            ..SweepSpec::default()
+        };
+
+        assert_eq!(
+            spec.validate(),
+            Err("Sweep axis timesteps has no values".to_string())
+        );
+        assert_eq!(
+            SweepSpec::from_json(&spec.to_json()),
+            Err("Sweep axis timesteps has no values".to_string())
+        );
+    }
+
+    #[test]
+    fn test_interaction_type_from_str() {
+        for interaction_type in [
+            InteractionType::Attraction,
+            InteractionType::Repulsion,
+            InteractionType::Neutral,
+        ] {
+            assert_eq!(interaction_type.to_string().parse(), Ok(interaction_type));
+        }
+        assert_eq!("repulsion".parse(), Ok(InteractionType::Repulsion));
+        assert_eq!("NEUTRAL".parse(), Ok(InteractionType::Neutral));
+        assert_eq!(
+            "Attract".parse::<InteractionType>(),
+            Err(
+                "Expected interaction type Attraction, Repulsion or Neutral, found Attract"
+                    .to_string()
+            )
+        );
+        assert!("".parse::<InteractionType>().is_err());
     }
 
     #[test]
@@ -270,4 +1330,359 @@ mod tests {
             "Index out of bounds"
         );
     }
+
+    #[test]
+    fn test_interaction_by_indices_few_particle_kinds() {
+        let mut parameters = test_parameters();
+        parameters.particle_parameters.truncate(1);
+        parameters.interactions.truncate(1);
+
+        assert_eq!(
+            parameters.interaction_by_indices(0, 0).unwrap(),
+            InteractionType::Attraction
+        );
+        assert_eq!(
+            parameters.interaction_by_indices(0, 1).unwrap_err(),
+            "Index out of bounds"
+        );
+
+        parameters.particle_parameters.clear();
+        parameters.interactions.clear();
+
+        assert_eq!(
+            parameters.interaction_by_indices(0, 0).unwrap_err(),
+            "No particle kinds"
+        );
+    }
+
+    #[test]
+    fn test_particle_parameters_ids_match_lookup_by_index() {
+        let mut parameters = test_parameters();
+        for (i, particle_params) in parameters.particle_parameters.iter_mut().enumerate() {
+            particle_params.id = Some(10 * i + 3);
+        }
+        // Kinds aren't necessarily stored in the order of their indices
+        parameters.particle_parameters.reverse();
+
+        let ids = parameters.particle_parameters_ids();
+
+        assert_eq!(ids.len(), parameters.particle_parameters.len());
+        for (index, id) in ids.iter().enumerate() {
+            assert_eq!(
+                *id,
+                parameters
+                    .particle_parameters_by_index(index)
+                    .and_then(|p| p.id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_particle_kind() {
+        let original = test_parameters();
+        let mut parameters = original.clone();
+
+        parameters.add_particle_kind(42.0);
+
+        assert_eq!(parameters.validate(), Ok(()));
+        assert_eq!(parameters.interactions.len(), 15);
+        assert_eq!(parameters.particle_parameters[4].index, 4);
+        assert_eq!(parameters.particle_parameters[4].mass, 42.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_eq!(
+                    parameters.interaction_by_indices(i, j),
+                    original.interaction_by_indices(i, j)
+                );
+            }
+            assert_eq!(
+                parameters.interaction_by_indices(i, 4),
+                Ok(InteractionType::Neutral)
+            );
+        }
+        assert_eq!(
+            parameters.interaction_by_indices(4, 4),
+            Ok(InteractionType::Neutral)
+        );
+    }
+
+    #[test]
+    fn test_remove_particle_kind() {
+        let original = test_parameters();
+        let mut parameters = original.clone();
+
+        let removed = parameters.remove_particle_kind().unwrap();
+
+        assert_eq!(removed.index, 3);
+        assert_eq!(parameters.validate(), Ok(()));
+        assert_eq!(parameters.interactions.len(), 6);
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_eq!(
+                    parameters.interaction_by_indices(i, j),
+                    original.interaction_by_indices(i, j)
+                );
+            }
+        }
+
+        parameters.add_particle_kind(removed.mass);
+        parameters.remove_particle_kind();
+        assert_eq!(
+            parameters,
+            Parameters {
+                particle_parameters: original.particle_parameters[..3].to_vec(),
+                interactions: parameters.interactions.clone(),
+                ..original
+            }
+        );
+
+        while parameters.remove_particle_kind().is_some() {}
+        assert!(parameters.particle_parameters.is_empty());
+        assert!(parameters.interactions.is_empty());
+    }
+
+    #[test]
+    fn test_parameters_builder() {
+        let interaction = |i: usize, j: usize| match (i + j) % 3 {
+            0 => InteractionType::Attraction,
+            1 => InteractionType::Repulsion,
+            _ => InteractionType::Neutral,
+        };
+
+        let parameters = Parameters::builder()
+            .kind(3.0)
+            .kind(250.0)
+            .colored_kind(1000.0, Srgba::new(10, 20, 30, 255))
+            .kind(10000.0)
+            .interactions(interaction)
+            .interaction(3, 1, InteractionType::Neutral)
+            .amount(42)
+            .seed(7)
+            .build()
+            .unwrap();
+
+        assert_eq!(parameters.amount, 42);
+        assert_eq!(parameters.seed, Some(7));
+        assert_eq!(parameters.interactions.len(), 10);
+        assert_eq!(
+            parameters.particle_parameters[2].color,
+            Some(Srgba::new(10, 20, 30, 255))
+        );
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if (i, j) == (1, 3) || (i, j) == (3, 1) {
+                    InteractionType::Neutral
+                } else {
+                    interaction(i, j)
+                };
+                assert_eq!(parameters.interaction_by_indices(i, j), Ok(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parameters_builder_rejects_incomplete_interactions() {
+        let missing = Parameters::builder()
+            .kind(3.0)
+            .kind(250.0)
+            .interaction(0, 0, InteractionType::Attraction)
+            .interaction(1, 0, InteractionType::Attraction)
+            .build();
+        let asymmetric = Parameters::builder()
+            .kind(3.0)
+            .kind(250.0)
+            .interactions(|i, _| {
+                if i == 0 {
+                    InteractionType::Attraction
+                } else {
+                    InteractionType::Repulsion
+                }
+            })
+            .build();
+
+        assert_eq!(
+            missing,
+            Err("No interaction given for particle kinds 1 and 1".to_string())
+        );
+        assert_eq!(
+            asymmetric,
+            Err("Interaction of particle kinds 0 and 1 is not symmetric".to_string())
+        );
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let parameters = test_parameters();
+
+        let parsed = Parameters::from_toml(&parameters.to_toml()).unwrap();
+
+        assert_eq!(parsed, parameters);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let parameters = test_parameters();
+
+        let parsed = Parameters::from_json(&parameters.to_json()).unwrap();
+
+        assert_eq!(parsed, parameters);
+    }
+
+    #[test]
+    fn test_validate_too_few_interactions() {
+        let mut parameters = test_parameters();
+        parameters.interactions.pop();
+
+        assert_eq!(
+            parameters.validate().unwrap_err(),
+            "Expected 10 interactions for 4 particle kinds, found 9"
+        );
+    }
+
+    #[test]
+    fn test_validate_too_many_interactions() {
+        let mut parameters = test_parameters();
+        parameters.interactions.push(InteractionType::Neutral);
+
+        assert_eq!(
+            parameters.validate().unwrap_err(),
+            "Expected 10 interactions for 4 particle kinds, found 11"
+        );
+    }
+
+    #[test]
+    fn test_validate_friction() {
+        for friction in [0.0, 0.5, 1.0] {
+            let parameters = Parameters {
+                friction,
+                ..test_parameters()
+            };
+            assert_eq!(parameters.validate(), Ok(()));
+        }
+
+        for friction in [-0.1, 2.0, f32::NAN] {
+            let parameters = Parameters {
+                friction,
+                ..test_parameters()
+            };
+            assert_eq!(
+                parameters.validate().unwrap_err(),
+                format!("Expected friction between 0 and 1, found {}", friction)
+            );
+        }
+
+        let spec = SweepSpec {
+            frictions: vec![0.0, 2.0],
+            ..SweepSpec::default()
+        };
+        assert_eq!(
+            spec.validate().unwrap_err(),
+            "Expected friction between 0 and 1, found 2"
+        );
+    }
+
+    #[test]
+    fn test_validate_particle_kind_indices() {
+        assert_eq!(test_parameters().validate(), Ok(()));
+
+        let mut parameters = test_parameters();
+        parameters.particle_parameters[3].index = 1;
+
+        assert_eq!(
+            parameters.validate().unwrap_err(),
+            "Expected particle kind indices 0 to 3, found [0, 1, 1, 2]"
+        );
+
+        parameters.particle_parameters[3].index = 4;
+
+        assert_eq!(
+            parameters.validate().unwrap_err(),
+            "Expected particle kind indices 0 to 3, found [0, 1, 2, 4]"
+        );
+    }
+
+    #[test]
+    fn test_validate_sphere_subdivisions() {
+        let parameters = Parameters {
+            sphere_subdivisions: 1,
+            ..test_parameters()
+        };
+
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected at least 2 sphere subdivisions, found 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_collision_radius() {
+        let mut parameters = test_parameters();
+        parameters.particle_parameters[1].collision_radius = Some(-1.0);
+
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected a positive collision radius for particle kind 1, found -1".to_string())
+        );
+
+        parameters.particle_parameters[1].collision_radius = Some(f32::NAN);
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected a positive collision radius for particle kind 1, found NaN".to_string())
+        );
+
+        parameters.particle_parameters[1].collision_radius = Some(2.5);
+        assert_eq!(Parameters::from_toml(&parameters.to_toml()), Ok(parameters));
+    }
+
+    #[test]
+    fn test_validate_force_constants() {
+        let parameters = Parameters {
+            repulsion_constant: -1.0,
+            ..test_parameters()
+        };
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected a non-negative repulsion constant, found -1".to_string())
+        );
+
+        let parameters = Parameters {
+            attraction_constant: f32::NAN,
+            ..test_parameters()
+        };
+        assert!(parameters.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_placement_radius() {
+        let mut parameters = test_parameters();
+        parameters.particle_parameters[2].placement = Placement::Cluster {
+            center: [0.0, 0.0, 0.0],
+            radius: 0.0,
+        };
+
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected a positive placement radius for particle kind 2, found 0".to_string())
+        );
+
+        parameters.particle_parameters[2].placement = Placement::Shell { radius: f32::NAN };
+        assert_eq!(
+            parameters.validate(),
+            Err("Expected a positive placement radius for particle kind 2, found NaN".to_string())
+        );
+
+        parameters.particle_parameters[2].placement = Placement::Shell { radius: 50.0 };
+        assert_eq!(Parameters::from_toml(&parameters.to_toml()), Ok(parameters));
+    }
+
+    #[test]
+    fn test_from_toml_invalid_interactions() {
+        let mut parameters = test_parameters();
+        parameters.interactions.pop();
+
+        assert_eq!(
+            Parameters::from_toml(&parameters.to_toml()).unwrap_err(),
+            "Expected 10 interactions for 4 particle kinds, found 9"
+        );
+    }
 }
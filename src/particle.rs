@@ -1,112 +1,286 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
 use three_d::{vec3, InnerSpace, Vector3};
 
-use crate::parameters::{InteractionType, Parameters};
-use crate::sphere::PositionableRender;
+use crate::parameters::{
+    BoundaryShape, ForceLaw, InteractionType, Parameters, Placement, VelocityClamp, VelocityInit,
+};
 
 pub struct Particle {
     pub index: usize,
     pub position: Vector3<f32>,
-    pub positionable: Option<Box<dyn PositionableRender>>,
     pub mass: f32,
     velocity: Vector3<f32>,
     max_velocity: f32,
 }
 
 impl Particle {
-    pub fn new(
+    pub fn new<R: Rng + ?Sized>(
         index: usize,
-        mut positionable: Option<Box<dyn PositionableRender>>,
-        border: f32,
+        position: Vector3<f32>,
+        velocity_init: &VelocityInit,
         mass: f32,
         max_velocity: f32,
+        rng: &mut R,
     ) -> Self {
-        // generate random position in the range of -1 to +1 times factor
-        let x = (rand::random::<f32>() - 0.5) * border;
-        let y = (rand::random::<f32>() - 0.5) * border;
-        let z = (rand::random::<f32>() - 0.5) * border;
-        let position = vec3(x, y, z);
-
-        if let Some(positionable) = &mut positionable {
-            positionable.set_position(position);
+        let velocity = match *velocity_init {
+            VelocityInit::Zero => vec3(0.0, 0.0, 0.0),
+            VelocityInit::Uniform => {
+                let vx = (rng.gen::<f32>() - 0.5) * max_velocity;
+                let vy = (rng.gen::<f32>() - 0.5) * max_velocity;
+                let vz = (rng.gen::<f32>() - 0.5) * max_velocity;
+                vec3(vx, vy, vz)
+            }
+            VelocityInit::Radial { speed } => normalize_or_zero(position) * speed,
+            VelocityInit::Tangential { speed } => {
+                // Rotate around the z axis, or around the x axis for positions on the z axis
+                let axis = if position.x == 0.0 && position.y == 0.0 {
+                    vec3(1.0, 0.0, 0.0)
+                } else {
+                    vec3(0.0, 0.0, 1.0)
+                };
+                normalize_or_zero(axis.cross(position)) * speed
+            }
+        };
+
+        Self {
+            index,
+            position,
+            velocity,
+            mass,
+            max_velocity,
         }
+    }
 
-        // initialize random velocity from 0 top max_velocity
-        let vx = (rand::random::<f32>() - 0.5) * max_velocity;
-        let vy = (rand::random::<f32>() - 0.5) * max_velocity;
-        let vz = (rand::random::<f32>() - 0.5) * max_velocity;
+    /// Returns the starting position of the `i`th of `amount` particles of a kind placed with the
+    /// given strategy.
+    pub fn initial_position<R: Rng + ?Sized>(
+        placement: &Placement,
+        i: usize,
+        amount: usize,
+        border: f32,
+        rng: &mut R,
+    ) -> Vector3<f32> {
+        match *placement {
+            Placement::Uniform => {
+                let x = (rng.gen::<f32>() - 0.5) * border;
+                let y = (rng.gen::<f32>() - 0.5) * border;
+                let z = (rng.gen::<f32>() - 0.5) * border;
+                vec3(x, y, z)
+            }
+            Placement::Shell { radius } => random_direction(rng) * radius,
+            Placement::Cluster { center, radius } => {
+                // The cube root spreads the particles uniformly over the volume of the ball
+                let distance = radius * rng.gen::<f32>().cbrt();
+                vec3(center[0], center[1], center[2]) + random_direction(rng) * distance
+            }
+            Placement::Grid => {
+                let nodes_per_axis = (1..).find(|n| n * n * n >= amount).unwrap();
+                let spacing = border / nodes_per_axis as f32;
+                let coordinate = |node: usize| (node as f32 + 0.5) * spacing - border / 2.0;
+                vec3(
+                    coordinate(i % nodes_per_axis),
+                    coordinate(i / nodes_per_axis % nodes_per_axis),
+                    coordinate(i / (nodes_per_axis * nodes_per_axis)),
+                )
+            }
+        }
+    }
 
+    /// Creates a particle without geometry from an explicit state.
+    pub fn from_state(
+        index: usize,
+        position: Vector3<f32>,
+        velocity: Vector3<f32>,
+        mass: f32,
+        max_velocity: f32,
+    ) -> Self {
         Self {
             index,
             position,
-            velocity: vec3(vx, vy, vz),
             mass,
-            positionable,
+            velocity,
             max_velocity,
         }
     }
 
+    /// Accelerates the particle towards or away from another one according to the force law of
+    /// the parameters and returns whether the velocity had to be clamped to `max_velocity`
+    /// afterwards.
     pub fn update_velocity(
         &mut self,
         other_position: Vector3<f32>,
         other_mass: f32,
         interaction_type: InteractionType,
-        gravity_constant: f32,
-    ) {
+        parameters: &Parameters,
+    ) -> bool {
         if interaction_type == InteractionType::Neutral {
-            return;
+            return false;
         }
 
         let direction = other_position - self.position;
         let distance = direction.magnitude();
         if distance > 0.0001 {
-            let force_magnitude = gravity_constant * self.mass * other_mass / (distance * distance);
-            let force = direction.normalize() * force_magnitude;
+            let force_magnitude = pair_force(
+                distance,
+                self.mass,
+                other_mass,
+                interaction_type,
+                parameters,
+            );
+            self.velocity += direction.normalize() * force_magnitude / self.mass;
 
-            if interaction_type == InteractionType::Attraction {
-                self.velocity += force / self.mass;
-            } else {
-                self.velocity -= force / self.mass;
-            }
+            self.clamp_velocity(parameters.velocity_clamp)
+        } else {
+            false
+        }
+    }
 
-            if self.velocity.x.abs() > self.max_velocity {
-                self.velocity.x = self.velocity.x.signum() * self.max_velocity;
+    /// Returns whether the velocity exceeded `max_velocity` and was clamped.
+    fn clamp_velocity(&mut self, velocity_clamp: VelocityClamp) -> bool {
+        match velocity_clamp {
+            VelocityClamp::PerAxis => {
+                let mut clamped = false;
+                for axis in 0..3 {
+                    if self.velocity[axis].abs() > self.max_velocity {
+                        self.velocity[axis] = self.velocity[axis].signum() * self.max_velocity;
+                        clamped = true;
+                    }
+                }
+                clamped
             }
-
-            if self.velocity.y.abs() > self.max_velocity {
-                self.velocity.y = self.velocity.y.signum() * self.max_velocity;
+            VelocityClamp::Magnitude => {
+                let speed = self.velocity.magnitude();
+                if speed > self.max_velocity {
+                    self.velocity *= self.max_velocity / speed;
+                    return true;
+                }
+                false
             }
+        }
+    }
 
-            if self.velocity.z.abs() > self.max_velocity {
-                self.velocity.z = self.velocity.z.signum() * self.max_velocity;
-            }
+    /// Resolves an elastic collision with another particle at the given position, e.g. its
+    /// nearest periodic image, by exchanging momentum along the line between their centers.
+    /// Momentum and kinetic energy are conserved. Particles that already move apart are left
+    /// alone, so a pair that still overlaps after colliding doesn't collide again.
+    pub fn collide(&mut self, other: &mut Particle, other_position: Vector3<f32>) {
+        let normal = normalize_or_zero(other_position - self.position);
+        let approach_speed = (self.velocity - other.velocity).dot(normal);
+        if approach_speed <= 0.0 {
+            return;
+        }
+        let impulse = 2.0 * approach_speed / (1.0 / self.mass + 1.0 / other.mass);
+        self.velocity -= normal * (impulse / self.mass);
+        other.velocity += normal * (impulse / other.mass);
+    }
+
+    /// Resolves an elastic collision with an immovable obstacle at the given position, e.g. a
+    /// frozen particle, which acts like an infinite mass: the velocity is mirrored along the line
+    /// between their centers. Like in `collide`, a particle moving away is left alone.
+    pub fn bounce_off(&mut self, obstacle_position: Vector3<f32>) {
+        let normal = normalize_or_zero(obstacle_position - self.position);
+        let approach_speed = self.velocity.dot(normal);
+        if approach_speed <= 0.0 {
+            return;
         }
+        self.velocity -= normal * (2.0 * approach_speed);
     }
 
+    /// Brings the particle to rest, e.g. because its kind is frozen.
+    pub fn stop(&mut self) {
+        self.velocity = vec3(0.0, 0.0, 0.0);
+    }
+
+    pub fn velocity(&self) -> Vector3<f32> {
+        self.velocity
+    }
+
+    pub fn max_velocity(&self) -> f32 {
+        self.max_velocity
+    }
+
+    pub fn kinetic_energy(&self) -> f32 {
+        0.5 * self.mass * self.velocity.magnitude2()
+    }
+
+    /// Changes the velocity by the given amount, e.g. due to thermal noise.
+    pub fn kick(&mut self, delta_velocity: Vector3<f32>) {
+        self.velocity += delta_velocity;
+    }
+
+    /// Scales the velocity down by the given fraction, which is clamped to `[0, 1]` so that
+    /// friction can never amplify or flip the velocity.
     pub fn apply_friction(&mut self, friction: f32) {
-        self.velocity *= 1.0 - friction;
+        self.velocity *= 1.0 - friction.clamp(0.0, 1.0);
     }
 
     pub fn update_position(&mut self, parameters: &Parameters) {
         let mut updated_position = self.compute_updated_position(parameters.timestep);
 
-        let distance_from_center = updated_position.magnitude();
+        match parameters.boundary_shape {
+            BoundaryShape::Sphere => {
+                let distance_from_center = updated_position.magnitude();
 
-        if distance_from_center.abs() > parameters.border {
-            self.velocity = -self.velocity;
-            updated_position = self.compute_updated_position(parameters.timestep);
+                // Like a wall, only the radial component is reversed and the tangential one kept.
+                // Particles already heading back inside are left alone.
+                if distance_from_center > parameters.border {
+                    let normal = updated_position / distance_from_center;
+                    let radial_speed = self.velocity.dot(normal);
+                    if radial_speed > 0.0 {
+                        self.velocity -= normal * (2.0 * radial_speed);
+                        updated_position = self.compute_updated_position(parameters.timestep);
+                    }
+                }
+                // A particle that overshot far in one step can still be outside after the
+                // reflection, so it is put back onto the surface
+                let distance_from_center = updated_position.magnitude();
+                if distance_from_center > parameters.border {
+                    updated_position *= parameters.border / distance_from_center;
+                }
+            }
+            BoundaryShape::Box => {
+                let mut reflected = false;
+                for axis in 0..3 {
+                    if updated_position[axis].abs() > parameters.border {
+                        self.velocity[axis] = -self.velocity[axis];
+                        reflected = true;
+                    }
+                }
+                if reflected {
+                    updated_position = self.compute_updated_position(parameters.timestep);
+                }
+                // See the sphere, the reflected position can still be outside after an overshoot
+                for axis in 0..3 {
+                    updated_position[axis] =
+                        updated_position[axis].clamp(-parameters.border, parameters.border);
+                }
+            }
+            BoundaryShape::Periodic => {
+                let period = 2.0 * parameters.border;
+                for axis in 0..3 {
+                    updated_position[axis] = (updated_position[axis] + parameters.border)
+                        .rem_euclid(period)
+                        - parameters.border;
+                }
+            }
         }
 
         self.position = updated_position;
-        if let Some(positionable) = &mut self.positionable {
-            positionable.set_position(self.position);
-        }
     }
 
-    pub fn to_state_vector(&self, bucket_size: f32, particle_parameters_id: usize) -> StateVector {
+    pub fn to_state_vector(
+        &self,
+        position_bucket_size: f32,
+        velocity_bucket_size: f32,
+        particle_parameters_id: usize,
+    ) -> StateVector {
         StateVector::new(
             (self.position.x, self.position.y, self.position.z),
             (self.velocity.x, self.velocity.y, self.velocity.z),
-            bucket_size,
+            self.mass,
+            position_bucket_size,
+            velocity_bucket_size,
             particle_parameters_id,
         )
     }
@@ -116,72 +290,283 @@ impl Particle {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub struct StateVector {
     pub particle_parameters_id: usize,
+    /// Mass rounded to the nearest integer
+    pub mass: i32,
     pub position_bucket: (i32, i32, i32),
     pub velocity_bucket: (i32, i32, i32),
 }
 
 impl StateVector {
+    /// Bucket `k` of an axis covers `[k * bucket_size, (k + 1) * bucket_size)`, also for negative
+    /// coordinates.
     pub fn new(
         position: (f32, f32, f32),
         velocity: (f32, f32, f32),
-        bucket_size: f32,
+        mass: f32,
+        position_bucket_size: f32,
+        velocity_bucket_size: f32,
         particle_parameters_id: usize,
     ) -> Self {
         Self {
+            mass: mass.round() as i32,
             position_bucket: (
-                (position.0 / bucket_size) as i32,
-                (position.1 / bucket_size) as i32,
-                (position.2 / bucket_size) as i32,
+                (position.0 / position_bucket_size).floor() as i32,
+                (position.1 / position_bucket_size).floor() as i32,
+                (position.2 / position_bucket_size).floor() as i32,
             ),
             velocity_bucket: (
-                (velocity.0 / bucket_size) as i32,
-                (velocity.1 / bucket_size) as i32,
-                (velocity.2 / bucket_size) as i32,
+                (velocity.0 / velocity_bucket_size).floor() as i32,
+                (velocity.1 / velocity_bucket_size).floor() as i32,
+                (velocity.2 / velocity_bucket_size).floor() as i32,
             ),
             particle_parameters_id,
         }
     }
+
+    /// Returns the lower corner of the position bucket, i.e. the original position rounded down
+    /// to a multiple of the bucket size on every axis.
+    pub fn approx_position(&self, bucket_size: f32) -> (f32, f32, f32) {
+        bucket_corner(self.position_bucket, bucket_size)
+    }
+
+    /// Returns the lower corner of the velocity bucket, like `approx_position`.
+    pub fn approx_velocity(&self, bucket_size: f32) -> (f32, f32, f32) {
+        bucket_corner(self.velocity_bucket, bucket_size)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use three_d::{Gm, Mesh, PhysicalMaterial};
+fn bucket_corner(bucket: (i32, i32, i32), bucket_size: f32) -> (f32, f32, f32) {
+    (
+        bucket.0 as f32 * bucket_size,
+        bucket.1 as f32 * bucket_size,
+        bucket.2 as f32 * bucket_size,
+    )
+}
 
-    use crate::parameters::ParticleParameters;
+/// Returns the magnitude of the force between two particles at the given distance according to
+/// the force law of the parameters, positive when it pulls them towards each other.
+pub fn pair_force(
+    distance: f32,
+    mass: f32,
+    other_mass: f32,
+    interaction_type: InteractionType,
+    parameters: &Parameters,
+) -> f32 {
+    match parameters.force_law {
+        ForceLaw::InversePower => {
+            let magnitude = parameters.gravity_constant * mass * other_mass
+                / distance_power(distance, parameters.force_exponent);
+            match interaction_type {
+                InteractionType::Attraction => parameters.attraction_constant * magnitude,
+                InteractionType::Repulsion => -parameters.repulsion_constant * magnitude,
+                InteractionType::Neutral => 0.0,
+            }
+        }
+        ForceLaw::LennardJones { epsilon, sigma } => {
+            -lennard_jones_force(distance, epsilon, sigma, interaction_type)
+        }
+    }
+}
 
-    use super::*;
-    use pretty_assertions_sorted::assert_eq;
+/// Distance in units of `sigma` beyond which the Lennard-Jones force is cut off, where it has
+/// fallen below 2 % of the depth of its potential well.
+pub const LENNARD_JONES_CUTOFF: f32 = 2.5;
+
+/// Returns the Lennard-Jones force `48 ε σ¹² / r¹³ - 24 ε σ⁶ / r⁷` at the given distance, positive
+/// when it repels. It vanishes at the equilibrium distance `2^(1/6) * sigma` and beyond the
+/// cutoff. Pairs interacting by repulsion only feel the repulsive term.
+pub fn lennard_jones_force(
+    distance: f32,
+    epsilon: f32,
+    sigma: f32,
+    interaction_type: InteractionType,
+) -> f32 {
+    if distance > LENNARD_JONES_CUTOFF * sigma {
+        return 0.0;
+    }
+    let sigma_6 = (sigma / distance).powi(6);
+    let repulsion = 48.0 * epsilon * sigma_6 * sigma_6 / distance;
+    match interaction_type {
+        InteractionType::Attraction => repulsion - 24.0 * epsilon * sigma_6 / distance,
+        InteractionType::Repulsion => repulsion,
+        InteractionType::Neutral => 0.0,
+    }
+}
 
-    struct MockPositionableRender;
+/// Returns `distance^exponent`, multiplying directly for the inverse-square default as that is
+/// much cheaper than `powf`.
+fn distance_power(distance: f32, exponent: f32) -> f32 {
+    if exponent == 2.0 {
+        distance * distance
+    } else {
+        distance.powf(exponent)
+    }
+}
+
+/// Returns the unit vector in the direction of `vector`, or zero for the zero vector.
+fn normalize_or_zero(vector: Vector3<f32>) -> Vector3<f32> {
+    let magnitude = vector.magnitude();
+    if magnitude > f32::EPSILON {
+        vector / magnitude
+    } else {
+        vec3(0.0, 0.0, 0.0)
+    }
+}
 
-    impl PositionableRender for MockPositionableRender {
-        fn set_position(&mut self, _position: Vector3<f32>) {
-            // Do nothing
+/// Returns a unit vector pointing in a uniformly random direction.
+fn random_direction<R: Rng + ?Sized>(rng: &mut R) -> Vector3<f32> {
+    loop {
+        let direction = vec3(
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+            rng.sample::<f32, _>(StandardNormal),
+        );
+        let magnitude = direction.magnitude();
+        if magnitude > f32::EPSILON {
+            return direction / magnitude;
         }
+    }
+}
+
+/// Information about the whole frame available when mapping a single particle onto a state
+/// vector.
+pub struct FrameContext<'a> {
+    pub parameters: &'a Parameters,
+    pub center_of_mass: Vector3<f32>,
+    /// Persisted id of each particle kind by its index, see `Parameters::particle_parameters_ids`
+    particle_parameters_ids: &'a [Option<usize>],
+}
 
-        fn get_geometry(&self) -> &Gm<Mesh, PhysicalMaterial> {
-            todo!()
+impl<'a> FrameContext<'a> {
+    /// Creates the context of a frame. The ids of the particle kinds are passed in so a run can
+    /// look them up once instead of in every frame.
+    ///
+    /// Panics if a kind has no id, i.e. the parameters weren't persisted before the run.
+    pub fn new(
+        parameters: &'a Parameters,
+        particle_parameters_ids: &'a [Option<usize>],
+        particles: &[Particle],
+    ) -> Self {
+        assert!(
+            particle_parameters_ids.iter().all(Option::is_some),
+            "Particle parameters must be persisted before their state vectors are mapped"
+        );
+        Self {
+            parameters,
+            center_of_mass: center_of_mass(particles),
+            particle_parameters_ids,
+        }
+    }
+
+    /// Returns the persisted id of the particle parameters of the given particle's kind.
+    pub fn particle_parameters_id(&self, particle: &Particle) -> usize {
+        self.particle_parameters_ids[particle.index].expect("Checked in FrameContext::new")
+    }
+}
+
+/// Defines which quantities of a particle are bucketed into the persisted state histogram.
+pub trait StateMapper {
+    fn map(&self, particle: &Particle, ctx: &FrameContext) -> StateVector;
+}
+
+/// Buckets the raw cartesian position and velocity of a particle.
+pub struct CartesianMapper;
+
+impl StateMapper for CartesianMapper {
+    fn map(&self, particle: &Particle, ctx: &FrameContext) -> StateVector {
+        particle.to_state_vector(
+            ctx.parameters.position_bucket_size,
+            ctx.parameters.velocity_bucket_size,
+            ctx.particle_parameters_id(particle),
+        )
+    }
+}
+
+/// Tracks the fraction of (particle, step) samples whose velocity was clamped to the velocity
+/// cap. A high fraction means the dynamics are dominated by the clamp rather than by the
+/// interactions.
+#[derive(Default)]
+pub struct MaxVelocityTracker {
+    samples: usize,
+    samples_at_max_velocity: usize,
+}
+
+impl MaxVelocityTracker {
+    /// Records a step of `particles` particles, of which `clamped` had their velocity clamped,
+    /// see `Simulation::last_step_clamp_events`. The clamp is reported by the step itself, as
+    /// friction and thermal noise move the velocity off the cap before the step ends.
+    pub fn record(&mut self, particles: usize, clamped: usize) {
+        self.samples += particles;
+        self.samples_at_max_velocity += clamped;
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
         }
+        self.samples_at_max_velocity as f32 / self.samples as f32
+    }
+}
+
+/// Returns the position of the image of `other_position` closest to `position`. Only periodic
+/// boundaries have images other than the position itself.
+pub fn nearest_image(
+    position: Vector3<f32>,
+    other_position: Vector3<f32>,
+    parameters: &Parameters,
+) -> Vector3<f32> {
+    if parameters.boundary_shape != BoundaryShape::Periodic {
+        return other_position;
+    }
+
+    let period = 2.0 * parameters.border;
+    let mut displacement = other_position - position;
+    for axis in 0..3 {
+        displacement[axis] -= period * (displacement[axis] / period).round();
     }
+    position + displacement
+}
+
+/// Returns the mass weighted mean position of the given particles.
+pub fn center_of_mass(particles: &[Particle]) -> Vector3<f32> {
+    let total_mass: f32 = particles.iter().map(|p| p.mass).sum();
+    if total_mass <= 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+
+    particles
+        .iter()
+        .fold(vec3(0.0, 0.0, 0.0), |sum, p| sum + p.position * p.mass)
+        / total_mass
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::ParticleParameters;
+
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
 
     #[test]
     fn test_new_particle() {
-        let positionable = Box::new(MockPositionableRender);
-        let border = 10.0;
+        let position = vec3(1.0, -2.0, 3.0);
         let mass = 1.0;
         let max_velocity = 1000.0;
 
-        let particle = Particle::new(0, Some(positionable), border, mass, max_velocity);
+        let particle = Particle::new(
+            0,
+            position,
+            &VelocityInit::Uniform,
+            mass,
+            max_velocity,
+            &mut rand::thread_rng(),
+        );
 
         assert_eq!(particle.mass, mass);
-
-        // assert position is within the range of -border/2 to +border/2
-        assert!(particle.position.x >= -border && particle.position.x <= border);
-        assert!(particle.position.y >= -border && particle.position.y <= border);
-        assert!(particle.position.z >= -border && particle.position.z <= border);
+        assert_eq!(particle.position, position);
 
         // assert velocity is within the range of -max_velocity to +max_velocity
         assert!(particle.velocity.x >= -max_velocity && particle.velocity.x <= max_velocity);
@@ -189,12 +574,154 @@ mod tests {
         assert!(particle.velocity.z >= -max_velocity && particle.velocity.z <= max_velocity);
     }
 
+    #[test]
+    fn test_head_on_collision_conserves_momentum_and_energy() {
+        let mut light =
+            Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(30.0, 0.0, 0.0), 1.0, 1000.0);
+        let mut heavy =
+            Particle::from_state(1, vec3(1.5, 0.0, 0.0), vec3(-10.0, 0.0, 0.0), 3.0, 1000.0);
+        let momentum = |a: &Particle, b: &Particle| a.velocity * a.mass + b.velocity * b.mass;
+        let energy = |a: &Particle, b: &Particle| a.kinetic_energy() + b.kinetic_energy();
+        let (momentum_before, energy_before) = (momentum(&light, &heavy), energy(&light, &heavy));
+
+        let heavy_position = heavy.position;
+        light.collide(&mut heavy, heavy_position);
+
+        assert!((momentum(&light, &heavy) - momentum_before).magnitude() < 1e-4);
+        assert!((energy(&light, &heavy) - energy_before).abs() < 1e-2);
+        // Light particle bounces back, the heavy one is pushed forward
+        assert_eq!(light.velocity, vec3(-30.0, 0.0, 0.0));
+        assert_eq!(heavy.velocity, vec3(10.0, 0.0, 0.0));
+
+        // Already separating, so nothing happens
+        light.collide(&mut heavy, heavy_position);
+        assert_eq!(light.velocity, vec3(-30.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_bounce_off_mirrors_approaching_velocity() {
+        let mut particle =
+            Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(30.0, 5.0, 0.0), 1.0, 1000.0);
+
+        particle.bounce_off(vec3(1.5, 0.0, 0.0));
+        assert_eq!(particle.velocity, vec3(-30.0, 5.0, 0.0));
+
+        // Already moving away, so nothing happens
+        particle.bounce_off(vec3(1.5, 0.0, 0.0));
+        assert_eq!(particle.velocity, vec3(-30.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn test_velocity_init() {
+        let position = vec3(3.0, 4.0, 12.0);
+        let mut rng = rand::thread_rng();
+        let mut velocity = |velocity_init: VelocityInit, position: Vector3<f32>| {
+            Particle::new(0, position, &velocity_init, 1.0, 1000.0, &mut rng).velocity
+        };
+
+        assert_eq!(velocity(VelocityInit::Zero, position), vec3(0.0, 0.0, 0.0));
+        let radial = velocity(VelocityInit::Radial { speed: 26.0 }, position);
+        assert!((radial - vec3(6.0, 8.0, 24.0)).magnitude() < 1e-4);
+        assert_eq!(
+            velocity(VelocityInit::Radial { speed: 26.0 }, vec3(0.0, 0.0, 0.0)),
+            vec3(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_tangential_velocity_is_orthogonal_to_position() {
+        let mut rng = rand::thread_rng();
+
+        for i in 0..100 {
+            let position = if i == 0 {
+                vec3(0.0, 0.0, 10.0)
+            } else {
+                Particle::initial_position(&Placement::Uniform, i, 100, 200.0, &mut rng)
+            };
+            let particle = Particle::new(
+                0,
+                position,
+                &VelocityInit::Tangential { speed: 50.0 },
+                1.0,
+                1000.0,
+                &mut rng,
+            );
+
+            assert!(particle.velocity.dot(position).abs() < 1e-2);
+            assert!((particle.velocity.magnitude() - 50.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_uniform_placement_stays_within_border() {
+        let border = 10.0;
+        let mut rng = rand::thread_rng();
+
+        for i in 0..100 {
+            let position =
+                Particle::initial_position(&Placement::Uniform, i, 100, border, &mut rng);
+
+            // assert position is within the range of -border/2 to +border/2
+            for coordinate in [position.x, position.y, position.z] {
+                assert!((-border / 2.0..=border / 2.0).contains(&coordinate));
+            }
+        }
+    }
+
+    #[test]
+    fn test_shell_placement_lies_on_sphere() {
+        let placement = Placement::Shell { radius: 50.0 };
+        let mut rng = rand::thread_rng();
+
+        for i in 0..100 {
+            let position = Particle::initial_position(&placement, i, 100, 200.0, &mut rng);
+
+            assert!((position.magnitude() - 50.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_cluster_placement_lies_within_ball() {
+        let center = vec3(20.0, -30.0, 5.0);
+        let placement = Placement::Cluster {
+            center: [center.x, center.y, center.z],
+            radius: 15.0,
+        };
+        let mut rng = rand::thread_rng();
+
+        for i in 0..100 {
+            let position = Particle::initial_position(&placement, i, 100, 200.0, &mut rng);
+
+            assert!((position - center).magnitude() <= 15.0 + 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_grid_placement_uses_distinct_lattice_nodes() {
+        let border = 30.0;
+        let amount = 10;
+        let mut rng = rand::thread_rng();
+
+        let positions = (0..amount)
+            .map(|i| Particle::initial_position(&Placement::Grid, i, amount, border, &mut rng))
+            .collect::<Vec<_>>();
+
+        // 10 particles need a 3x3x3 lattice with a spacing of 10
+        for position in &positions {
+            for coordinate in [position.x, position.y, position.z] {
+                assert!([-10.0, 0.0, 10.0].contains(&coordinate));
+            }
+        }
+        for (i, position) in positions.iter().enumerate() {
+            assert!(positions[i + 1..].iter().all(|other| other != position));
+        }
+    }
+
     #[test]
     fn test_update_velocity() {
         let mut particle = Particle {
             index: 0,
             position: Vector3::new(0.0, 0.0, 0.0),
-            positionable: Some(Box::new(MockPositionableRender)),
             mass: 1.0,
             velocity: Vector3::new(0.0, 0.0, 0.0),
             max_velocity: 1000.0,
@@ -208,7 +735,10 @@ mod tests {
             other_position,
             other_mass,
             InteractionType::Attraction,
-            gravity_constant,
+            &Parameters {
+                gravity_constant,
+                ..Parameters::default()
+            },
         );
 
         assert_eq!(
@@ -217,31 +747,134 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_force_exponent() {
+        let other_position = Vector3::new(3.0, 0.0, 0.0);
+        let other_mass = 2.0;
+        let gravity_constant = 9.8;
+        let speed_with_exponent = |force_exponent| {
+            let mut particle =
+                Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0, 1000.0);
+            particle.update_velocity(
+                other_position,
+                other_mass,
+                InteractionType::Attraction,
+                &Parameters {
+                    gravity_constant,
+                    force_exponent,
+                    ..Parameters::default()
+                },
+            );
+            particle.velocity.magnitude()
+        };
+
+        // The inverse-square law the force used before the exponent was configurable
+        assert_eq!(
+            speed_with_exponent(2.0),
+            gravity_constant * other_mass / (3.0 * 3.0)
+        );
+        assert!((speed_with_exponent(1.0) - gravity_constant * other_mass / 3.0).abs() < 1e-5);
+        assert!((speed_with_exponent(3.0) - gravity_constant * other_mass / 27.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_attraction_and_repulsion_constants_scale_independently() {
+        let force = |interaction_type, attraction_constant, repulsion_constant| {
+            pair_force(
+                2.0,
+                1.0,
+                3.0,
+                interaction_type,
+                &Parameters {
+                    attraction_constant,
+                    repulsion_constant,
+                    ..Parameters::default()
+                },
+            )
+        };
+        let attraction = force(InteractionType::Attraction, 1.0, 1.0);
+        let repulsion = force(InteractionType::Repulsion, 1.0, 1.0);
+        assert_eq!(attraction, -repulsion);
+
+        assert_eq!(
+            force(InteractionType::Attraction, 4.0, 1.0),
+            4.0 * attraction
+        );
+        assert_eq!(force(InteractionType::Repulsion, 4.0, 1.0), repulsion);
+        assert_eq!(force(InteractionType::Attraction, 1.0, 0.5), attraction);
+        assert_eq!(force(InteractionType::Repulsion, 1.0, 0.5), 0.5 * repulsion);
+        assert_eq!(force(InteractionType::Neutral, 4.0, 4.0), 0.0);
+    }
+
+    #[test]
+    fn test_lennard_jones_force() {
+        let (epsilon, sigma) = (100.0, 10.0);
+        let parameters = Parameters {
+            force_law: ForceLaw::LennardJones { epsilon, sigma },
+            ..Parameters::default()
+        };
+        let velocity_at = |distance: f32, interaction_type| {
+            let mut particle =
+                Particle::from_state(0, vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0, 1000.0);
+            particle.update_velocity(vec3(distance, 0.0, 0.0), 1.0, interaction_type, &parameters);
+            particle.velocity().x
+        };
+
+        let equilibrium = 2.0f32.powf(1.0 / 6.0) * sigma;
+        assert!(
+            lennard_jones_force(equilibrium, epsilon, sigma, InteractionType::Attraction).abs()
+                < 1e-4
+        );
+        assert!(velocity_at(equilibrium, InteractionType::Attraction).abs() < 1e-4);
+        // Pushed away when closer, pulled in when further away
+        assert!(velocity_at(0.9 * equilibrium, InteractionType::Attraction) < 0.0);
+        assert!(velocity_at(1.1 * equilibrium, InteractionType::Attraction) > 0.0);
+        // Repelling pairs lack the attraction
+        assert!(velocity_at(1.1 * equilibrium, InteractionType::Repulsion) < 0.0);
+        assert_eq!(
+            velocity_at(
+                LENNARD_JONES_CUTOFF * sigma + 0.1,
+                InteractionType::Attraction
+            ),
+            0.0
+        );
+    }
+
     #[test]
     fn test_update_position() {
         let mut particle = Particle {
             index: 0,
             position: Vector3::new(0.0, 0.0, 0.0),
-            positionable: Some(Box::new(MockPositionableRender)),
             mass: 1.0,
             velocity: Vector3::new(1.0, 1.0, 1.0),
             max_velocity: 1000.0,
         };
 
         let parameters = Parameters {
+            run_id: None,
             friction: 0.0,
             border: 10.0,
             amount: 30,
             timestep: 0.1,
             gravity_constant: 9.8,
             max_velocity: 1000.0,
-            bucket_size: 1.0,
-            particle_parameters: vec![ParticleParameters {
-                id: None,
-                mass: 1.0,
-                index: 0,
-            }],
+            position_bucket_size: 1.0,
+            velocity_bucket_size: 1.0,
+            particle_parameters: vec![ParticleParameters::new(0, 1.0)],
             interactions: vec![InteractionType::Attraction],
+            sample_every: 1,
+            seed: None,
+            convergence_interval: 0,
+            convergence_threshold: 0.01,
+            temperature: 0.0,
+            velocity_clamp: VelocityClamp::PerAxis,
+            boundary_shape: BoundaryShape::Sphere,
+            sphere_subdivisions: 16,
+            force_exponent: 2.0,
+            attraction_constant: 1.0,
+            repulsion_constant: 1.0,
+            force_law: ForceLaw::InversePower,
+            recenter: false,
         };
 
         particle.update_position(&parameters);
@@ -249,12 +882,306 @@ mod tests {
         assert_eq!(particle.position, Vector3::new(0.1, 0.1, 0.1));
     }
 
+    fn boundary_parameters(boundary_shape: BoundaryShape) -> Parameters {
+        Parameters {
+            border: 10.0,
+            boundary_shape,
+            timestep: 1.0,
+            ..Parameters::default()
+        }
+    }
+
+    #[test]
+    fn test_sphere_boundary_reflects_outward_particle() {
+        let parameters = boundary_parameters(BoundaryShape::Sphere);
+        // Inside the box's corner region but outside the sphere after the step
+        let mut particle = Particle::from_state(
+            0,
+            Vector3::new(6.0, 6.0, 0.0),
+            Vector3::new(2.0, 2.0, 0.0),
+            1.0,
+            1000.0,
+        );
+
+        particle.update_position(&parameters);
+
+        // Moving straight outwards, so the whole velocity is reversed
+        assert!((particle.velocity() - Vector3::new(-2.0, -2.0, 0.0)).magnitude() < 1e-5);
+        assert!((particle.position - Vector3::new(4.0, 4.0, 0.0)).magnitude() < 1e-5);
+        assert!(particle.position.magnitude() <= parameters.border);
+    }
+
+    #[test]
+    fn test_sphere_boundary_keeps_tangential_velocity() {
+        let parameters = boundary_parameters(BoundaryShape::Sphere);
+        // Grazing the wall on the x axis, mostly moving along it
+        let mut particle = Particle::from_state(
+            0,
+            Vector3::new(9.5, 0.0, 0.0),
+            Vector3::new(1.0, 3.0, 0.0),
+            1.0,
+            1000.0,
+        );
+
+        particle.update_position(&parameters);
+
+        let normal = Vector3::new(10.5, 3.0, 0.0).normalize();
+        let tangent = Vector3::new(-normal.y, normal.x, 0.0);
+        let velocity = Vector3::new(1.0, 3.0, 0.0);
+        assert!((particle.velocity().dot(tangent) - velocity.dot(tangent)).abs() < 1e-5);
+        assert!((particle.velocity().dot(normal) + velocity.dot(normal)).abs() < 1e-5);
+        assert_eq!(particle.velocity().z, 0.0);
+    }
+
+    #[test]
+    fn test_huge_velocity_stays_within_border() {
+        for boundary_shape in [BoundaryShape::Sphere, BoundaryShape::Box] {
+            let parameters = boundary_parameters(boundary_shape);
+            // Overshoots the border by several times its size in one step
+            let mut particle = Particle::from_state(
+                0,
+                Vector3::new(9.0, 2.0, -3.0),
+                Vector3::new(80.0, 35.0, -50.0),
+                1.0,
+                1000.0,
+            );
+
+            particle.update_position(&parameters);
+
+            let inside = match boundary_shape {
+                BoundaryShape::Sphere => particle.position.magnitude() <= parameters.border,
+                _ => (0..3).all(|axis| particle.position[axis].abs() <= parameters.border),
+            };
+            assert!(
+                inside,
+                "{:?} escaped to {:?}",
+                boundary_shape, particle.position
+            );
+        }
+    }
+
+    #[test]
+    fn test_box_boundary_reflects_outward_axis() {
+        let parameters = boundary_parameters(BoundaryShape::Box);
+        let mut particle = Particle::from_state(
+            0,
+            Vector3::new(6.0, 9.0, 0.0),
+            Vector3::new(2.0, 2.0, 0.0),
+            1.0,
+            1000.0,
+        );
+
+        particle.update_position(&parameters);
+
+        // Only the y axis crosses the wall, the corner region of the box stays reachable
+        assert_eq!(particle.velocity(), Vector3::new(2.0, -2.0, 0.0));
+        assert_eq!(particle.position, Vector3::new(8.0, 7.0, 0.0));
+        assert!(particle.position.magnitude() > parameters.border);
+    }
+
+    #[test]
+    fn test_periodic_boundary_wraps_to_opposite_face() {
+        let parameters = boundary_parameters(BoundaryShape::Periodic);
+        let mut particle = Particle::from_state(
+            0,
+            Vector3::new(9.5, 0.0, -9.0),
+            Vector3::new(2.0, 0.0, -3.0),
+            1.0,
+            1000.0,
+        );
+
+        particle.update_position(&parameters);
+
+        assert_eq!(particle.position, Vector3::new(-8.5, 0.0, 8.0));
+        assert_eq!(particle.velocity(), Vector3::new(2.0, 0.0, -3.0));
+    }
+
+    #[test]
+    fn test_nearest_image() {
+        let position = Vector3::new(9.0, 0.0, 0.0);
+        let other_position = Vector3::new(-9.0, 1.0, 0.0);
+
+        let periodic = boundary_parameters(BoundaryShape::Periodic);
+        let image = nearest_image(position, other_position, &periodic);
+
+        assert_eq!(image, Vector3::new(11.0, 1.0, 0.0));
+        assert!((image - position).magnitude() < (other_position - position).magnitude());
+
+        let sphere = boundary_parameters(BoundaryShape::Sphere);
+        assert_eq!(
+            nearest_image(position, other_position, &sphere),
+            other_position
+        );
+    }
+
+    #[test]
+    fn test_apply_friction_is_clamped() {
+        let particle = || {
+            Particle::from_state(
+                0,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(10.0, -4.0, 2.0),
+                1.0,
+                100.0,
+            )
+        };
+
+        let mut half = particle();
+        half.apply_friction(0.5);
+        assert_eq!(half.velocity(), Vector3::new(5.0, -2.0, 1.0));
+
+        let mut stopped = particle();
+        stopped.apply_friction(2.0);
+        assert_eq!(stopped.velocity(), Vector3::new(0.0, 0.0, 0.0));
+
+        let mut unchanged = particle();
+        unchanged.apply_friction(-1.0);
+        assert_eq!(unchanged.velocity(), Vector3::new(10.0, -4.0, 2.0));
+    }
+
+    #[test]
+    fn test_velocity_clamp() {
+        let accelerated_particle = |velocity_clamp| {
+            let mut particle = Particle::from_state(
+                0,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+                1.0,
+                10.0,
+            );
+            particle.update_velocity(
+                Vector3::new(3.0, 4.0, 0.0),
+                1.0,
+                InteractionType::Attraction,
+                &Parameters {
+                    gravity_constant: 1000.0,
+                    velocity_clamp,
+                    ..Parameters::default()
+                },
+            );
+            particle
+        };
+
+        let per_axis = accelerated_particle(VelocityClamp::PerAxis);
+        assert_eq!(per_axis.velocity(), Vector3::new(10.0, 10.0, 0.0));
+
+        let magnitude = accelerated_particle(VelocityClamp::Magnitude);
+        assert!(magnitude.velocity().magnitude() <= 10.0 + 1e-4);
+        assert!((magnitude.velocity() - Vector3::new(6.0, 8.0, 0.0)).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn test_kinetic_energy() {
+        let particle = Particle::from_state(
+            0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(3.0, 0.0, -4.0),
+            2.0,
+            1000.0,
+        );
+
+        assert_eq!(particle.kinetic_energy(), 25.0);
+    }
+
+    #[test]
+    fn test_to_state_vector() {
+        let particle = Particle {
+            index: 0,
+            position: Vector3::new(25.0, -5.0, 0.0),
+            mass: 250.0,
+            velocity: Vector3::new(100.0, 0.0, -30.0),
+            max_velocity: 1000.0,
+        };
+
+        let state_vector = particle.to_state_vector(10.0, 10.0, 3);
+
+        assert_eq!(
+            state_vector,
+            StateVector {
+                particle_parameters_id: 3,
+                mass: 250,
+                position_bucket: (2, -1, 0),
+                velocity_bucket: (10, 0, -3),
+            }
+        );
+    }
+
+    #[test]
+    fn test_state_vector_buckets_positions_and_velocities_at_different_scales() {
+        let state_vector = StateVector::new(
+            (25.0, 150.0, 0.0),
+            (15000.0, -2500.0, 999.0),
+            1.0,
+            10.0,
+            1000.0,
+            0,
+        );
+
+        assert_eq!(state_vector.position_bucket, (2, 15, 0));
+        assert_eq!(state_vector.velocity_bucket, (15, -3, 0));
+    }
+
+    #[test]
+    fn test_state_vector_buckets_are_uniform_across_zero() {
+        let bucket = |x: f32| StateVector::new((x, 0.0, 0.0), (x, 0.0, 0.0), 1.0, 1.0, 1.0, 0);
+
+        assert_eq!(bucket(0.5).position_bucket.0, 0);
+        assert_eq!(bucket(-0.5).position_bucket.0, -1);
+        assert_eq!(bucket(1.5).position_bucket.0, 1);
+        assert_eq!(bucket(-1.5).position_bucket.0, -2);
+        assert_eq!(bucket(-0.5).velocity_bucket.0, -1);
+        // Symmetric coordinates land in buckets mirrored around the boundary at zero
+        for x in [0.25, 0.5, 3.75, 41.5] {
+            assert_eq!(
+                bucket(-x).position_bucket.0,
+                -bucket(x).position_bucket.0 - 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_state_vector_approx_coordinates_within_one_bucket() {
+        let (position_bucket_size, velocity_bucket_size) = (10.0, 1000.0);
+        for (position, velocity) in [
+            ((25.0, 150.0, 0.0), (15000.0, -2500.0, 999.0)),
+            ((-0.5, -199.9, 37.3), (-1.0, 0.0, 19999.0)),
+        ] {
+            let state_vector = StateVector::new(
+                position,
+                velocity,
+                1.0,
+                position_bucket_size,
+                velocity_bucket_size,
+                0,
+            );
+
+            let within_bucket = |original: (f32, f32, f32), approx: (f32, f32, f32), size| {
+                [
+                    original.0 - approx.0,
+                    original.1 - approx.1,
+                    original.2 - approx.2,
+                ]
+                .iter()
+                .all(|offset| (0.0..size).contains(offset))
+            };
+            assert!(within_bucket(
+                position,
+                state_vector.approx_position(position_bucket_size),
+                position_bucket_size
+            ));
+            assert!(within_bucket(
+                velocity,
+                state_vector.approx_velocity(velocity_bucket_size),
+                velocity_bucket_size
+            ));
+        }
+    }
+
     #[test]
     fn test_compute_updated_position() {
         let particle = Particle {
             index: 0,
             position: Vector3::new(0.0, 0.0, 0.0),
-            positionable: Some(Box::new(MockPositionableRender)),
             mass: 1.0,
             velocity: Vector3::new(1.0, 1.0, 1.0),
             max_velocity: 1000.0,
@@ -266,4 +1193,64 @@ mod tests {
 
         assert_eq!(updated_position, Vector3::new(0.1, 0.1, 0.1));
     }
+
+    struct SpeedMapper;
+
+    impl StateMapper for SpeedMapper {
+        fn map(&self, particle: &Particle, ctx: &FrameContext) -> StateVector {
+            StateVector {
+                particle_parameters_id: ctx.particle_parameters_id(particle),
+                mass: 0,
+                position_bucket: (0, 0, 0),
+                velocity_bucket: (
+                    (particle.velocity.magnitude() / ctx.parameters.velocity_bucket_size) as i32,
+                    0,
+                    0,
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_state_mapper() {
+        let particle_with_velocity = |position, velocity| Particle {
+            index: 0,
+            position,
+            mass: 1.0,
+            velocity,
+            max_velocity: 1000.0,
+        };
+        let particles = vec![
+            particle_with_velocity(Vector3::new(-50.0, 0.0, 0.0), Vector3::new(30.0, 40.0, 0.0)),
+            particle_with_velocity(Vector3::new(50.0, 5.0, 0.0), Vector3::new(0.0, 0.0, -50.0)),
+        ];
+        let parameters = Parameters {
+            particle_parameters: vec![ParticleParameters {
+                id: Some(7),
+                ..ParticleParameters::new(0, 1.0)
+            }],
+            interactions: vec![InteractionType::Attraction],
+            velocity_bucket_size: 10.0,
+            ..Parameters::default()
+        };
+        let particle_parameters_ids = parameters.particle_parameters_ids();
+        let ctx = FrameContext::new(&parameters, &particle_parameters_ids, &particles);
+
+        let state_vectors = particles
+            .iter()
+            .map(|p| SpeedMapper.map(p, &ctx))
+            .collect::<Vec<_>>();
+
+        assert_eq!(ctx.center_of_mass, Vector3::new(0.0, 2.5, 0.0));
+        assert_eq!(state_vectors[0], state_vectors[1]);
+        assert_eq!(
+            state_vectors[0],
+            StateVector {
+                particle_parameters_id: 7,
+                mass: 0,
+                position_bucket: (0, 0, 0),
+                velocity_bucket: (5, 0, 0),
+            }
+        );
+    }
 }
@@ -1,9 +1,14 @@
 use lazy_static::lazy_static;
-use rusqlite::{params, Connection, Result, Statement, Transaction};
+use rusqlite::{params, Connection, OptionalExtension, Result, Statement, Transaction};
 use rusqlite_migration::{Migrations, M};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
-use crate::{parameters::Parameters, particle::StateVector};
+use crate::{parameters::Parameters, particle::StateVector, store::ResultStore};
 
 lazy_static! {
     static ref MIGRATIONS: Migrations<'static> = Migrations::new(vec![
@@ -58,6 +63,90 @@ lazy_static! {
             "
         )
         .down("DROP TABLE state_vectors;"),
+        M::up(
+            "CREATE TABLE state_vectors_with_mass(
+                 px INTEGER NOT NULL,
+                 py INTEGER NOT NULL,
+                 pz INTEGER NOT NULL,
+                 vx INTEGER NOT NULL,
+                 vy INTEGER NOT NULL,
+                 vz INTEGER NOT NULL,
+                 mass INTEGER NOT NULL,
+                 count INTEGER,
+                 particle_parameters_id INTEGER NOT NULL,
+                 PRIMARY KEY (px, py, pz, vx, vy, vz, mass, particle_parameters_id),
+                 FOREIGN KEY (particle_parameters_id) REFERENCES particle_parameters(id) ON DELETE CASCADE
+             );
+             INSERT INTO state_vectors_with_mass
+             SELECT s.px, s.py, s.pz, s.vx, s.vy, s.vz, CAST(round(p.mass) AS INTEGER), s.count,
+                 s.particle_parameters_id
+             FROM state_vectors s
+             JOIN particle_parameters p ON p.id = s.particle_parameters_id;
+             DROP TABLE state_vectors;
+             ALTER TABLE state_vectors_with_mass RENAME TO state_vectors;
+            "
+        )
+        .down(
+            "CREATE TABLE state_vectors_without_mass(
+                 px INTEGER NOT NULL,
+                 py INTEGER NOT NULL,
+                 pz INTEGER NOT NULL,
+                 vx INTEGER NOT NULL,
+                 vy INTEGER NOT NULL,
+                 vz INTEGER NOT NULL,
+                 count INTEGER,
+                 particle_parameters_id INTEGER NOT NULL,
+                 PRIMARY KEY (px, py, pz, vx, vy, vz, particle_parameters_id),
+                 FOREIGN KEY (particle_parameters_id) REFERENCES particle_parameters(id) ON DELETE CASCADE
+             );
+             INSERT INTO state_vectors_without_mass
+             SELECT px, py, pz, vx, vy, vz, sum(count), particle_parameters_id
+             FROM state_vectors
+             GROUP BY px, py, pz, vx, vy, vz, particle_parameters_id;
+             DROP TABLE state_vectors;
+             ALTER TABLE state_vectors_without_mass RENAME TO state_vectors;
+            "
+        ),
+        M::up(
+            "CREATE TABLE run_metrics (
+                run_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value REAL NOT NULL,
+                PRIMARY KEY (run_id, name),
+                FOREIGN KEY (run_id) REFERENCES run_parameters(run_id) ON DELETE CASCADE
+            );"
+        )
+        .down("DROP TABLE run_metrics;"),
+        M::up(
+            "CREATE INDEX state_vectors_particle_parameters_id_count
+             ON state_vectors (particle_parameters_id, count);"
+        )
+        .down("DROP INDEX state_vectors_particle_parameters_id_count;"),
+        M::up("ALTER TABLE run_parameters ADD COLUMN seed INTEGER;")
+            .down("ALTER TABLE run_parameters DROP COLUMN seed;"),
+        M::up(
+            "ALTER TABLE run_parameters RENAME COLUMN bucket_size TO position_bucket_size;
+             ALTER TABLE run_parameters ADD COLUMN velocity_bucket_size REAL NOT NULL DEFAULT 0;
+             UPDATE run_parameters SET velocity_bucket_size = position_bucket_size;"
+        )
+        .down(
+            "ALTER TABLE run_parameters DROP COLUMN velocity_bucket_size;
+             ALTER TABLE run_parameters RENAME COLUMN position_bucket_size TO bucket_size;"
+        ),
+        M::up("ALTER TABLE run_parameters ADD COLUMN force_exponent REAL NOT NULL DEFAULT 2.0;")
+            .down("ALTER TABLE run_parameters DROP COLUMN force_exponent;"),
+        M::up("ALTER TABLE run_parameters ADD COLUMN elapsed_time_s REAL;")
+            .down("ALTER TABLE run_parameters DROP COLUMN elapsed_time_s;"),
+        M::up(
+            "ALTER TABLE run_parameters ADD COLUMN attraction_constant REAL NOT NULL DEFAULT 1.0;
+             ALTER TABLE run_parameters ADD COLUMN repulsion_constant REAL NOT NULL DEFAULT 1.0;"
+        )
+        .down(
+            "ALTER TABLE run_parameters DROP COLUMN repulsion_constant;
+             ALTER TABLE run_parameters DROP COLUMN attraction_constant;"
+        ),
+        M::up("ALTER TABLE run_parameters ADD COLUMN config TEXT;")
+            .down("ALTER TABLE run_parameters DROP COLUMN config;"),
     ]);
 }
 
@@ -99,10 +188,121 @@ impl<'a> TransactionProvider for TransactionProviderImpl<'a> {
     }
 }
 
-pub fn open_database(path: &str) -> Result<ConnectionProviderImpl> {
-    Ok(ConnectionProviderImpl {
-        connection: Connection::open(path)?,
-    })
+/// Runs statements on a connection directly, i.e. within the transaction opened on it manually.
+struct ConnectionTransactionProvider<'a> {
+    connection: &'a Connection,
+}
+
+impl<'a> TransactionProvider for ConnectionTransactionProvider<'a> {
+    fn prepare(&self, sql: &str) -> Result<Statement> {
+        self.connection.prepare(sql)
+    }
+
+    fn commit(self) -> Result<()> {
+        self.connection.execute_batch("COMMIT;")
+    }
+
+    fn get_last_insert_rowid(&self) -> i64 {
+        self.connection.last_insert_rowid()
+    }
+}
+
+/// Result store persisting to SQLite. A transaction is opened by the first write after a commit,
+/// so everything up to the next `commit` is persisted atomically.
+pub struct SqliteResultStore {
+    connection_provider: ConnectionProviderImpl,
+}
+
+impl SqliteResultStore {
+    pub fn new(connection_provider: ConnectionProviderImpl) -> Self {
+        Self {
+            connection_provider,
+        }
+    }
+
+    fn transaction(&mut self) -> Result<ConnectionTransactionProvider<'_>> {
+        let connection = &self.connection_provider.connection;
+        if connection.is_autocommit() {
+            connection.execute_batch("BEGIN;")?;
+        }
+        Ok(ConnectionTransactionProvider { connection })
+    }
+}
+
+impl ResultStore for SqliteResultStore {
+    fn persist_parameters(&mut self, parameters: &mut Parameters) -> Result<(), Box<dyn Error>> {
+        persist_parameters(parameters, &self.transaction()?)
+    }
+
+    fn increment_state_counts(
+        &mut self,
+        state_vectors: &[StateVector],
+    ) -> Result<(), Box<dyn Error>> {
+        increment_state_counts(state_vectors, &self.transaction()?)
+    }
+
+    fn persist_run_metric(
+        &mut self,
+        run_id: usize,
+        name: &str,
+        value: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        persist_run_metric(run_id, name, value, &self.transaction()?)
+    }
+
+    fn persist_elapsed_time(&mut self, run_id: usize, seconds: f64) -> Result<(), Box<dyn Error>> {
+        update_run_elapsed(run_id, seconds, &self.transaction()?)
+    }
+
+    fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.connection_provider.connection.is_autocommit() {
+            self.transaction()?.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Connection tuning applied by `open_database`. The default keeps SQLite's own defaults.
+#[derive(Debug, Default, Clone)]
+pub struct PersistenceOptions {
+    /// Use a write-ahead log instead of a rollback journal, which makes the many small
+    /// transactions of a search considerably cheaper.
+    pub wal: bool,
+    /// Only sync to disk at WAL checkpoints instead of on every commit. The database can't get
+    /// corrupted this way, but an OS crash or power loss may roll back the most recently
+    /// committed runs. Only has an effect together with `wal`.
+    pub synchronous_normal: bool,
+    /// Keep temporary tables and indices in memory.
+    pub temp_store_memory: bool,
+}
+
+impl PersistenceOptions {
+    /// Options for write heavy workloads like search runs.
+    pub fn write_heavy() -> Self {
+        Self {
+            wal: true,
+            synchronous_normal: true,
+            temp_store_memory: true,
+        }
+    }
+}
+
+pub fn open_database(path: &str, options: &PersistenceOptions) -> Result<ConnectionProviderImpl> {
+    let connection = Connection::open(path)?;
+    // SQLite only enforces foreign keys, and thereby cascades deletes, if enabled per connection
+    connection.pragma_update(None, "foreign_keys", "ON")?;
+    if options.wal {
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    }
+    if options.synchronous_normal {
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+    }
+    if options.temp_store_memory {
+        connection.pragma_update(None, "temp_store", "MEMORY")?;
+    }
+
+    Ok(ConnectionProviderImpl { connection })
 }
 
 pub fn migrate_to_latest(
@@ -111,6 +311,19 @@ pub fn migrate_to_latest(
     MIGRATIONS.to_latest(&mut connection_provider.connection)
 }
 
+/// Opens the database at the given path and migrates it to the latest schema. The errors name
+/// the path and the failed step, as they are shown to the user as is.
+pub fn open_and_migrate(
+    path: &str,
+    options: &PersistenceOptions,
+) -> Result<ConnectionProviderImpl, Box<dyn Error>> {
+    let mut connection_provider =
+        open_database(path, options).map_err(|e| format!("Can't open database {}: {}", path, e))?;
+    migrate_to_latest(&mut connection_provider)
+        .map_err(|e| format!("Can't migrate database {}: {}", path, e))?;
+    Ok(connection_provider)
+}
+
 pub fn create_transaction_provider(
     connection: &mut ConnectionProviderImpl,
 ) -> Result<TransactionProviderImpl<'_>, Box<dyn Error>> {
@@ -125,32 +338,49 @@ pub fn commit_transaction(transaction: TransactionProviderImpl) -> Result<()> {
 pub fn increment_state_count<T: TransactionProvider>(
     state_vector: &StateVector,
     tx: &T,
+) -> Result<(), Box<dyn Error>> {
+    increment_state_counts(std::slice::from_ref(state_vector), tx)
+}
+
+/// Increments the counts of all given state vectors, preparing the upsert statement only once.
+///
+/// Upserting 100k state vectors into an in-memory database takes about 0.2 s this way compared
+/// to about 0.8 - 1.1 s when preparing the statement for every row (release build).
+pub fn increment_state_counts<T: TransactionProvider>(
+    state_vectors: &[StateVector],
+    tx: &T,
 ) -> Result<(), Box<dyn Error>> {
     let mut stmt = tx.prepare(
-        "INSERT INTO state_vectors (px, py, pz, vx, vy, vz, particle_parameters_id, count)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 1)
-         ON CONFLICT(px, py, pz, vx, vy, vz, particle_parameters_id)
+        "INSERT INTO state_vectors (px, py, pz, vx, vy, vz, mass, particle_parameters_id, count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 1)
+         ON CONFLICT(px, py, pz, vx, vy, vz, mass, particle_parameters_id)
          DO UPDATE SET count = count + 1;",
     )?;
-    stmt.execute(params![
-        state_vector.position_bucket.0,
-        state_vector.position_bucket.1,
-        state_vector.position_bucket.2,
-        state_vector.velocity_bucket.0,
-        state_vector.velocity_bucket.1,
-        state_vector.velocity_bucket.2,
-        state_vector.particle_parameters_id,
-    ])?;
+    for state_vector in state_vectors {
+        stmt.execute(params![
+            state_vector.position_bucket.0,
+            state_vector.position_bucket.1,
+            state_vector.position_bucket.2,
+            state_vector.velocity_bucket.0,
+            state_vector.velocity_bucket.1,
+            state_vector.velocity_bucket.2,
+            state_vector.mass,
+            state_vector.particle_parameters_id,
+        ])?;
+    }
     Ok(())
 }
 
+/// Stores the parameters of a new run and assigns its run id and the ids of its particle kinds.
+/// Besides the columns the analysis queries, the full configuration is kept as TOML, which
+/// identifies the run.
 pub fn persist_parameters<T: TransactionProvider>(
     parameters: &mut Parameters,
     tx: &T,
 ) -> Result<(), Box<dyn Error>> {
     let mut stmt = tx.prepare(
-        "INSERT INTO run_parameters (amount, border, timestep, gravity_constant, friction, max_velocity, bucket_size)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+        "INSERT INTO run_parameters (amount, border, timestep, gravity_constant, friction, max_velocity, position_bucket_size, velocity_bucket_size, seed, force_exponent, attraction_constant, repulsion_constant, config)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);",
     )?;
     stmt.execute(params![
         parameters.amount,
@@ -159,9 +389,16 @@ pub fn persist_parameters<T: TransactionProvider>(
         parameters.gravity_constant,
         parameters.friction,
         parameters.max_velocity,
-        parameters.bucket_size
+        parameters.position_bucket_size,
+        parameters.velocity_bucket_size,
+        parameters.seed,
+        parameters.force_exponent,
+        parameters.attraction_constant,
+        parameters.repulsion_constant,
+        parameters.to_toml()
     ])?;
     let parameters_id = tx.get_last_insert_rowid();
+    parameters.run_id = Some(parameters_id as usize);
 
     for particle in parameters.particle_parameters.iter_mut() {
         let mut stmt = tx.prepare(
@@ -173,6 +410,12 @@ pub fn persist_parameters<T: TransactionProvider>(
         particle.id = Some(tx.get_last_insert_rowid() as usize);
     }
 
+    let particle_parameters_id = |index: usize| {
+        parameters
+            .particle_parameters_by_index(index)
+            .and_then(|p| p.id)
+            .ok_or_else(|| format!("No persisted particle kind with index {}", index))
+    };
     for i in 0..parameters.particle_parameters.len() {
         for j in i..parameters.particle_parameters.len() {
             let interaction = parameters.interaction_by_indices(i, j)?;
@@ -180,15 +423,390 @@ pub fn persist_parameters<T: TransactionProvider>(
                 "INSERT INTO interactions (interaction_type, parameter_id_0, parameter_id_1)
                  VALUES (?1, ?2, ?3);",
             )?;
-            stmt.execute(params![interaction.to_string(), i as i64 + 1, j as i64 + 1])?;
+            stmt.execute(params![
+                interaction.to_string(),
+                particle_parameters_id(i)?,
+                particle_parameters_id(j)?
+            ])?;
         }
     }
     Ok(())
 }
 
+/// Reconstructs the parameters of a persisted run from its stored configuration, the inverse of
+/// `persist_parameters`. Runs persisted before configurations were stored can't be restored
+/// faithfully and return an error.
+pub fn load_parameters<T: TransactionProvider>(
+    run_id: usize,
+    tx: &T,
+) -> Result<Parameters, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT config FROM run_parameters
+         WHERE run_id = ?1;",
+    )?;
+    let config: Option<Option<String>> = stmt
+        .query_row(params![run_id], |row| row.get(0))
+        .optional()?;
+    let config = config.ok_or_else(|| format!("No run with id {}", run_id))?;
+    let config = config.ok_or_else(|| {
+        format!(
+            "Run {} was persisted without its configuration and can't be restored",
+            run_id
+        )
+    })?;
+    let mut parameters = Parameters::from_toml(&config)?;
+    parameters.run_id = Some(run_id);
+
+    let mut stmt = tx.prepare(
+        "SELECT id, ix FROM particle_parameters
+         WHERE run_id = ?1;",
+    )?;
+    let ids = stmt
+        .query_map(params![run_id], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    for (id, index) in ids {
+        let particle = parameters
+            .particle_parameters
+            .iter_mut()
+            .find(|p| p.index == index)
+            .ok_or_else(|| format!("No particle kind with index {} in run {}", index, run_id))?;
+        particle.id = Some(id);
+    }
+    Ok(parameters)
+}
+
+/// Stores a named scalar metric of a run, replacing a previous value of the same name.
+pub fn persist_run_metric<T: TransactionProvider>(
+    run_id: usize,
+    name: &str,
+    value: f64,
+    tx: &T,
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "INSERT INTO run_metrics (run_id, name, value)
+         VALUES (?1, ?2, ?3)
+         ON CONFLICT(run_id, name) DO UPDATE SET value = excluded.value;",
+    )?;
+    stmt.execute(params![run_id, name, value])?;
+    Ok(())
+}
+
+/// Records how long simulating a run took, e.g. to find the slow regions of a parameter space.
+pub fn update_run_elapsed<T: TransactionProvider>(
+    run_id: usize,
+    seconds: f64,
+    tx: &T,
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt =
+        tx.prepare("UPDATE run_parameters SET elapsed_time_s = ?1 WHERE run_id = ?2;")?;
+    stmt.execute(params![seconds, run_id])?;
+    Ok(())
+}
+
+/// Returns the most visited state vectors of a run together with their counts, ordered by
+/// descending count. Optionally only states of a single particle kind are considered.
+pub fn top_states<T: TransactionProvider>(
+    run_id: usize,
+    particle_parameters_id: Option<usize>,
+    limit: usize,
+    tx: &T,
+) -> Result<Vec<(StateVector, u64)>, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT s.px, s.py, s.pz, s.vx, s.vy, s.vz, s.mass, s.particle_parameters_id, s.count
+         FROM state_vectors s
+         JOIN particle_parameters p ON p.id = s.particle_parameters_id
+         WHERE p.run_id = ?1 AND (?2 IS NULL OR s.particle_parameters_id = ?2)
+         ORDER BY s.count DESC
+         LIMIT ?3;",
+    )?;
+    let states = stmt
+        .query_map(params![run_id, particle_parameters_id, limit], |row| {
+            let state_vector = StateVector {
+                position_bucket: (row.get(0)?, row.get(1)?, row.get(2)?),
+                velocity_bucket: (row.get(3)?, row.get(4)?, row.get(5)?),
+                mass: row.get(6)?,
+                particle_parameters_id: row.get(7)?,
+            };
+            Ok((state_vector, row.get(8)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(states)
+}
+
+/// Writes all state vectors of a run with their counts as CSV to the given path. Rows are
+/// streamed from the database, so the run doesn't have to fit into memory.
+pub fn export_run_csv<T: TransactionProvider>(
+    run_id: usize,
+    path: &Path,
+    tx: &T,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "px,py,pz,vx,vy,vz,mass,count,particle_parameters_id"
+    )?;
+
+    let mut stmt = tx.prepare(
+        "SELECT s.px, s.py, s.pz, s.vx, s.vy, s.vz, s.mass, s.count, s.particle_parameters_id
+         FROM state_vectors s
+         JOIN particle_parameters p ON p.id = s.particle_parameters_id
+         WHERE p.run_id = ?1
+         ORDER BY s.particle_parameters_id, s.px, s.py, s.pz, s.vx, s.vy, s.vz, s.mass;",
+    )?;
+    let mut rows = stmt.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        let values = (0..9)
+            .map(|i| row.get::<_, i64>(i).map(|value| value.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        writeln!(writer, "{}", values.join(","))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Scalar parameters of a persisted run together with its computed metrics by name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMetrics {
+    pub run_id: usize,
+    pub amount: usize,
+    pub border: f32,
+    pub timestep: f32,
+    pub gravity_constant: f32,
+    pub friction: f32,
+    pub max_velocity: f32,
+    pub position_bucket_size: f32,
+    pub velocity_bucket_size: f32,
+    pub seed: Option<u64>,
+    pub force_exponent: f32,
+    pub attraction_constant: f32,
+    pub repulsion_constant: f32,
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// Writes every run with its scalar parameters and metrics as a JSON array of `RunMetrics` to
+/// the given path. Runs are streamed from the database one at a time, so big databases don't
+/// have to fit into memory.
+pub fn export_metrics_json<T: TransactionProvider>(
+    path: &Path,
+    tx: &T,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut stmt = tx.prepare(
+        "SELECT r.run_id, r.amount, r.border, r.timestep, r.gravity_constant, r.friction,
+             r.max_velocity, r.position_bucket_size, r.velocity_bucket_size, r.seed,
+             r.force_exponent, r.attraction_constant, r.repulsion_constant, m.name, m.value
+         FROM run_parameters r
+         LEFT JOIN run_metrics m ON m.run_id = r.run_id
+         ORDER BY r.run_id, m.name;",
+    )?;
+    let mut rows = stmt.query([])?;
+
+    write!(writer, "[")?;
+    let mut current: Option<RunMetrics> = None;
+    let mut written_runs = 0;
+    while let Some(row) = rows.next()? {
+        let run_id: usize = row.get(0)?;
+        if current.as_ref().map(|run| run.run_id) != Some(run_id) {
+            if let Some(run) = current.take() {
+                write_json_element(&mut writer, &run, written_runs)?;
+                written_runs += 1;
+            }
+            current = Some(RunMetrics {
+                run_id,
+                amount: row.get(1)?,
+                border: row.get(2)?,
+                timestep: row.get(3)?,
+                gravity_constant: row.get(4)?,
+                friction: row.get(5)?,
+                max_velocity: row.get(6)?,
+                position_bucket_size: row.get(7)?,
+                velocity_bucket_size: row.get(8)?,
+                seed: row.get(9)?,
+                force_exponent: row.get(10)?,
+                attraction_constant: row.get(11)?,
+                repulsion_constant: row.get(12)?,
+                metrics: BTreeMap::new(),
+            });
+        }
+        // Runs without metrics yield a single row without a name
+        if let (Some(run), Some(name)) = (current.as_mut(), row.get::<_, Option<String>>(13)?) {
+            run.metrics.insert(name, row.get(14)?);
+        }
+    }
+    if let Some(run) = current {
+        write_json_element(&mut writer, &run, written_runs)?;
+    }
+    writeln!(writer, "]")?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes an element of a JSON array, preceded by a separator unless it's the first one.
+fn write_json_element<W: Write, S: Serialize>(
+    writer: &mut W,
+    element: &S,
+    index: usize,
+) -> Result<(), Box<dyn Error>> {
+    if index > 0 {
+        write!(writer, ",")?;
+    }
+    serde_json::to_writer(&mut *writer, element)?;
+    Ok(())
+}
+
+/// Deletes a run together with its particle kinds, interactions, state vectors and metrics.
+pub fn delete_run<T: TransactionProvider>(run_id: usize, tx: &T) -> Result<(), Box<dyn Error>> {
+    let mut stmt = tx.prepare("DELETE FROM run_parameters WHERE run_id = ?1;")?;
+    stmt.execute(params![run_id])?;
+    Ok(())
+}
+
+/// Scalar parameters of a persisted run together with the number of its state vector rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    pub run_id: usize,
+    pub amount: usize,
+    pub border: f32,
+    pub timestep: f32,
+    pub gravity_constant: f32,
+    pub friction: f32,
+    pub max_velocity: f32,
+    pub position_bucket_size: f32,
+    pub velocity_bucket_size: f32,
+    pub created_at: String,
+    pub state_vector_rows: usize,
+}
+
+/// Returns a summary of every persisted run, ordered by run id.
+pub fn list_runs<T: TransactionProvider>(tx: &T) -> Result<Vec<RunSummary>, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT r.run_id, r.amount, r.border, r.timestep, r.gravity_constant, r.friction,
+             r.max_velocity, r.position_bucket_size, r.velocity_bucket_size, r.created_at,
+             count(s.particle_parameters_id)
+         FROM run_parameters r
+         LEFT JOIN particle_parameters p ON p.run_id = r.run_id
+         LEFT JOIN state_vectors s ON s.particle_parameters_id = p.id
+         GROUP BY r.run_id
+         ORDER BY r.run_id;",
+    )?;
+    let runs = stmt
+        .query_map([], |row| {
+            Ok(RunSummary {
+                run_id: row.get(0)?,
+                amount: row.get(1)?,
+                border: row.get(2)?,
+                timestep: row.get(3)?,
+                gravity_constant: row.get(4)?,
+                friction: row.get(5)?,
+                max_velocity: row.get(6)?,
+                position_bucket_size: row.get(7)?,
+                velocity_bucket_size: row.get(8)?,
+                created_at: row.get(9)?,
+                state_vector_rows: row.get(10)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(runs)
+}
+
+/// Returns the `limit` runs with the highest values of the named metric together with those
+/// values, in descending order. Runs without the metric are left out.
+pub fn rank_runs_by_metric<T: TransactionProvider>(
+    metric_name: &str,
+    limit: usize,
+    tx: &T,
+) -> Result<Vec<(RunSummary, f64)>, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT r.run_id, r.amount, r.border, r.timestep, r.gravity_constant, r.friction,
+             r.max_velocity, r.position_bucket_size, r.velocity_bucket_size, r.created_at,
+             count(s.particle_parameters_id), m.value
+         FROM run_parameters r
+         JOIN run_metrics m ON m.run_id = r.run_id AND m.name = ?1
+         LEFT JOIN particle_parameters p ON p.run_id = r.run_id
+         LEFT JOIN state_vectors s ON s.particle_parameters_id = p.id
+         GROUP BY r.run_id
+         ORDER BY m.value DESC, r.run_id
+         LIMIT ?2;",
+    )?;
+    let runs = stmt
+        .query_map(params![metric_name, limit], |row| {
+            let run = RunSummary {
+                run_id: row.get(0)?,
+                amount: row.get(1)?,
+                border: row.get(2)?,
+                timestep: row.get(3)?,
+                gravity_constant: row.get(4)?,
+                friction: row.get(5)?,
+                max_velocity: row.get(6)?,
+                position_bucket_size: row.get(7)?,
+                velocity_bucket_size: row.get(8)?,
+                created_at: row.get(9)?,
+                state_vector_rows: row.get(10)?,
+            };
+            Ok((run, row.get(11)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(runs)
+}
+
+/// Returns the id of a persisted run with exactly the given parameters, if any. Runs are
+/// identified by their full configuration, see `persist_parameters`.
+pub fn find_run_id<T: TransactionProvider>(
+    parameters: &Parameters,
+    tx: &T,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT run_id FROM run_parameters
+         WHERE config = ?1
+         LIMIT 1;",
+    )?;
+    let run_id = stmt
+        .query_row(params![parameters.to_toml()], |row| row.get(0))
+        .optional()?;
+    Ok(run_id)
+}
+
+/// Whether a run with exactly the given parameters has been simulated to the end. The elapsed
+/// time is persisted in the same transaction as the metrics after the last iteration, so a run
+/// interrupted midway doesn't count even if some of its state vectors were flushed.
+pub fn parameters_already_run<T: TransactionProvider>(
+    parameters: &Parameters,
+    tx: &T,
+) -> Result<bool, Box<dyn Error>> {
+    let mut stmt = tx.prepare(
+        "SELECT EXISTS(
+             SELECT 1 FROM run_parameters
+             WHERE config = ?1 AND elapsed_time_s IS NOT NULL
+         );",
+    )?;
+    let already_run = stmt.query_row(params![parameters.to_toml()], |row| row.get(0))?;
+    Ok(already_run)
+}
+
+/// Drops every parameter set that was already simulated to the end, see
+/// `parameters_already_run`, so that an extended sweep only simulates its new points.
+pub fn retain_new_parameters<T: TransactionProvider>(
+    parameter_space: impl IntoIterator<Item = Parameters>,
+    tx: &T,
+) -> Result<Vec<Parameters>, Box<dyn Error>> {
+    let mut new_parameters = vec![];
+    for parameters in parameter_space {
+        if !parameters_already_run(&parameters, tx)? {
+            new_parameters.push(parameters);
+        }
+    }
+    Ok(new_parameters)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::parameters::{InteractionType, ParticleParameters};
+    use crate::parameters::{
+        BoundaryShape, ForceLaw, InteractionType, ParticleParameters, SweepSpec, VelocityClamp,
+    };
 
     use super::*;
     use pretty_assertions_sorted::assert_eq;
@@ -199,43 +817,98 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_open_database_wal() {
+        let path = std::env::temp_dir().join(format!("atomata-wal-{}.db3", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let connection_provider = open_database(path, &PersistenceOptions::write_heavy()).unwrap();
+        let journal_mode: String = connection_provider
+            .connection
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        drop(connection_provider);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(journal_mode, "wal");
+    }
+
+    #[test]
+    fn test_open_and_migrate_invalid_path() {
+        let path = "/nonexistent-atomata-directory/results.db3";
+
+        let result = open_and_migrate(path, &PersistenceOptions::write_heavy());
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.starts_with("Can't open database /nonexistent-atomata-directory/results.db3"));
+    }
+
+    #[test]
+    fn test_open_and_migrate_corrupt_database() {
+        let path = std::env::temp_dir().join(format!("atomata-corrupt-{}.db3", std::process::id()));
+        std::fs::write(
+            &path,
+            "not a database, just some text padding it beyond one header",
+        )
+        .unwrap();
+
+        let result = open_and_migrate(path.to_str().unwrap(), &PersistenceOptions::default());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_migrations() {
         assert!(MIGRATIONS.validate().is_ok());
     }
 
+    #[test]
+    fn test_migration_splits_bucket_size() {
+        let mut connection_provider = open_memory_database();
+        MIGRATIONS
+            .to_version(&mut connection_provider.connection, 8)
+            .unwrap();
+        connection_provider
+            .connection
+            .execute(
+                "INSERT INTO run_parameters
+                 (amount, border, timestep, gravity_constant, friction, max_velocity, bucket_size)
+                 VALUES (10, 200.0, 0.0002, 1.0, 0.0, 20000.0, 5.0);",
+                [],
+            )
+            .unwrap();
+
+        migrate_to_latest(&mut connection_provider).unwrap();
+
+        let bucket_sizes: (f32, f32) = connection_provider
+            .connection
+            .query_row(
+                "SELECT position_bucket_size, velocity_bucket_size FROM run_parameters;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(bucket_sizes, (5.0, 5.0));
+    }
+
     #[test]
     fn test_persist_parameters() {
         let mut connection_provider = open_memory_database();
         migrate_to_latest(&mut connection_provider).unwrap();
         let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
         let mut parameters = Parameters {
+            run_id: None,
             amount: 10,
             border: 200.0,
             friction: 0.0,
             timestep: 0.0002,
             gravity_constant: 1.0,
             particle_parameters: vec![
-                ParticleParameters {
-                    id: None,
-                    mass: 3.0,
-                    index: 0,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 250.0,
-                    index: 1,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 10000.0,
-                    index: 2,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 10000.0,
-                    index: 3,
-                },
+                ParticleParameters::new(0, 3.0),
+                ParticleParameters::new(1, 250.0),
+                ParticleParameters::new(2, 10000.0),
+                ParticleParameters::new(3, 10000.0),
             ],
             interactions: vec![
                 InteractionType::Attraction, // 0 <-> 0
@@ -250,9 +923,23 @@ mod tests {
                 InteractionType::Repulsion,  // 3 <-> 3
             ],
             max_velocity: 20000.0,
-            bucket_size: 10.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 10.0,
+            sample_every: 1,
+            seed: None,
+            convergence_interval: 0,
+            convergence_threshold: 0.01,
+            temperature: 0.0,
+            velocity_clamp: VelocityClamp::PerAxis,
+            boundary_shape: BoundaryShape::Sphere,
+            sphere_subdivisions: 16,
+            force_exponent: 2.0,
+            attraction_constant: 1.0,
+            repulsion_constant: 1.0,
+            force_law: ForceLaw::InversePower,
+            recenter: false,
         };
-        let _ = persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
         commit_transaction(tx_provider).unwrap();
 
         let mut stmt = connection_provider
@@ -277,6 +964,96 @@ mod tests {
         assert_eq!(count, parameters.interactions.len() as i32);
     }
 
+    #[test]
+    fn test_load_parameters_round_trip() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters {
+            amount: 7,
+            border: 150.0,
+            friction: 0.25,
+            timestep: 0.001,
+            gravity_constant: 3.0,
+            max_velocity: 500.0,
+            position_bucket_size: 5.0,
+            velocity_bucket_size: 50.0,
+            seed: Some(42),
+            force_exponent: 1.5,
+            attraction_constant: 0.5,
+            repulsion_constant: 3.0,
+            boundary_shape: BoundaryShape::Periodic,
+            force_law: ForceLaw::LennardJones {
+                epsilon: 2.0,
+                sigma: 4.0,
+            },
+            temperature: 0.5,
+            recenter: true,
+            velocity_clamp: VelocityClamp::Magnitude,
+            sample_every: 3,
+            ..Parameters::default()
+        };
+        parameters.add_particle_kind(42.0);
+        parameters.particle_parameters[1].frozen = true;
+        parameters.particle_parameters[2].collision_radius = Some(1.5);
+        parameters.interactions = vec![
+            InteractionType::Attraction, // 0 <-> 0
+            InteractionType::Neutral,    // 1 <-> 0
+            InteractionType::Repulsion,  // 2 <-> 0
+            InteractionType::Attraction, // 3 <-> 0
+            InteractionType::Repulsion,  // 1 <-> 1
+            InteractionType::Neutral,    // 1 <-> 2
+            InteractionType::Repulsion,  // 1 <-> 3
+            InteractionType::Attraction, // 2 <-> 2
+            InteractionType::Neutral,    // 2 <-> 3
+            InteractionType::Repulsion,  // 3 <-> 3
+        ];
+        let mut other = Parameters::default();
+        persist_parameters(&mut other, &tx_provider).unwrap();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+
+        let loaded = load_parameters(parameters.run_id.unwrap(), &tx_provider).unwrap();
+
+        assert_eq!(loaded, parameters);
+        assert_eq!(
+            load_parameters(other.run_id.unwrap(), &tx_provider).unwrap(),
+            other
+        );
+    }
+
+    #[test]
+    fn test_load_parameters_unknown_run() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+
+        let error = load_parameters(1, &tx_provider).unwrap_err();
+
+        assert_eq!(error.to_string(), "No run with id 1");
+    }
+
+    #[test]
+    fn test_load_parameters_without_config() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        // Like a run persisted before the configuration was stored
+        tx_provider
+            .prepare("UPDATE run_parameters SET config = NULL;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+
+        let error = load_parameters(parameters.run_id.unwrap(), &tx_provider).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Run 1 was persisted without its configuration and can't be restored"
+        );
+    }
+
     #[test]
     fn test_increment_state_count() {
         let mut connection_provider = open_memory_database();
@@ -284,22 +1061,15 @@ mod tests {
 
         let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
         let mut parameters = Parameters {
+            run_id: None,
             amount: 10,
             border: 200.0,
             friction: 0.0,
             timestep: 0.0002,
             gravity_constant: 1.0,
             particle_parameters: vec![
-                ParticleParameters {
-                    id: None,
-                    mass: 3.0,
-                    index: 0,
-                },
-                ParticleParameters {
-                    id: None,
-                    mass: 250.0,
-                    index: 1,
-                },
+                ParticleParameters::new(0, 3.0),
+                ParticleParameters::new(1, 250.0),
             ],
             interactions: vec![
                 InteractionType::Attraction, // 0 <-> 0
@@ -307,7 +1077,21 @@ mod tests {
                 InteractionType::Repulsion,  // 1 <-> 1
             ],
             max_velocity: 20000.0,
-            bucket_size: 10.0,
+            position_bucket_size: 10.0,
+            velocity_bucket_size: 10.0,
+            sample_every: 1,
+            seed: None,
+            convergence_interval: 0,
+            convergence_threshold: 0.01,
+            temperature: 0.0,
+            velocity_clamp: VelocityClamp::PerAxis,
+            boundary_shape: BoundaryShape::Sphere,
+            sphere_subdivisions: 16,
+            force_exponent: 2.0,
+            attraction_constant: 1.0,
+            repulsion_constant: 1.0,
+            force_law: ForceLaw::InversePower,
+            recenter: false,
         };
 
         persist_parameters(&mut parameters, &tx_provider).unwrap();
@@ -316,6 +1100,8 @@ mod tests {
         let state_vector = StateVector::new(
             (0.0, 0.0, 0.0),
             (0.0, 0.0, 0.0),
+            3.0,
+            10.0,
             10.0,
             particle_parameter_id,
         );
@@ -333,4 +1119,662 @@ mod tests {
         let count: i32 = stmt.query_row([], |row| row.get(0)).unwrap();
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn test_retain_new_parameters() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+
+        let spec = SweepSpec {
+            amounts: vec![10],
+            borders: vec![200.0],
+            frictions: vec![0.0, 0.005],
+            timesteps: vec![0.0002],
+            gravity_constants: vec![1.0],
+            force_exponents: vec![2.0],
+            attraction_constants: vec![1.0],
+            repulsion_constants: vec![1.0],
+            max_velocities: vec![20000.0],
+            position_bucket_sizes: vec![10.0],
+            velocity_bucket_sizes: vec![10.0],
+        };
+        for mut parameters in Parameters::parameter_space_from_spec(&spec, &Parameters::default()) {
+            persist_parameters(&mut parameters, &tx_provider).unwrap();
+            update_run_elapsed(parameters.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+        }
+        let appended_spec = SweepSpec::from_toml(
+            "amounts = [10, 20]
+             borders = [200.0]
+             frictions = [0.005, 0.01]
+             timesteps = [0.0002]
+             gravity_constants = [1.0]
+             max_velocities = [20000.0]
+             bucket_sizes = [10.0]",
+        )
+        .unwrap();
+        let appended_space =
+            Parameters::parameter_space_from_spec(&appended_spec, &Parameters::default());
+        assert_eq!(appended_space.len(), 4);
+        // Neither an interrupted run nor one with other interactions covers a point of the sweep
+        let point = |amount: usize, friction: f32| {
+            appended_space
+                .clone()
+                .find(|p| p.amount == amount && p.friction == friction)
+                .unwrap()
+        };
+        let mut interrupted = point(20, 0.005);
+        persist_parameters(&mut interrupted, &tx_provider).unwrap();
+        let mut other_interactions = point(10, 0.01);
+        other_interactions.interactions[0] = InteractionType::Attraction;
+        persist_parameters(&mut other_interactions, &tx_provider).unwrap();
+        update_run_elapsed(other_interactions.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+
+        let new_parameters = retain_new_parameters(appended_space, &tx_provider).unwrap();
+        let new_points = new_parameters
+            .iter()
+            .map(|p| (p.amount, p.friction))
+            .collect::<Vec<_>>();
+        assert_eq!(new_points, vec![(10, 0.01), (20, 0.005), (20, 0.01)]);
+    }
+
+    #[test]
+    fn test_retain_new_parameters_distinguishes_seeds() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let parameter_space = Parameters::random_parameter_space(3, 1, &Parameters::default());
+        let mut parameters = parameter_space[0].clone();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        update_run_elapsed(parameters.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+
+        let seed: Option<u64> = tx_provider
+            .prepare("SELECT seed FROM run_parameters;")
+            .unwrap()
+            .query_row([], |row| row.get(0))
+            .unwrap();
+        assert_eq!(seed, parameters.seed);
+
+        let new_parameters = retain_new_parameters(parameter_space.clone(), &tx_provider).unwrap();
+        assert_eq!(new_parameters, parameter_space[1..].to_vec());
+    }
+
+    #[test]
+    fn test_parameters_already_run() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters {
+            friction: 0.01,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+
+        assert!(!parameters_already_run(&parameters, &tx_provider).unwrap());
+
+        let state_vector = StateVector::new(
+            (0.0, 0.0, 0.0),
+            (0.0, 0.0, 0.0),
+            parameters.particle_parameters[0].mass,
+            parameters.position_bucket_size,
+            parameters.velocity_bucket_size,
+            parameters.particle_parameters[0].id.unwrap(),
+        );
+        increment_state_count(&state_vector, &tx_provider).unwrap();
+        update_run_elapsed(parameters.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+
+        assert!(parameters_already_run(&parameters, &tx_provider).unwrap());
+        assert!(!parameters_already_run(&other_parameters, &tx_provider).unwrap());
+    }
+
+    #[test]
+    fn test_parameters_already_run_requires_completion() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        // An interrupted run flushed its first state vectors but never persisted its elapsed time
+        let state_vector = StateVector::new(
+            (0.0, 0.0, 0.0),
+            (0.0, 0.0, 0.0),
+            parameters.particle_parameters[0].mass,
+            parameters.position_bucket_size,
+            parameters.velocity_bucket_size,
+            parameters.particle_parameters[0].id.unwrap(),
+        );
+        increment_state_count(&state_vector, &tx_provider).unwrap();
+
+        assert!(!parameters_already_run(&parameters, &tx_provider).unwrap());
+
+        update_run_elapsed(parameters.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+
+        assert!(parameters_already_run(&parameters, &tx_provider).unwrap());
+    }
+
+    #[test]
+    fn test_parameters_already_run_distinguishes_interactions() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        let state_vector = StateVector::new(
+            (0.0, 0.0, 0.0),
+            (0.0, 0.0, 0.0),
+            parameters.particle_parameters[0].mass,
+            parameters.position_bucket_size,
+            parameters.velocity_bucket_size,
+            parameters.particle_parameters[0].id.unwrap(),
+        );
+        increment_state_count(&state_vector, &tx_provider).unwrap();
+        update_run_elapsed(parameters.run_id.unwrap(), 1.0, &tx_provider).unwrap();
+        let mut other_parameters = Parameters::default();
+        other_parameters.interactions[0] = InteractionType::Attraction;
+
+        assert!(parameters_already_run(&parameters, &tx_provider).unwrap());
+        assert!(!parameters_already_run(&other_parameters, &tx_provider).unwrap());
+        assert_eq!(find_run_id(&other_parameters, &tx_provider).unwrap(), None);
+    }
+
+    #[test]
+    fn test_increment_state_counts() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        let particle_parameter_id = parameters.particle_parameters[0].id.unwrap();
+
+        let state_vectors = [(0.0, 0.0, 0.0), (0.0, 0.0, 0.0), (50.0, 0.0, 0.0)].map(|position| {
+            StateVector::new(
+                position,
+                (0.0, 0.0, 0.0),
+                3.0,
+                10.0,
+                10.0,
+                particle_parameter_id,
+            )
+        });
+        increment_state_counts(&state_vectors, &tx_provider).unwrap();
+        commit_transaction(tx_provider).unwrap();
+
+        let mut stmt = connection_provider
+            .connection
+            .prepare("SELECT px, count FROM state_vectors ORDER BY px;")
+            .unwrap();
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i32, i32)>>>()
+            .unwrap();
+        assert_eq!(counts, vec![(0, 2), (5, 1)]);
+    }
+
+    #[test]
+    fn test_increment_state_count_distinguishes_mass() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        let particle_parameter_id = parameters.particle_parameters[0].id.unwrap();
+
+        let state_vectors = [3.0, 250.0].map(|mass| {
+            StateVector::new(
+                (0.0, 0.0, 0.0),
+                (0.0, 0.0, 0.0),
+                mass,
+                10.0,
+                10.0,
+                particle_parameter_id,
+            )
+        });
+        increment_state_counts(&state_vectors, &tx_provider).unwrap();
+        commit_transaction(tx_provider).unwrap();
+
+        let mut stmt = connection_provider
+            .connection
+            .prepare("SELECT mass, count FROM state_vectors ORDER BY mass;")
+            .unwrap();
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i32, i32)>>>()
+            .unwrap();
+        assert_eq!(counts, vec![(3, 1), (250, 1)]);
+    }
+
+    #[test]
+    fn test_top_states() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters {
+            amount: 20,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+        let run_id = find_run_id(&parameters, &tx_provider).unwrap().unwrap();
+        let ids = parameters
+            .particle_parameters
+            .iter()
+            .map(|p| p.id.unwrap())
+            .collect::<Vec<_>>();
+        let state_vector = |x: f32, id: usize| {
+            StateVector::new((x, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id)
+        };
+
+        let mut state_vectors = vec![];
+        state_vectors.extend((0..3).map(|_| state_vector(0.0, ids[0])));
+        state_vectors.extend((0..5).map(|_| state_vector(10.0, ids[0])));
+        state_vectors.extend((0..4).map(|_| state_vector(20.0, ids[1])));
+        state_vectors.extend(
+            (0..9).map(|_| state_vector(0.0, other_parameters.particle_parameters[0].id.unwrap())),
+        );
+        increment_state_counts(&state_vectors, &tx_provider).unwrap();
+
+        let top = top_states(run_id, None, 2, &tx_provider).unwrap();
+        assert_eq!(
+            top,
+            vec![
+                (state_vector(10.0, ids[0]), 5),
+                (state_vector(20.0, ids[1]), 4)
+            ]
+        );
+
+        let top = top_states(run_id, Some(ids[0]), 10, &tx_provider).unwrap();
+        assert_eq!(
+            top,
+            vec![
+                (state_vector(10.0, ids[0]), 5),
+                (state_vector(0.0, ids[0]), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_run_csv() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters {
+            amount: 20,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+        let id = parameters.particle_parameters[0].id.unwrap();
+        let other_id = other_parameters.particle_parameters[0].id.unwrap();
+        increment_state_counts(
+            &[
+                StateVector::new((20.0, 0.0, -10.0), (0.0, 30.0, 0.0), 3.0, 10.0, 10.0, id),
+                StateVector::new((20.0, 0.0, -10.0), (0.0, 30.0, 0.0), 3.0, 10.0, 10.0, id),
+                StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id),
+                StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, other_id),
+            ],
+            &tx_provider,
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("atomata-{}.csv", std::process::id()));
+
+        export_run_csv(parameters.run_id.unwrap(), &path, &tx_provider).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            csv,
+            format!(
+                "px,py,pz,vx,vy,vz,mass,count,particle_parameters_id\n\
+                 0,0,0,0,0,0,3,1,{id}\n\
+                 2,0,-1,0,3,0,3,2,{id}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_list_runs() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters {
+            amount: 20,
+            friction: 0.01,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+        let ids = parameters
+            .particle_parameters
+            .iter()
+            .map(|p| p.id.unwrap())
+            .collect::<Vec<_>>();
+        increment_state_counts(
+            &[
+                StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, ids[0]),
+                StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, ids[0]),
+                StateVector::new((10.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, ids[0]),
+                StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 250.0, 10.0, 10.0, ids[1]),
+            ],
+            &tx_provider,
+        )
+        .unwrap();
+
+        let runs = list_runs(&tx_provider).unwrap();
+
+        assert_eq!(runs.len(), 2);
+        assert!(!runs[0].created_at.is_empty());
+        let summary = |parameters: &Parameters, created_at: &str, state_vector_rows| RunSummary {
+            run_id: parameters.run_id.unwrap(),
+            amount: parameters.amount,
+            border: parameters.border,
+            timestep: parameters.timestep,
+            gravity_constant: parameters.gravity_constant,
+            friction: parameters.friction,
+            max_velocity: parameters.max_velocity,
+            position_bucket_size: parameters.position_bucket_size,
+            velocity_bucket_size: parameters.velocity_bucket_size,
+            created_at: created_at.to_string(),
+            state_vector_rows,
+        };
+        assert_eq!(
+            runs,
+            vec![
+                summary(&parameters, &runs[0].created_at, 3),
+                summary(&other_parameters, &runs[1].created_at, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rank_runs_by_metric() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut run_ids = vec![];
+        for (amount, entropy) in [
+            (10, Some(0.5)),
+            (20, Some(2.0)),
+            (30, None),
+            (40, Some(1.0)),
+        ] {
+            let mut parameters = Parameters {
+                amount,
+                ..Parameters::default()
+            };
+            persist_parameters(&mut parameters, &tx_provider).unwrap();
+            let run_id = parameters.run_id.unwrap();
+            if let Some(entropy) = entropy {
+                persist_run_metric(run_id, "entropy", entropy, &tx_provider).unwrap();
+            }
+            // Other metrics must not interfere with the ranking
+            persist_run_metric(run_id, "kinetic_energy", 100.0, &tx_provider).unwrap();
+            run_ids.push(run_id);
+        }
+
+        let ranked = rank_runs_by_metric("entropy", 2, &tx_provider).unwrap();
+
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|(run, value)| (run.run_id, run.amount, *value))
+                .collect::<Vec<_>>(),
+            vec![(run_ids[1], 20, 2.0), (run_ids[3], 40, 1.0)]
+        );
+        assert_eq!(
+            rank_runs_by_metric("entropy", 10, &tx_provider)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(rank_runs_by_metric("unknown", 10, &tx_provider)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_open_database_enables_foreign_keys() {
+        let mut connection_provider =
+            open_database(":memory:", &PersistenceOptions::default()).unwrap();
+        let foreign_keys: bool = connection_provider
+            .connection
+            .pragma_query_value(None, "foreign_keys", |row| row.get(0))
+            .unwrap();
+        assert!(foreign_keys);
+
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        let id = parameters.particle_parameters[0].id.unwrap();
+        increment_state_count(
+            &StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id),
+            &tx_provider,
+        )
+        .unwrap();
+
+        tx_provider
+            .prepare("DELETE FROM run_parameters;")
+            .unwrap()
+            .execute([])
+            .unwrap();
+
+        for table in ["particle_parameters", "interactions", "state_vectors"] {
+            let count: usize = tx_provider
+                .prepare(&format!("SELECT count(*) FROM {};", table))
+                .unwrap()
+                .query_row([], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 0, "{} wasn't cleaned up", table);
+        }
+    }
+
+    #[test]
+    fn test_state_vectors_index_used_for_filtered_count() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+
+        let plan = connection_provider
+            .connection
+            .prepare(
+                "EXPLAIN QUERY PLAN
+                 SELECT count(*) FROM state_vectors WHERE particle_parameters_id = ?1;",
+            )
+            .unwrap()
+            .query_map([1], |row| row.get::<_, String>(3))
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap()
+            .join("\n");
+
+        assert!(
+            plan.contains("INDEX state_vectors_particle_parameters_id_count"),
+            "{}",
+            plan
+        );
+    }
+
+    #[test]
+    fn test_delete_run() {
+        let mut connection_provider =
+            open_database(":memory:", &PersistenceOptions::default()).unwrap();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters {
+            amount: 20,
+            ..Parameters::default()
+        };
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+        let state_vectors = [&parameters, &other_parameters].map(|parameters| {
+            let id = parameters.particle_parameters[0].id.unwrap();
+            StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id)
+        });
+        increment_state_counts(&state_vectors, &tx_provider).unwrap();
+        let run_id = parameters.run_id.unwrap();
+        persist_run_metric(run_id, "entropy", 0.5, &tx_provider).unwrap();
+
+        delete_run(run_id, &tx_provider).unwrap();
+
+        let count = |sql: &str| -> usize {
+            tx_provider
+                .prepare(sql)
+                .unwrap()
+                .query_row([], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("SELECT count(*) FROM run_parameters;"), 1);
+        assert_eq!(
+            count("SELECT count(*) FROM particle_parameters;"),
+            other_parameters.particle_parameters.len()
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM interactions;"),
+            other_parameters.interactions.len()
+        );
+        assert_eq!(count("SELECT count(*) FROM state_vectors;"), 1);
+        assert_eq!(count("SELECT count(*) FROM run_metrics;"), 0);
+    }
+
+    #[test]
+    fn test_sqlite_result_store() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let mut store = SqliteResultStore::new(connection_provider);
+        let mut parameters = Parameters::default();
+
+        store.persist_parameters(&mut parameters).unwrap();
+        let id = parameters.particle_parameters[0].id.unwrap();
+        let state_vector = StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id);
+        store.increment_state_count(&state_vector).unwrap();
+        store.increment_state_count(&state_vector).unwrap();
+        store
+            .persist_run_metric(parameters.run_id.unwrap(), "entropy", 0.5)
+            .unwrap();
+        store.commit().unwrap();
+        // Committing without pending writes is fine
+        store.commit().unwrap();
+
+        let connection = &store.connection_provider.connection;
+        assert!(connection.is_autocommit());
+        let count: u64 = connection
+            .query_row("SELECT count FROM state_vectors;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let value: f64 = connection
+            .query_row("SELECT value FROM run_metrics;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, 0.5);
+    }
+
+    #[test]
+    fn test_export_metrics_json() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut first = Parameters {
+            seed: Some(3),
+            ..Parameters::default()
+        };
+        let mut second = Parameters {
+            amount: 20,
+            ..Parameters::default()
+        };
+        let mut unmeasured = Parameters::default();
+        persist_parameters(&mut first, &tx_provider).unwrap();
+        persist_parameters(&mut second, &tx_provider).unwrap();
+        persist_parameters(&mut unmeasured, &tx_provider).unwrap();
+        persist_run_metric(first.run_id.unwrap(), "entropy", 0.5, &tx_provider).unwrap();
+        persist_run_metric(first.run_id.unwrap(), "kinetic_energy", 2.0, &tx_provider).unwrap();
+        persist_run_metric(second.run_id.unwrap(), "entropy", 0.25, &tx_provider).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("atomata-metrics-{}.json", std::process::id()));
+
+        export_metrics_json(&path, &tx_provider).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let runs: Vec<RunMetrics> = serde_json::from_str(&content).unwrap();
+        let run_metrics = |parameters: &Parameters, metrics: &[(&str, f64)]| RunMetrics {
+            run_id: parameters.run_id.unwrap(),
+            amount: parameters.amount,
+            border: parameters.border,
+            timestep: parameters.timestep,
+            gravity_constant: parameters.gravity_constant,
+            friction: parameters.friction,
+            max_velocity: parameters.max_velocity,
+            position_bucket_size: parameters.position_bucket_size,
+            velocity_bucket_size: parameters.velocity_bucket_size,
+            seed: parameters.seed,
+            force_exponent: parameters.force_exponent,
+            attraction_constant: parameters.attraction_constant,
+            repulsion_constant: parameters.repulsion_constant,
+            metrics: metrics
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        };
+        assert_eq!(
+            runs,
+            vec![
+                run_metrics(&first, &[("entropy", 0.5), ("kinetic_energy", 2.0)]),
+                run_metrics(&second, &[("entropy", 0.25)]),
+                run_metrics(&unmeasured, &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_run_elapsed() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        persist_parameters(&mut other_parameters, &tx_provider).unwrap();
+
+        update_run_elapsed(parameters.run_id.unwrap(), 1.25, &tx_provider).unwrap();
+
+        let elapsed_times: Vec<Option<f64>> = tx_provider
+            .prepare("SELECT elapsed_time_s FROM run_parameters ORDER BY run_id;")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(elapsed_times, vec![Some(1.25), None]);
+    }
+
+    #[test]
+    fn test_persist_run_metric() {
+        let mut connection_provider = open_memory_database();
+        migrate_to_latest(&mut connection_provider).unwrap();
+        let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+        let mut parameters = Parameters::default();
+        persist_parameters(&mut parameters, &tx_provider).unwrap();
+        let run_id = parameters.run_id.unwrap();
+
+        persist_run_metric(run_id, "entropy", 0.25, &tx_provider).unwrap();
+        persist_run_metric(run_id, "entropy", 0.5, &tx_provider).unwrap();
+        commit_transaction(tx_provider).unwrap();
+
+        let mut stmt = connection_provider
+            .connection
+            .prepare("SELECT run_id, name, value FROM run_metrics;")
+            .unwrap();
+        let metrics = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(usize, String, f64)>>>()
+            .unwrap();
+        assert_eq!(metrics, vec![(run_id, "entropy".to_string(), 0.5)]);
+    }
 }
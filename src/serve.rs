@@ -0,0 +1,193 @@
+//! Headless simulation streaming its particles over HTTP, for external visualizers and
+//! dashboards.
+//!
+//! Every frame is a JSON array with one object per particle:
+//!
+//! ```json
+//! [{"index": 0, "position": [1.5, -3.0, 20.0], "velocity": [10.0, 0.0, -2.5]}, ...]
+//! ```
+//!
+//! `index` is the index of the particle's kind in the parameters. Consumers either poll
+//! `GET /frame` for the latest frame or subscribe to `GET /events`, a server-sent event stream
+//! with one `data:` event per frame.
+
+use std::error::Error;
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::sync::{
+    mpsc::{sync_channel, SyncSender, TrySendError},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::particle::Particle;
+use crate::Simulation;
+
+/// Pause between two simulation steps, i.e. frames, of a served simulation.
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Frames buffered for an event stream subscriber. Frames published while the buffer is full are
+/// dropped for that subscriber, so a slow consumer can't make the server run out of memory.
+const SUBSCRIBER_BUFFER: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParticleFrame {
+    pub index: usize,
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+}
+
+impl From<&Particle> for ParticleFrame {
+    fn from(particle: &Particle) -> Self {
+        let velocity = particle.velocity();
+        Self {
+            index: particle.index,
+            position: [
+                particle.position.x,
+                particle.position.y,
+                particle.position.z,
+            ],
+            velocity: [velocity.x, velocity.y, velocity.z],
+        }
+    }
+}
+
+/// Returns the current frame of the simulation serialized as JSON.
+pub fn frame_json(simulation: &Simulation) -> String {
+    let frame = simulation
+        .particles
+        .iter()
+        .map(ParticleFrame::from)
+        .collect::<Vec<_>>();
+    serde_json::to_string(&frame).expect("Frames are always representable as JSON")
+}
+
+/// HTTP server publishing the frames of a simulation.
+pub struct FrameServer {
+    server: Arc<Server>,
+    latest_frame: Arc<Mutex<String>>,
+    subscribers: Arc<Mutex<Vec<SyncSender<String>>>>,
+}
+
+impl FrameServer {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self {
+            server: Arc::new(Server::http(address)?),
+            latest_frame: Arc::new(Mutex::new("[]".to_string())),
+            subscribers: Arc::new(Mutex::new(vec![])),
+        })
+    }
+
+    /// Port the server listens on, which is chosen by the OS if it was bound to port zero.
+    pub fn port(&self) -> Option<u16> {
+        self.server
+            .server_addr()
+            .to_ip()
+            .map(|address| address.port())
+    }
+
+    /// Steps the simulation forever, publishing every frame. Only returns if a step fails.
+    pub fn run(self, mut simulation: Simulation, frame_interval: Duration) -> Result<(), String> {
+        self.publish(frame_json(&simulation));
+
+        let server = Arc::clone(&self.server);
+        let latest_frame = Arc::clone(&self.latest_frame);
+        let subscribers = Arc::clone(&self.subscribers);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let latest_frame = Arc::clone(&latest_frame);
+                let subscribers = Arc::clone(&subscribers);
+                // Event streams keep their request busy, so every request gets its own thread
+                thread::spawn(move || {
+                    if let Err(e) = respond(request, &latest_frame, &subscribers) {
+                        warn!("Failed to respond to request: {}", e);
+                    }
+                });
+            }
+        });
+
+        loop {
+            simulation.step()?;
+            self.publish(frame_json(&simulation));
+            thread::sleep(frame_interval);
+        }
+    }
+
+    fn publish(&self, frame: String) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            match subscriber.try_send(frame.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        *self.latest_frame.lock().unwrap() = frame;
+    }
+}
+
+fn respond(
+    request: Request,
+    latest_frame: &Mutex<String>,
+    subscribers: &Mutex<Vec<SyncSender<String>>>,
+) -> std::io::Result<()> {
+    match request.url() {
+        "/frame" => {
+            let frame = latest_frame.lock().unwrap().clone();
+            request.respond(
+                Response::from_string(frame)
+                    .with_header(header("Content-Type", "application/json")),
+            )
+        }
+        "/events" => {
+            let (sender, receiver) = sync_channel(SUBSCRIBER_BUFFER);
+            subscribers.lock().unwrap().push(sender);
+            // Written directly to the connection, as tiny_http would buffer a streamed response
+            // body into large chunks instead of sending every event right away
+            let mut writer = request.into_writer();
+            writer.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+                  Connection: close\r\n\r\n",
+            )?;
+            writer.flush()?;
+            for frame in receiver {
+                writer.write_all(event(&frame).as_bytes())?;
+                writer.flush()?;
+            }
+            Ok(())
+        }
+        _ => request.respond(Response::empty(404)),
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Headers are valid ASCII")
+}
+
+/// Formats a frame as a server-sent event.
+fn event(frame: &str) -> String {
+    format!("data: {}\n\n", frame)
+}
+
+/// Runs the simulation headless and serves its frames on the given port of the loopback
+/// interface, so only local consumers can connect.
+pub fn serve(port: u16, simulation: Simulation) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let server = FrameServer::bind(("127.0.0.1", port))?;
+    info!("Serving frames on port {}", port);
+    server.run(simulation, FRAME_INTERVAL)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(event("[]"), "data: []\n\n");
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use three_d::{vec3, Srgba};
+
+use crate::parameters::Parameters;
+use crate::particle::Particle;
+
+/// Exact live state of a simulation, unlike the bucketed state vectors persisted by the search.
+/// A simulation restored from it continues exactly like the one it was taken from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub parameters: Parameters,
+    pub particles: Vec<ParticleSnapshot>,
+    /// Color of each particle kind by its index as RGBA
+    pub colors: Vec<[u8; 4]>,
+    /// Seed of the random number generator of the simulation
+    pub rng_seed: [u8; 32],
+    /// Number of words the random number generator had produced when the snapshot was taken
+    pub rng_word_pos: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParticleSnapshot {
+    pub index: usize,
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub mass: f32,
+    pub max_velocity: f32,
+}
+
+impl From<&Particle> for ParticleSnapshot {
+    fn from(particle: &Particle) -> Self {
+        let velocity = particle.velocity();
+        Self {
+            index: particle.index,
+            position: particle.position.into(),
+            velocity: velocity.into(),
+            mass: particle.mass,
+            max_velocity: particle.max_velocity(),
+        }
+    }
+}
+
+impl From<&ParticleSnapshot> for Particle {
+    fn from(snapshot: &ParticleSnapshot) -> Self {
+        let [px, py, pz] = snapshot.position;
+        let [vx, vy, vz] = snapshot.velocity;
+        Particle::from_state(
+            snapshot.index,
+            vec3(px, py, pz),
+            vec3(vx, vy, vz),
+            snapshot.mass,
+            snapshot.max_velocity,
+        )
+    }
+}
+
+impl Snapshot {
+    pub fn colors(&self) -> Vec<Srgba> {
+        self.colors
+            .iter()
+            .map(|[r, g, b, a]| Srgba::new(*r, *g, *b, *a))
+            .collect()
+    }
+
+    /// Writes the snapshot as JSON, which keeps every float exactly.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content)
+            .map_err(|e| format!("Can't write snapshot {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read snapshot {}: {}", path.display(), e))?;
+        let snapshot: Snapshot = serde_json::from_str(&content)
+            .map_err(|e| format!("Can't parse snapshot {}: {}", path.display(), e))?;
+        snapshot.parameters.validate()?;
+        Ok(snapshot)
+    }
+}
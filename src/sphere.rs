@@ -1,37 +1,430 @@
-use three_d::{Context, CpuMaterial, CpuMesh, Gm, Mat4, Mesh, PhysicalMaterial, Srgba, Vector3};
+use std::collections::VecDeque;
 
-pub trait PositionableRender {
-    fn set_position(&mut self, position: Vector3<f32>);
-    fn get_geometry(&self) -> &Gm<Mesh, PhysicalMaterial>;
+use crate::analysis::FieldSample;
+use crate::parameters::MIN_SPHERE_SUBDIVISIONS;
+use crate::particle::Particle;
+use three_d::{
+    Context, CpuMaterial, CpuMesh, Gm, InnerSpace, InstancedMesh, Instances, Mat4,
+    PhysicalMaterial, Srgba, Vector3,
+};
+
+/// Radius of the trail spheres relative to the particle spheres
+const TRAIL_SCALE: f32 = 0.4;
+
+/// Material of the particle and trail spheres. The defaults are the opaque, non-metallic and
+/// fully rough material the spheres always had.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SphereMaterial {
+    /// Multiplies the alpha of the kind colors, lower values let dense clusters be seen into
+    pub opacity: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for SphereMaterial {
+    fn default() -> Self {
+        Self {
+            opacity: 1.0,
+            metallic: 0.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+impl SphereMaterial {
+    /// Returns the white material tinted by the instance colors, with the opacity as its alpha.
+    fn cpu_material(&self) -> CpuMaterial {
+        CpuMaterial {
+            albedo: Srgba::new(
+                255,
+                255,
+                255,
+                (self.opacity.clamp(0.0, 1.0) * 255.0).round() as u8,
+            ),
+            metallic: self.metallic,
+            roughness: self.roughness,
+            ..Default::default()
+        }
+    }
+}
+
+/// Ring buffer of the last positions of a particle, oldest first.
+#[derive(Debug, Default)]
+pub struct Trail {
+    positions: VecDeque<Vector3<f32>>,
+    length: usize,
+}
+
+impl Trail {
+    /// Sets the maximum number of positions, dropping the oldest ones that no longer fit.
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+        while self.positions.len() > length {
+            self.positions.pop_front();
+        }
+    }
+
+    pub fn push(&mut self, position: Vector3<f32>) {
+        if self.length == 0 {
+            return;
+        }
+        if self.positions.len() == self.length {
+            self.positions.pop_front();
+        }
+        self.positions.push_back(position);
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &Vector3<f32>> {
+        self.positions.iter()
+    }
+
+    /// Returns the transformation and color of one instance per position, shrinking and fading
+    /// out towards the oldest one.
+    fn instances(&self, color: Srgba) -> impl Iterator<Item = (Mat4, Srgba)> + '_ {
+        let len = self.positions.len() as f32;
+        self.positions().enumerate().map(move |(age, position)| {
+            let fraction = (age + 1) as f32 / len;
+            (
+                Mat4::from_translation(*position) * Mat4::from_scale(TRAIL_SCALE * fraction),
+                Srgba::new(color.r, color.g, color.b, (fraction * 160.0) as u8),
+            )
+        })
+    }
 }
 
-pub struct Sphere {
-    pub geometry: Gm<Mesh, PhysicalMaterial>,
+/// Renders all particles as instances of one sphere mesh, colored by their kind, and their
+/// trails as instances of a second, coarser one.
+///
+/// Each frame only uploads one transformation and color per particle and trail point, and the
+/// particles take a single draw call. With a separate mesh per particle there was one draw call
+/// per particle instead, i.e. `amount × particle kinds` per frame, whose overhead dominated the
+/// frame time from a few hundred particles on. At `amount = 1000` with three kinds, rendering is
+/// expected to get about an order of magnitude faster, leaving the simulation step as the
+/// bottleneck.
+pub struct ParticleRenderer {
+    spheres: Gm<InstancedMesh, PhysicalMaterial>,
+    trail_spheres: Gm<InstancedMesh, PhysicalMaterial>,
+    /// Trail of each particle, in the order of the particles
+    trails: Vec<Trail>,
+    trail_length: usize,
+    /// Instances of the last update, kept to reuse their buffers in the next one
+    sphere_instances: Instances,
+    trail_instances: Instances,
+    material: SphereMaterial,
 }
 
-impl Sphere {
-    pub fn new(context: &Context, color: Srgba) -> Self {
-        let geometry = Gm::new(
-            Mesh::new(context, &CpuMesh::sphere(16)),
-            PhysicalMaterial::new_transparent(
+impl ParticleRenderer {
+    /// Creates a renderer whose sphere mesh has the given angle subdivisions, see
+    /// `Parameters::sphere_subdivisions`. Trail spheres use half as many.
+    pub fn new(context: &Context, subdivisions: u32) -> Self {
+        let material = SphereMaterial::default();
+        Self {
+            spheres: instanced_spheres(context, subdivisions, material),
+            trail_spheres: instanced_spheres(
                 context,
-                &CpuMaterial {
-                    albedo: color,
-                    ..Default::default()
-                },
+                (subdivisions / 2).max(MIN_SPHERE_SUBDIVISIONS),
+                material,
             ),
+            trails: vec![],
+            trail_length: 0,
+            sphere_instances: Instances::default(),
+            trail_instances: Instances::default(),
+            material,
+        }
+    }
+
+    /// Replaces the sphere meshes by ones with the given subdivisions. The instances are set
+    /// again by the next call to `update`.
+    pub fn set_subdivisions(&mut self, context: &Context, subdivisions: u32) {
+        self.spheres = instanced_spheres(context, subdivisions, self.material);
+        self.trail_spheres = instanced_spheres(
+            context,
+            (subdivisions / 2).max(MIN_SPHERE_SUBDIVISIONS),
+            self.material,
         );
+    }
+
+    /// Rebuilds the materials of the particle and trail spheres if the given one differs from the
+    /// current one, so it can be called every frame.
+    pub fn set_material(&mut self, context: &Context, material: SphereMaterial) {
+        if self.material == material {
+            return;
+        }
+        self.material = material;
+        let cpu_material = material.cpu_material();
+        self.spheres.material = PhysicalMaterial::new_transparent(context, &cpu_material);
+        self.trail_spheres.material = PhysicalMaterial::new_transparent(context, &cpu_material);
+    }
+
+    /// Sets how many past positions are kept in the trails, zero disables them.
+    pub fn set_trail_length(&mut self, length: usize) {
+        if self.trail_length == length {
+            return;
+        }
+        self.trail_length = length;
+        for trail in self.trails.iter_mut() {
+            trail.set_length(length);
+        }
+    }
+
+    /// Starts all trails over, e.g. after the particles were recreated.
+    pub fn clear_trails(&mut self) {
+        self.trails.clear();
+    }
+
+    /// Records the current positions of the particles in their trails.
+    pub fn push_trails(&mut self, particles: &[Particle]) {
+        if self.trail_length == 0 {
+            return;
+        }
+        let trail_length = self.trail_length;
+        self.trails.resize_with(particles.len(), || {
+            let mut trail = Trail::default();
+            trail.set_length(trail_length);
+            trail
+        });
+        for (trail, particle) in self.trails.iter_mut().zip(particles) {
+            trail.push(particle.position);
+        }
+    }
+
+    /// Moves the instances to the current positions of the particles. `colors` holds the color
+    /// of each particle kind by its index.
+    pub fn update(&mut self, particles: &[Particle], colors: &[Srgba]) {
+        let kind_color =
+            |particle: &Particle| colors.get(particle.index).copied().unwrap_or(Srgba::WHITE);
+
+        refill_instances(&mut self.sphere_instances, || {
+            particles.iter().map(|particle| {
+                (
+                    Mat4::from_translation(particle.position),
+                    kind_color(particle),
+                )
+            })
+        });
+        self.spheres.geometry.set_instances(&self.sphere_instances);
+
+        let trail_length = self.trail_length;
+        let trails = &self.trails;
+        refill_instances(&mut self.trail_instances, || {
+            trails
+                .iter()
+                .zip(particles)
+                .filter(|_| trail_length > 0)
+                .flat_map(|(trail, particle)| trail.instances(kind_color(particle)))
+        });
+        self.trail_spheres
+            .geometry
+            .set_instances(&self.trail_instances);
+    }
 
-        Self { geometry }
+    pub fn spheres(&self) -> &Gm<InstancedMesh, PhysicalMaterial> {
+        &self.spheres
     }
+
+    pub fn trails(&self) -> &Gm<InstancedMesh, PhysicalMaterial> {
+        &self.trail_spheres
+    }
+}
+
+/// Radius of the field sample points relative to the particle spheres
+const FIELD_POINT_SCALE: f32 = 0.6;
+
+/// Renders samples of the force field as points colored from blue for the weakest to red for the
+/// strongest field of the current samples.
+pub struct FieldRenderer {
+    points: Gm<InstancedMesh, PhysicalMaterial>,
+    instances: Instances,
 }
 
-impl PositionableRender for Sphere {
-    fn set_position(&mut self, position: Vector3<f32>) {
-        self.geometry
-            .set_transformation(Mat4::from_translation(position));
+impl FieldRenderer {
+    pub fn new(context: &Context) -> Self {
+        Self {
+            points: instanced_spheres(context, MIN_SPHERE_SUBDIVISIONS, SphereMaterial::default()),
+            instances: Instances::default(),
+        }
     }
-    fn get_geometry(&self) -> &Gm<Mesh, PhysicalMaterial> {
-        &self.geometry
+
+    /// Places one point per sample.
+    pub fn update(&mut self, samples: &[FieldSample]) {
+        let colors = field_colors(samples.iter().map(|sample| sample.field.magnitude()));
+        refill_instances(&mut self.instances, || {
+            samples.iter().zip(&colors).map(|(sample, color)| {
+                (
+                    Mat4::from_translation(sample.position) * Mat4::from_scale(FIELD_POINT_SCALE),
+                    *color,
+                )
+            })
+        });
+        self.points.geometry.set_instances(&self.instances);
+    }
+
+    pub fn points(&self) -> &Gm<InstancedMesh, PhysicalMaterial> {
+        &self.points
+    }
+}
+
+/// Maps field magnitudes to colors on a logarithmic scale between the smallest and the largest
+/// one, as the field of a point mass spans orders of magnitude within a few grid cells.
+fn field_colors(magnitudes: impl Iterator<Item = f32>) -> Vec<Srgba> {
+    let logs = magnitudes
+        .map(|magnitude| magnitude.max(f32::MIN_POSITIVE).ln())
+        .collect::<Vec<_>>();
+    let min = logs.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = logs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    logs.iter()
+        .map(|log| {
+            let fraction = if max > min {
+                (log - min) / (max - min)
+            } else {
+                0.0
+            };
+            Srgba::new(
+                (fraction * 255.0) as u8,
+                0,
+                ((1.0 - fraction) * 255.0) as u8,
+                128,
+            )
+        })
+        .collect()
+}
+
+/// Replaces the instances by the given transformations and colors while keeping their buffers
+/// allocated. Each buffer is extended by its own pass over the items, as that lets it reserve
+/// exactly the needed capacity.
+fn refill_instances<I, F>(instances: &mut Instances, items: F)
+where
+    I: Iterator<Item = (Mat4, Srgba)>,
+    F: Fn() -> I,
+{
+    instances.transformations.clear();
+    instances
+        .transformations
+        .extend(items().map(|(transformation, _)| transformation));
+    let colors = instances.colors.get_or_insert_with(Vec::new);
+    colors.clear();
+    colors.extend(items().map(|(_, color)| color));
+}
+
+/// Returns a sphere mesh without instances. Its white material is tinted by the instance colors.
+fn instanced_spheres(
+    context: &Context,
+    subdivisions: u32,
+    material: SphereMaterial,
+) -> Gm<InstancedMesh, PhysicalMaterial> {
+    Gm::new(
+        InstancedMesh::new(
+            context,
+            &Instances::default(),
+            &CpuMesh::sphere(subdivisions),
+        ),
+        PhysicalMaterial::new_transparent(context, &material.cpu_material()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_sphere_material() {
+        let material = SphereMaterial::default().cpu_material();
+        assert_eq!(material.albedo, Srgba::WHITE);
+        assert_eq!((material.metallic, material.roughness), (0.0, 1.0));
+
+        let material = SphereMaterial {
+            opacity: 0.5,
+            metallic: 0.25,
+            roughness: 0.75,
+        }
+        .cpu_material();
+        assert_eq!(material.albedo, Srgba::new(255, 255, 255, 128));
+        assert_eq!((material.metallic, material.roughness), (0.25, 0.75));
+    }
+
+    #[test]
+    fn test_trail_keeps_last_positions() {
+        let mut trail = Trail::default();
+        trail.set_length(3);
+
+        for x in 0..5 {
+            trail.push(Vector3::new(x as f32, 0.0, 0.0));
+        }
+
+        assert_eq!(
+            trail.positions().map(|p| p.x).collect::<Vec<_>>(),
+            vec![2.0, 3.0, 4.0]
+        );
+
+        trail.set_length(1);
+
+        assert_eq!(
+            trail.positions().map(|p| p.x).collect::<Vec<_>>(),
+            vec![4.0]
+        );
+    }
+
+    #[test]
+    fn test_trail_instances_fade_out_in_the_kind_color() {
+        let mut trail = Trail::default();
+        trail.set_length(2);
+        trail.push(Vector3::new(1.0, 0.0, 0.0));
+        trail.push(Vector3::new(2.0, 0.0, 0.0));
+
+        let colors = trail
+            .instances(Srgba::new(10, 20, 30, 255))
+            .map(|(_, color)| color)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            colors,
+            vec![Srgba::new(10, 20, 30, 80), Srgba::new(10, 20, 30, 160)]
+        );
+    }
+
+    #[test]
+    fn test_refill_instances_matches_fresh_instances() {
+        let items = |n: usize| {
+            move || {
+                (0..n).map(|i| {
+                    (
+                        Mat4::from_translation(Vector3::new(i as f32, 0.0, 0.0)),
+                        Srgba::new(i as u8, 0, 0, 255),
+                    )
+                })
+            }
+        };
+        let mut instances = Instances::default();
+
+        refill_instances(&mut instances, items(5));
+        refill_instances(&mut instances, items(3));
+
+        let (transformations, colors): (Vec<_>, Vec<_>) = items(3)().unzip();
+        assert_eq!(instances.transformations, transformations);
+        assert_eq!(instances.colors, Some(colors));
+    }
+
+    #[test]
+    fn test_field_colors_span_weakest_to_strongest() {
+        let colors = field_colors([1.0, 10.0, 100.0].into_iter());
+
+        assert_eq!(
+            colors,
+            vec![
+                Srgba::new(0, 0, 255, 128),
+                Srgba::new(127, 0, 127, 128),
+                Srgba::new(255, 0, 0, 128),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disabled_trail_records_nothing() {
+        let mut trail = Trail::default();
+
+        trail.push(Vector3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(trail.positions().count(), 0);
     }
 }
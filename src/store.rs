@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::{parameters::Parameters, particle::StateVector};
+
+/// Backend the results of a search are persisted to.
+pub trait ResultStore {
+    /// Persists the parameters of a run and assigns the ids of the run and its particle kinds.
+    fn persist_parameters(&mut self, parameters: &mut Parameters) -> Result<(), Box<dyn Error>>;
+
+    /// Increments the counts of all given state vectors.
+    fn increment_state_counts(
+        &mut self,
+        state_vectors: &[StateVector],
+    ) -> Result<(), Box<dyn Error>>;
+
+    fn increment_state_count(&mut self, state_vector: &StateVector) -> Result<(), Box<dyn Error>> {
+        self.increment_state_counts(std::slice::from_ref(state_vector))
+    }
+
+    /// Stores a named scalar metric of a run, replacing a previous value of the same name.
+    fn persist_run_metric(
+        &mut self,
+        run_id: usize,
+        name: &str,
+        value: f64,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Records how long simulating a run took in seconds.
+    fn persist_elapsed_time(&mut self, run_id: usize, seconds: f64) -> Result<(), Box<dyn Error>>;
+
+    /// Makes everything persisted since the last commit durable.
+    fn commit(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+/// Store keeping all results in memory, e.g. for tests and quick runs that don't need to be
+/// kept.
+#[derive(Debug, Default)]
+pub struct MemoryResultStore {
+    pub runs: Vec<Parameters>,
+    pub state_counts: HashMap<StateVector, u64>,
+    pub metrics: HashMap<(usize, String), f64>,
+    /// Elapsed time of each run in seconds by its id
+    pub elapsed_times: HashMap<usize, f64>,
+    next_particle_parameters_id: usize,
+}
+
+impl ResultStore for MemoryResultStore {
+    fn persist_parameters(&mut self, parameters: &mut Parameters) -> Result<(), Box<dyn Error>> {
+        // Ids start at 1 like SQLite's row ids
+        parameters.run_id = Some(self.runs.len() + 1);
+        for particle in parameters.particle_parameters.iter_mut() {
+            self.next_particle_parameters_id += 1;
+            particle.id = Some(self.next_particle_parameters_id);
+        }
+        self.runs.push(parameters.clone());
+        Ok(())
+    }
+
+    fn increment_state_counts(
+        &mut self,
+        state_vectors: &[StateVector],
+    ) -> Result<(), Box<dyn Error>> {
+        for state_vector in state_vectors {
+            *self.state_counts.entry(state_vector.clone()).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    fn persist_run_metric(
+        &mut self,
+        run_id: usize,
+        name: &str,
+        value: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.metrics.insert((run_id, name.to_string()), value);
+        Ok(())
+    }
+
+    fn persist_elapsed_time(&mut self, run_id: usize, seconds: f64) -> Result<(), Box<dyn Error>> {
+        self.elapsed_times.insert(run_id, seconds);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_memory_result_store() {
+        let mut store = MemoryResultStore::default();
+        let mut parameters = Parameters::default();
+        let mut other_parameters = Parameters::default();
+
+        store.persist_parameters(&mut parameters).unwrap();
+        store.persist_parameters(&mut other_parameters).unwrap();
+        let id = other_parameters.particle_parameters[0].id.unwrap();
+        let state_vector = StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id);
+        store.increment_state_count(&state_vector).unwrap();
+        store.increment_state_count(&state_vector).unwrap();
+        store.persist_run_metric(2, "entropy", 0.5).unwrap();
+        store.commit().unwrap();
+
+        assert_eq!(parameters.run_id, Some(1));
+        assert_eq!(other_parameters.run_id, Some(2));
+        assert_eq!(
+            other_parameters
+                .particle_parameters
+                .iter()
+                .map(|p| p.id.unwrap())
+                .collect::<Vec<_>>(),
+            vec![4, 5, 6]
+        );
+        assert_eq!(store.runs, vec![parameters, other_parameters]);
+        assert_eq!(store.state_counts[&state_vector], 2);
+        assert_eq!(store.metrics[&(2, "entropy".to_string())], 0.5);
+    }
+}
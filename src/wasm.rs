@@ -0,0 +1,191 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+#[cfg(target_arch = "wasm32")]
+use lazy_static::lazy_static;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::parameters::Parameters;
+#[cfg(target_arch = "wasm32")]
+use crate::{
+    indexed_db::IndexedDbResultStore, particle::CartesianMapper, simulate_search_run,
+    store::ResultStore, FLUSH_INTERVAL,
+};
+
+#[cfg(target_arch = "wasm32")]
+lazy_static! {
+    /// Parameters of the simulation running in the page, shared with its render loop.
+    pub(crate) static ref WASM_HANDLE: WasmHandle = WasmHandle::new(Parameters::default());
+}
+
+/// Returns the handle through which the embedding page controls the running simulation.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn handle() -> WasmHandle {
+    WASM_HANDLE.clone()
+}
+
+/// Name of the IndexedDB database the searches of the page are persisted to
+#[cfg(target_arch = "wasm32")]
+pub const SEARCH_DATABASE: &str = "atomata";
+
+/// Simulates `runs` random interactions matrices of the current parameters for `iterations`
+/// steps each and persists the results to IndexedDB, like the search mode of the native build.
+/// The runs are simulated one after another on the thread of the page, which gets control back
+/// in between while the results of a run are written.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn search(runs: usize, iterations: usize, seed: u64) -> Result<(), JsValue> {
+    let to_js = |e: Box<dyn std::error::Error>| JsValue::from_str(&e.to_string());
+    let mut store = IndexedDbResultStore::open(SEARCH_DATABASE)
+        .await
+        .map_err(to_js)?;
+    let base = WASM_HANDLE.parameters();
+    let parameter_space = Parameters::random_parameter_space(runs, seed, &base);
+    for (i, mut parameters) in parameter_space.into_iter().enumerate() {
+        store.persist_parameters(&mut parameters).map_err(to_js)?;
+        // Instant isn't available in the browser
+        let start_time = js_sys::Date::now();
+        let search_run = simulate_search_run(
+            &parameters,
+            iterations,
+            &CartesianMapper,
+            FLUSH_INTERVAL,
+            |state_vectors| {
+                store.increment_state_counts(state_vectors)?;
+                store.commit()
+            },
+        )
+        .map_err(to_js)?;
+        let run_id = parameters.run_id.unwrap();
+        for (name, value) in search_run.metrics() {
+            store
+                .persist_run_metric(run_id, name, value)
+                .map_err(to_js)?;
+        }
+        let elapsed_time = (js_sys::Date::now() - start_time) / 1000.0;
+        store
+            .persist_elapsed_time(run_id, elapsed_time)
+            .map_err(to_js)?;
+        store.flush().await.map_err(to_js)?;
+        log::info!("Search run {} of {} completed", i + 1, runs);
+    }
+    Ok(())
+}
+
+/// Control surface of the simulation for JavaScript. Changed parameters are picked up by the
+/// render loop in its next frame, while the egui panel keeps working alongside it.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Clone)]
+pub struct WasmHandle {
+    parameters: Arc<Mutex<Parameters>>,
+    changed: Arc<AtomicBool>,
+    reset_requested: Arc<AtomicBool>,
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl WasmHandle {
+    pub fn set_amount(&self, amount: usize) -> Result<(), String> {
+        self.update(|parameters| parameters.amount = amount)
+    }
+
+    pub fn set_gravity(&self, gravity: f32) -> Result<(), String> {
+        self.update(|parameters| parameters.gravity_constant = gravity)
+    }
+
+    pub fn set_friction(&self, friction: f32) -> Result<(), String> {
+        self.update(|parameters| parameters.friction = friction)
+    }
+
+    /// Recreates the particles from the current parameters, e.g. after changing the amount.
+    pub fn reset(&self) {
+        self.reset_requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn amount(&self) -> usize {
+        self.parameters.lock().unwrap().amount
+    }
+
+    pub fn gravity(&self) -> f32 {
+        self.parameters.lock().unwrap().gravity_constant
+    }
+
+    pub fn friction(&self) -> f32 {
+        self.parameters.lock().unwrap().friction
+    }
+}
+
+impl WasmHandle {
+    pub fn new(parameters: Parameters) -> Self {
+        Self {
+            parameters: Arc::new(Mutex::new(parameters)),
+            changed: Arc::new(AtomicBool::new(false)),
+            reset_requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        self.parameters.lock().unwrap().clone()
+    }
+
+    /// Applies the change if the resulting parameters are valid.
+    fn update<F: FnOnce(&mut Parameters)>(&self, change: F) -> Result<(), String> {
+        let mut parameters = self.parameters.lock().unwrap();
+        let mut updated = parameters.clone();
+        change(&mut updated);
+        updated.validate()?;
+        *parameters = updated;
+        self.changed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns the parameters if they were changed through the handle since the last call.
+    pub fn take_changed_parameters(&self) -> Option<Parameters> {
+        self.changed
+            .swap(false, Ordering::SeqCst)
+            .then(|| self.parameters())
+    }
+
+    /// Returns whether a reset was requested since the last call.
+    pub fn take_reset(&self) -> bool {
+        self.reset_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Mirrors parameters changed by the render loop, e.g. from the egui panel, unless a change
+    /// through the handle is still pending.
+    pub fn sync(&self, parameters: &Parameters) {
+        let mut shared = self.parameters.lock().unwrap();
+        if !self.changed.load(Ordering::SeqCst) {
+            *shared = parameters.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions_sorted::assert_eq;
+
+    #[test]
+    fn test_setters_change_the_parameters_once() {
+        let handle = WasmHandle::new(Parameters::default());
+
+        handle.set_amount(42).unwrap();
+        handle.set_gravity(2.5).unwrap();
+        assert!(handle.set_friction(1.5).is_err());
+        handle.sync(&Parameters::default());
+
+        let parameters = handle.take_changed_parameters().unwrap();
+        assert_eq!(parameters.amount, 42);
+        assert_eq!(parameters.gravity_constant, 2.5);
+        assert_eq!(parameters.friction, Parameters::default().friction);
+        assert_eq!(handle.take_changed_parameters(), None);
+        assert!(!handle.take_reset());
+
+        handle.reset();
+        assert!(handle.take_reset());
+    }
+}
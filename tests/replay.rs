@@ -0,0 +1,62 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::process::Command;
+
+use atomata::parameters::{InteractionType, Parameters};
+use atomata::persistence::{
+    commit_transaction, create_transaction_provider, open_and_migrate, persist_parameters,
+    PersistenceOptions,
+};
+
+#[test]
+fn test_replay_opens_the_parameters_of_the_stored_run() {
+    let directory = std::env::temp_dir().join(format!("atomata-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let db = directory.join("results.db3");
+
+    let mut expected = Parameters {
+        amount: 4,
+        border: 120.0,
+        gravity_constant: 2.5,
+        force_exponent: 1.5,
+        seed: Some(7),
+        ..Parameters::default()
+    };
+    expected.interactions[0] = InteractionType::Attraction;
+    let mut connection_provider =
+        open_and_migrate(db.to_str().unwrap(), &PersistenceOptions::default()).unwrap();
+    let tx_provider = create_transaction_provider(&mut connection_provider).unwrap();
+    persist_parameters(&mut Parameters::default(), &tx_provider).unwrap();
+    let mut persisted = expected.clone();
+    persist_parameters(&mut persisted, &tx_provider).unwrap();
+    commit_transaction(tx_provider).unwrap();
+    drop(connection_provider);
+
+    // --dry-run prints the parameters instead of opening the window
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .current_dir(&directory)
+        .args([
+            "--replay",
+            &persisted.run_id.unwrap().to_string(),
+            "--dry-run",
+        ])
+        .arg("--db")
+        .arg(&db)
+        .output()
+        .unwrap();
+    let unknown_run = Command::new(env!("CARGO_BIN_EXE_main"))
+        .current_dir(&directory)
+        .args(["--replay", "42", "--dry-run", "--db"])
+        .arg(&db)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert_eq!(Parameters::from_toml(&stdout).unwrap(), expected);
+    assert!(!unknown_run.status.success());
+    assert!(String::from_utf8(unknown_run.stderr)
+        .unwrap()
+        .contains("Can't replay run 42: No run with id 42"));
+}
@@ -0,0 +1,41 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::process::Command;
+
+use rusqlite::Connection;
+
+#[test]
+fn test_run_once_prints_metrics_and_persists_one_run() {
+    let directory = std::env::temp_dir().join(format!("atomata-run-once-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let db = directory.join("results.db3");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .current_dir(&directory)
+        .args(["--run-once", "--persist", "--db"])
+        .arg(&db)
+        .args(["--amount", "3", "--iterations", "20", "--seed", "11"])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let run_count: usize = Connection::open(&db)
+        .unwrap()
+        .query_row("SELECT count(*) FROM run_parameters;", [], |row| row.get(0))
+        .unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(output.status.success(), "{}", stdout);
+    for metric in ["entropy", "kinetic_energy", "cluster_count", "iterations"] {
+        assert!(
+            stdout
+                .lines()
+                .any(|line| line.starts_with(&format!("{}: ", metric))),
+            "{} missing in {}",
+            metric,
+            stdout
+        );
+    }
+    assert!(stdout.contains("iterations: 20"));
+    assert_eq!(run_count, 1);
+}
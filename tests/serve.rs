@@ -0,0 +1,36 @@
+#![cfg(all(feature = "serve", not(target_arch = "wasm32")))]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use atomata::parameters::Parameters;
+use atomata::serve::{FrameServer, ParticleFrame};
+use atomata::Simulation;
+
+#[test]
+fn test_frame_endpoint_serves_every_particle() {
+    let parameters = Parameters {
+        amount: 3,
+        seed: Some(7),
+        ..Parameters::default()
+    };
+    let particle_count = parameters.amount * parameters.particle_parameters.len();
+    let server = FrameServer::bind("127.0.0.1:0").unwrap();
+    let port = server.port().unwrap();
+    thread::spawn(move || server.run(Simulation::new(parameters), Duration::from_millis(1)));
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .write_all(b"GET /frame HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert!(head.contains("application/json"));
+    let frame: Vec<ParticleFrame> = serde_json::from_str(body).unwrap();
+    assert_eq!(frame.len(), particle_count);
+}
@@ -0,0 +1,57 @@
+#![cfg(target_arch = "wasm32")]
+
+use atomata::indexed_db::IndexedDbResultStore;
+use atomata::parameters::Parameters;
+use atomata::particle::StateVector;
+use atomata::store::ResultStore;
+use atomata::wasm::handle;
+use rexie::Rexie;
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+// IndexedDB is only available in the browser
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn test_setter_changes_observed_parameter() {
+    let handle = handle();
+
+    handle.set_gravity(4.0).unwrap();
+
+    assert_eq!(handle.gravity(), 4.0);
+    assert_eq!(
+        handle.take_changed_parameters().unwrap().gravity_constant,
+        4.0
+    );
+}
+
+#[wasm_bindgen_test]
+async fn test_indexed_db_store_counts_state_vectors() {
+    let name = "atomata-test-state-counts";
+    Rexie::delete(name).await.unwrap();
+    let mut store = IndexedDbResultStore::open(name).await.unwrap();
+    let mut parameters = Parameters::default();
+    store.persist_parameters(&mut parameters).unwrap();
+    let id = parameters.particle_parameters[0].id.unwrap();
+    let state_vector = StateVector::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id);
+    let other_state_vector =
+        StateVector::new((20.0, 0.0, 0.0), (0.0, 0.0, 0.0), 3.0, 10.0, 10.0, id);
+
+    store.increment_state_count(&state_vector).unwrap();
+    store.increment_state_count(&state_vector).unwrap();
+    store.flush().await.unwrap();
+    // Committed in the background, flushing waits for it
+    store.increment_state_count(&state_vector).unwrap();
+    store.commit().unwrap();
+    store.flush().await.unwrap();
+
+    assert_eq!(parameters.run_id, Some(1));
+    assert_eq!(store.state_count(&state_vector).await.unwrap(), 3);
+    assert_eq!(store.state_count(&other_state_vector).await.unwrap(), 0);
+
+    // Ids continue after the persisted ones when the database is opened again
+    let mut store = IndexedDbResultStore::open(name).await.unwrap();
+    let mut other_parameters = Parameters::default();
+    store.persist_parameters(&mut other_parameters).unwrap();
+    assert_eq!(other_parameters.run_id, Some(2));
+    assert_eq!(other_parameters.particle_parameters[0].id, Some(4));
+}